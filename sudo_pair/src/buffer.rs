@@ -0,0 +1,389 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use sudo_plugin::options::FromSudoOption;
+
+const DROPPED_MARKER_PREFIX : &str = "\r\n[sudo_pair: ";
+const DROPPED_MARKER_SUFFIX : &str = " bytes of output dropped]\r\n";
+
+/// The longest the marker for discarded output can be: the number of
+/// bytes dropped is a `usize`, which is at most 20 digits long.
+const MAX_DROPPED_MARKER_LEN : usize =
+    DROPPED_MARKER_PREFIX.len() + 20 + DROPPED_MARKER_SUFFIX.len();
+
+/// The smallest chunk of output that must fit in the buffer alongside
+/// the marker for discarded output.
+const MIN_CHUNK_LEN : usize = 1024;
+
+/// The smallest allowed capacity for an output buffer. Anything smaller
+/// could leave the marker for discarded output unable to ever fit,
+/// refusing all output from then on.
+pub(crate) const MIN_CAPACITY : usize = MAX_DROPPED_MARKER_LEN + MIN_CHUNK_LEN;
+
+/// Returns whether `capacity` is large enough for an output buffer.
+//
+// the signature is dictated by `#[sudo_option(validate = "...")]`
+#[allow(clippy::trivially_copy_pass_by_ref)]
+pub(crate) fn is_valid_capacity(capacity: &usize) -> bool {
+    *capacity >= MIN_CAPACITY
+}
+
+/// What to do with command output when the pair isn't reading it fast
+/// enough for it to fit in the output buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Overflow {
+    /// Stall the command until the pair has caught up. This is the
+    /// historical behavior, and guarantees the pair sees everything.
+    Block,
+
+    /// Discard output that doesn't fit, and show the pair a marker
+    /// noting how much was lost once there's room again.
+    Drop,
+
+    /// End the session.
+    Terminate,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block"     => Ok(Self::Block),
+            "drop"      => Ok(Self::Drop),
+            "terminate" => Ok(Self::Terminate),
+            _           => Err(format!("unknown output overflow policy: {}", s)),
+        }
+    }
+}
+
+impl FromSudoOption for Overflow {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+    }
+}
+
+/// A bounded queue of command output waiting to be written to the
+/// pair.
+///
+/// Output is pushed into the buffer as the command produces it, and is
+/// drained into a non-blocking sink whenever the sink will accept it,
+/// so that a slow or stalled pair doesn't stall the command itself.
+#[derive(Debug)]
+pub(crate) struct OutputBuffer {
+    buf:      VecDeque<u8>,
+    capacity: usize,
+    dropped:  usize,
+}
+
+impl OutputBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            buf:     VecDeque::new(),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The largest chunk of output that [`push`](Self::push) can
+    /// accept, even with a marker for discarded output in front of it.
+    /// Larger output must be split into chunks of this size.
+    pub(crate) fn max_chunk_len(&self) -> usize {
+        self.capacity.saturating_sub(MAX_DROPPED_MARKER_LEN).max(1)
+    }
+
+    /// Enqueues `data` if (and only if) it fits in the remaining space
+    /// in the buffer. Returns whether or not it was enqueued.
+    ///
+    /// If output has previously been discarded, a marker noting how
+    /// much was lost is enqueued first, so the pair can see where the
+    /// gap in their output is. If the marker and the data don't both
+    /// fit, nothing is enqueued.
+    pub(crate) fn push(&mut self, data: &[u8]) -> bool {
+        if !self.push_dropped_marker(data.len()) {
+            return false;
+        }
+
+        if self.remaining() < data.len() {
+            return false;
+        }
+
+        self.buf.extend(data);
+
+        true
+    }
+
+    /// Records that `len` bytes of output have been discarded without
+    /// ever being enqueued.
+    pub(crate) fn discard(&mut self, len: usize) {
+        self.dropped += len;
+    }
+
+    /// Writes as much of the buffer as `sink` will currently accept,
+    /// stopping without error as soon as it would block.
+    pub(crate) fn drain<W: Write>(&mut self, sink: &mut W) -> io::Result<()> {
+        // a previous drop may have been waiting for the buffer to empty
+        // out enough for the marker to fit
+        let _ = self.push_dropped_marker(0);
+
+        while !self.buf.is_empty() {
+            let written = match sink.write(self.buf.as_slices().0) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock  => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            let _ = self.buf.drain(..written);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the entire buffer to `sink`, which is expected to be
+    /// blocking.
+    pub(crate) fn flush<W: Write>(&mut self, sink: &mut W) -> io::Result<()> {
+        let (front, back) = self.buf.as_slices();
+
+        sink.write_all(front)?;
+        sink.write_all(back)?;
+
+        self.buf.clear();
+
+        // the marker is written directly, since it may not fit in the
+        // buffer at all
+        if let Some(marker) = self.take_dropped_marker() {
+            sink.write_all(marker.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes and returns everything in the buffer, followed by the
+    /// marker for any discarded output.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        let mut taken : Vec<u8> = self.buf.drain(..).collect();
+
+        if let Some(marker) = self.take_dropped_marker() {
+            taken.extend_from_slice(marker.as_bytes());
+        }
+
+        taken
+    }
+
+    fn remaining(&self) -> usize {
+        self.capacity.saturating_sub(self.buf.len())
+    }
+
+    /// Enqueues the marker for any discarded output, as long as there's
+    /// room for both it and an additional `reserve` bytes. Returns
+    /// whether or not any previously-discarded output has now been
+    /// accounted for.
+    fn push_dropped_marker(&mut self, reserve: usize) -> bool {
+        if self.dropped == 0 {
+            return true;
+        }
+
+        if self.remaining() < self.dropped_marker().len() + reserve {
+            return false;
+        }
+
+        if let Some(marker) = self.take_dropped_marker() {
+            self.buf.extend(marker.as_bytes());
+        }
+
+        true
+    }
+
+    fn take_dropped_marker(&mut self) -> Option<String> {
+        if self.dropped == 0 {
+            return None;
+        }
+
+        let marker = self.dropped_marker();
+
+        self.dropped = 0;
+
+        Some(marker)
+    }
+
+    fn dropped_marker(&self) -> String {
+        format!(
+            "{}{}{}",
+            DROPPED_MARKER_PREFIX,
+            self.dropped,
+            DROPPED_MARKER_SUFFIX,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sink that accepts at most `limit` bytes before blocking.
+    struct Throttled {
+        written: Vec<u8>,
+        limit:   usize,
+    }
+
+    impl Write for Throttled {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.limit - self.written.len());
+
+            if len == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            self.written.extend_from_slice(&buf[..len]);
+
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn push_within_capacity() {
+        let mut buffer = OutputBuffer::new(8);
+
+        assert!(buffer.push(b"abcd"));
+        assert!(buffer.push(b"efgh"));
+        assert!(!buffer.push(b"i"));
+        assert_eq!(8, buffer.len());
+    }
+
+    #[test]
+    fn drain_stops_when_sink_blocks() {
+        let mut buffer = OutputBuffer::new(8);
+        let mut sink   = Throttled { written: vec![], limit: 3 };
+
+        assert!(buffer.push(b"abcdef"));
+
+        buffer.drain(&mut sink).unwrap();
+
+        assert_eq!(b"abc", &sink.written[..]);
+        assert_eq!(3, buffer.len());
+    }
+
+    #[test]
+    fn drain_empties_buffer() {
+        let mut buffer = OutputBuffer::new(8);
+        let mut sink   = Throttled { written: vec![], limit: 64 };
+
+        assert!(buffer.push(b"abcdef"));
+
+        buffer.drain(&mut sink).unwrap();
+
+        assert_eq!(b"abcdef", &sink.written[..]);
+        assert_eq!(0, buffer.len());
+    }
+
+    #[test]
+    fn discard_inserts_marker() {
+        let mut buffer = OutputBuffer::new(128);
+        let mut sink   = Vec::new();
+
+        assert!(buffer.push(b"abc"));
+        buffer.discard(42);
+        assert!(buffer.push(b"def"));
+
+        buffer.flush(&mut sink).unwrap();
+
+        assert_eq!(
+            &b"abc\r\n[sudo_pair: 42 bytes of output dropped]\r\ndef"[..],
+            &sink[..],
+        );
+    }
+
+    #[test]
+    fn discard_waits_for_room_for_marker() {
+        let mut buffer = OutputBuffer::new(16);
+        let mut sink   = Vec::new();
+
+        buffer.discard(1);
+
+        assert!(!buffer.push(b"a"));
+        assert_eq!(0, buffer.len());
+
+        buffer.flush(&mut sink).unwrap();
+
+        assert_eq!(
+            &b"\r\n[sudo_pair: 1 bytes of output dropped]\r\n"[..],
+            &sink[..],
+        );
+    }
+
+    #[test]
+    fn take_empties_buffer() {
+        let mut buffer = OutputBuffer::new(128);
+
+        assert!(buffer.push(b"abc"));
+        buffer.discard(7);
+
+        assert_eq!(
+            &b"abc\r\n[sudo_pair: 7 bytes of output dropped]\r\n"[..],
+            &buffer.take()[..],
+        );
+
+        assert!(buffer.is_empty());
+        assert!(buffer.take().is_empty());
+    }
+
+    #[test]
+    fn capacity_must_fit_marker_and_chunk() {
+        assert!(!is_valid_capacity(&0));
+        assert!(!is_valid_capacity(&MAX_DROPPED_MARKER_LEN));
+        assert!(!is_valid_capacity(&(MIN_CAPACITY - 1)));
+        assert!( is_valid_capacity(&MIN_CAPACITY));
+    }
+
+    #[test]
+    fn max_chunk_fits_after_largest_marker() {
+        let mut buffer = OutputBuffer::new(MIN_CAPACITY);
+        let chunk      = vec![b'a'; buffer.max_chunk_len()];
+
+        buffer.discard(usize::MAX);
+
+        assert!(buffer.push(&chunk));
+        assert_eq!(MAX_DROPPED_MARKER_LEN + MIN_CHUNK_LEN, buffer.len());
+    }
+
+    #[test]
+    fn overflow_from_str() {
+        assert_eq!(Ok(Overflow::Block),     "block"    .parse());
+        assert_eq!(Ok(Overflow::Drop),      "drop"     .parse());
+        assert_eq!(Ok(Overflow::Terminate), "terminate".parse());
+
+        assert!("explode".parse::<Overflow>().is_err());
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::state;
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+/// Identifies a single approvable request: who is asking, from where,
+/// to become whom, and to run exactly what.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct CacheKey([u8; 32]);
+
+impl CacheKey {
+    /// Derives a key from each of the `fields` that must match for a
+    /// previous approval to be reused. Each field is length-prefixed so
+    /// that no two distinct sets of fields can produce the same input
+    /// to the hash.
+    pub(crate) fn new(fields: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+
+        for field in fields {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+
+        let mut key = [0; 32];
+        key.copy_from_slice(&hasher.finalize());
+
+        Self(key)
+    }
+}
+
+impl Display for CacheKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A record of recently-approved sessions, so that running the same
+/// command again shortly afterward doesn't require finding another
+/// pair. This is analogous to `sudo`'s own timestamp tickets.
+///
+/// Each approval is a file in `dir`, named after its key, containing
+/// the ID of the session that was approved. An approval can be reused
+/// until `ttl` has passed since that file was written.
+#[derive(Clone, Debug)]
+pub(crate) struct ApprovalCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ApprovalCache {
+    pub(crate) fn new<P: Into<PathBuf>>(dir: P, ttl: Duration) -> Self {
+        Self { dir: dir.into(), ttl }
+    }
+
+    /// Returns the ID of the session whose approval covers `key`, if
+    /// there is one that hasn't yet expired.
+    pub(crate) fn lookup(&self, key: &CacheKey) -> Result<Option<String>> {
+        if !self.dir.exists() {
+            return Ok(None);
+        }
+
+        state::enforce_ownership(&self.dir, true)?;
+
+        let path = self.path(key);
+
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        state::enforce_ownership(&path, false)?;
+
+        if !is_fresh(metadata.modified()?, SystemTime::now(), self.ttl) {
+            // stale approvals are cleaned up opportunistically; there's
+            // no harm if this fails, since they'll be ignored anyway
+            let _ = fs::remove_file(&path);
+
+            return Ok(None);
+        }
+
+        Ok(Some(fs::read_to_string(&path)?.trim().to_owned()))
+    }
+
+    /// Records that `session_id` was approved for `key`, replacing any
+    /// previous approval.
+    pub(crate) fn record<D: Display>(&self, key: &CacheKey, session_id: D) -> Result<()> {
+        state::create_dir(&self.dir)?;
+        state::write_atomically(
+            &self.dir,
+            &key.to_string(),
+            format!("{}\n", session_id).as_bytes(),
+        )
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(key.to_string())
+    }
+}
+
+/// Returns true if an approval written at `modified` is still valid at
+/// `now`. Approvals that claim to have been written in the future are
+/// never trusted.
+fn is_fresh(modified: SystemTime, now: SystemTime, ttl: Duration) -> bool {
+    now.duration_since(modified)
+        .map_or(false, |age| age <= ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TempDir;
+
+    const TTL : Duration = Duration::from_secs(300);
+
+    fn cache(name: &str) -> (TempDir, ApprovalCache) {
+        let dir   = TempDir::new(name);
+        let cache = ApprovalCache::new(dir.path(), TTL);
+
+        (dir, cache)
+    }
+
+    #[test]
+    fn key_fields_are_unambiguous() {
+        assert_ne!(
+            CacheKey::new(&[b"ab", b"c"]),
+            CacheKey::new(&[b"a",  b"bc"]),
+        );
+    }
+
+    #[test]
+    fn lookup_without_cache() {
+        let (_dir, cache) = cache("cache-missing");
+
+        assert_eq!(None, cache.lookup(&CacheKey::new(&[b"key"])).unwrap());
+    }
+
+    #[test]
+    fn record_then_lookup() {
+        let (_dir, cache) = cache("cache-record");
+
+        let key   = CacheKey::new(&[b"key"]);
+        let other = CacheKey::new(&[b"other"]);
+
+        cache.record(&key, "7f3a0001abcdef10").unwrap();
+
+        assert_eq!(Some("7f3a0001abcdef10".into()), cache.lookup(&key).unwrap());
+        assert_eq!(None,                            cache.lookup(&other).unwrap());
+    }
+
+    #[test]
+    fn freshness() {
+        let now = SystemTime::now();
+
+        assert!( is_fresh(now,           now, TTL));
+        assert!( is_fresh(now - TTL,     now, TTL));
+        assert!(!is_fresh(now - TTL * 2, now, TTL));
+        assert!(!is_fresh(now + TTL,     now, TTL));
+    }
+}
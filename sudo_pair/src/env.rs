@@ -0,0 +1,94 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The parts of the `sudo` environment that pairing depends on. The I/O
+//! plugin and the approval plugin are each given this information, but
+//! at different times and in different shapes.
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+
+use sudo_plugin::prelude::*;
+use sudo_plugin::options::{CommandInfo, Settings, UserInfo};
+use sudo_plugin::output::{PrintFacility, Tty};
+
+#[derive(Debug)]
+pub(crate) struct Env {
+    pub(crate) settings:     Settings,
+    pub(crate) user_info:    UserInfo,
+    pub(crate) command_info: CommandInfo,
+
+    /// The command being executed under `sudo`, in the same form as
+    /// would be passed to the `execve(2)` system call.
+    pub(crate) cmdline: Vec<CString>,
+
+    stderr: PrintFacility,
+}
+
+impl Env {
+    pub(crate) fn from_io_env(env: &IoEnv) -> Self {
+        Self {
+            settings:     env.settings.clone(),
+            user_info:    env.user_info.clone(),
+            command_info: env.command_info.clone(),
+            cmdline:      env.cmdline.clone(),
+            stderr:       env.stderr(),
+        }
+    }
+
+    pub(crate) fn from_approval_env(
+        env:          &ApprovalEnv,
+        command_info: &CommandInfo,
+        run_argv:     &[CString],
+    ) -> Self {
+        Self {
+            settings:     env.settings.clone(),
+            user_info:    env.user_info.clone(),
+            command_info: command_info.clone(),
+            cmdline:      run_argv.to_vec(),
+            stderr:       env.stderr(),
+        }
+    }
+
+    /// Returns a facility that emits to the invoking user's STDERR.
+    pub(crate) fn stderr(&self) -> PrintFacility {
+        self.stderr.clone()
+    }
+
+    /// Returns a facility that emits to the user's TTY, if `sudo`
+    /// detected one.
+    pub(crate) fn tty(&self) -> Option<Tty> {
+        self.user_info.tty.as_ref().and_then(|path|
+            Tty::try_from(path.as_path()).ok()
+        )
+    }
+}
+
+impl CommandEnv for Env {
+    fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    fn user_info(&self) -> &UserInfo {
+        &self.user_info
+    }
+
+    fn command_info(&self) -> &CommandInfo {
+        &self.command_info
+    }
+
+    fn cmdline(&self) -> &[CString] {
+        &self.cmdline
+    }
+}
@@ -18,17 +18,25 @@ use std::error::Error as StdError;
 
 use failure::Context;
 
-use sudo_plugin::prelude::{Error as PluginError, OpenStatus, LogStatus};
+use sudo_plugin::prelude::{Error as PluginError, CheckStatus, OpenStatus, LogStatus};
 
 pub(crate) type Result<T> = StdResult<T, Error>;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub(crate) enum ErrorKind {
     CommunicationError,
+    OutputOverflow,
+    ReconfirmationFailed,
+    RedactionPatternsInvalid,
     SessionDeclined,
+    SessionExpired,
+    SessionIdUnavailable,
+    SessionIdle,
     SessionTerminated,
+    SessionUnmirrored,
     StdinRedirected,
     SudoToUserAndGroup,
+    SuspendDenied,
 
     PluginError(PluginError),
 }
@@ -36,13 +44,21 @@ pub(crate) enum ErrorKind {
 impl ErrorKind {
     fn as_str(&self) -> &'static str {
         match self {
-            ErrorKind::CommunicationError => "couldn't establish communications with the pair",
-            ErrorKind::SessionDeclined    => "pair declined the session",
-            ErrorKind::SessionTerminated  => "pair ended the session",
-            ErrorKind::StdinRedirected    => "redirection of stdin to paired sessions is prohibited",
-            ErrorKind::SudoToUserAndGroup => "the -u and -g options may not both be specified",
-
-            ErrorKind::PluginError(_)     => "the plugin failed to initialize",
+            ErrorKind::CommunicationError       => "couldn't establish communications with the pair",
+            ErrorKind::OutputOverflow           => "pair fell too far behind the session's output",
+            ErrorKind::ReconfirmationFailed     => "pair didn't reconfirm the session",
+            ErrorKind::RedactionPatternsInvalid => "couldn't load redaction patterns",
+            ErrorKind::SessionDeclined          => "pair declined the session",
+            ErrorKind::SessionExpired           => "session reached its maximum duration",
+            ErrorKind::SessionIdUnavailable     => "couldn't generate a session id",
+            ErrorKind::SessionIdle              => "session was idle for too long",
+            ErrorKind::SessionTerminated        => "pair ended the session",
+            ErrorKind::SessionUnmirrored        => "session was approved but never mirrored to the pair",
+            ErrorKind::StdinRedirected          => "redirection of stdin to paired sessions is prohibited",
+            ErrorKind::SudoToUserAndGroup       => "the -u and -g options may not both be specified",
+            ErrorKind::SuspendDenied            => "paired sessions may not be suspended",
+
+            ErrorKind::PluginError(_)           => "the plugin failed to initialize",
         }
     }
 }
@@ -58,6 +74,12 @@ pub(crate) struct Error {
     inner: Context<ErrorKind>,
 }
 
+impl Error {
+    pub(crate) fn kind(&self) -> &ErrorKind {
+        self.inner.get_context()
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         self.inner.fmt(f)
@@ -77,8 +99,11 @@ impl From<Context<ErrorKind>> for Error {
 }
 
 impl From<Error> for OpenStatus {
-    fn from(_: Error) -> Self {
-        OpenStatus::Deny
+    fn from(err: Error) -> Self {
+        match err.inner.get_context() {
+            ErrorKind::PluginError(PluginError::OptionsInvalid(_)) => OpenStatus::Usage,
+            _                                                      => OpenStatus::Deny,
+        }
     }
 }
 
@@ -88,10 +113,23 @@ impl From<Error> for LogStatus {
     }
 }
 
+impl From<Error> for CheckStatus {
+    fn from(_: Error) -> Self {
+        CheckStatus::Reject
+    }
+}
+
 impl From<PluginError> for Error {
     fn from(err: PluginError) -> Self {
         ErrorKind::PluginError(err).into()
     }
 }
 
-impl StdError for Error { }
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.inner.get_context() {
+            ErrorKind::PluginError(err) => Some(err),
+            _                           => None,
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::time::{Duration, Instant};
+
+/// The probe sent to the pair to check that they're still there. This
+/// is the ANSI "device status report" request; the pair's terminal
+/// answers it with `ESC [ 0 n` without any action on their part, which
+/// the approval command relays back to us over the socket.
+pub(crate) const PROBE : &[u8] = b"\x1b[5n";
+
+/// Tracks whether or not the pair is still on the other end of the
+/// socket.
+///
+/// Periodically, a probe is sent to the pair. Anything at all received
+/// from the pair counts as a response, but if nothing is received for
+/// `timeout` after a probe was sent, the pair is presumed to be gone.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    interval: Duration,
+    timeout:  Duration,
+
+    /// when we last sent a probe (or, before the first probe, when the
+    /// session started)
+    last_probe: Instant,
+
+    /// when we sent the oldest probe the pair hasn't responded to yet
+    pending: Option<Instant>,
+}
+
+impl Heartbeat {
+    /// Creates a new heartbeat which sends a probe every `interval`. An
+    /// `interval` of zero disables probes entirely.
+    pub(crate) fn new(interval: Duration, timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_probe: now,
+            pending:    None,
+        }
+    }
+
+    /// Returns true if it's time to send another probe.
+    pub(crate) fn is_probe_due(&self, now: Instant) -> bool {
+        if self.interval == Duration::from_secs(0) {
+            return false;
+        }
+
+        now.saturating_duration_since(self.last_probe) >= self.interval
+    }
+
+    /// Records that a probe was sent to the pair.
+    pub(crate) fn probe_sent(&mut self, now: Instant) {
+        self.last_probe = now;
+        self.pending    = self.pending.or(Some(now));
+    }
+
+    /// Records that we've heard from the pair.
+    pub(crate) fn response_received(&mut self) {
+        self.pending = None;
+    }
+
+    /// Returns true if the pair has failed to respond to a probe within
+    /// the timeout.
+    pub(crate) fn is_expired(&self, now: Instant) -> bool {
+        self.pending.map_or(false, |sent| {
+            now.saturating_duration_since(sent) > self.timeout
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL : Duration = Duration::from_secs(30);
+    const TIMEOUT  : Duration = Duration::from_secs(60);
+
+    #[test]
+    fn probe_due_after_interval() {
+        let start     = Instant::now();
+        let heartbeat = Heartbeat::new(INTERVAL, TIMEOUT, start);
+
+        assert!(!heartbeat.is_probe_due(start));
+        assert!(!heartbeat.is_probe_due(start + INTERVAL / 2));
+        assert!( heartbeat.is_probe_due(start + INTERVAL));
+    }
+
+    #[test]
+    fn probe_disabled() {
+        let start     = Instant::now();
+        let heartbeat = Heartbeat::new(Duration::from_secs(0), TIMEOUT, start);
+
+        assert!(!heartbeat.is_probe_due(start + INTERVAL * 100));
+    }
+
+    #[test]
+    fn expires_without_response() {
+        let start         = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, TIMEOUT, start);
+
+        heartbeat.probe_sent(start + INTERVAL);
+
+        assert!(!heartbeat.is_expired(start + INTERVAL + TIMEOUT));
+        assert!( heartbeat.is_expired(start + INTERVAL + TIMEOUT * 2));
+    }
+
+    #[test]
+    fn expiry_measured_from_oldest_probe() {
+        let start         = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, TIMEOUT, start);
+
+        heartbeat.probe_sent(start);
+        heartbeat.probe_sent(start + TIMEOUT);
+
+        assert!(heartbeat.is_expired(start + TIMEOUT + INTERVAL));
+    }
+
+    #[test]
+    fn response_resets_expiry() {
+        let start         = Instant::now();
+        let mut heartbeat = Heartbeat::new(INTERVAL, TIMEOUT, start);
+
+        heartbeat.probe_sent(start);
+        heartbeat.response_received();
+
+        assert!(!heartbeat.is_expired(start + TIMEOUT * 2));
+    }
+
+    #[test]
+    fn idle_session_does_not_expire() {
+        let start     = Instant::now();
+        let heartbeat = Heartbeat::new(INTERVAL, TIMEOUT, start);
+
+        assert!(!heartbeat.is_expired(start + TIMEOUT * 100));
+    }
+}
@@ -16,6 +16,11 @@
 //!
 //! This plugin implements dual control for `sudo`, requiring that
 //! another engineer approve and monitor any privileged sessions.
+//!
+//! Sessions may be approved either by the I/O plugin when it's opened,
+//! or by an approval plugin before the command is started. In the
+//! latter case, the approved session is handed off to the I/O plugin,
+//! which then only mirrors it to the pair.
 
 // TODO: remove all to_string_lossy
 // TODO: error message when /var/run/sudo_pair missing
@@ -65,107 +70,581 @@
 // but this needs to be either investigated or reported upstream
 #![allow(clippy::let_underscore_drop)]
 
+mod buffer;
+mod cache;
+mod env;
 mod errors;
+mod heartbeat;
+mod reconfirm;
+mod redact;
+mod session;
+mod state;
+mod summary;
 mod template;
+mod tickets;
 mod socket;
 
+use crate::buffer::{OutputBuffer, Overflow};
+use crate::cache::{ApprovalCache, CacheKey};
+use crate::env::Env;
 use crate::errors::{Error, ErrorKind, Result};
+use crate::heartbeat::Heartbeat;
+use crate::reconfirm::Reconfirmation;
+use crate::redact::{Redactor, StreamRedactor};
+use crate::session::SessionId;
+use crate::summary::{Outcome, Summary};
 use crate::template::Spec;
+use crate::tickets::{Request, TicketStore};
 use crate::socket::Socket;
 
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashSet;
+use std::ffi::CString;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::ops::Deref;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard, Once, PoisonError, Weak};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use libc::{gid_t, mode_t, uid_t};
 
 use failure::ResultExt;
 
 use sudo_plugin::prelude::*;
-use sudo_plugin::options::OptionMap;
+use sudo_plugin::event::{Event, Events};
+use sudo_plugin::options::{CommandInfo, OptionMap};
+
+const DEFAULT_BINARY_PATH        : &str       = "/usr/bin/sudo_approve";
+const DEFAULT_USER_PROMPT_PATH   : &str       = "/etc/sudo_pair.prompt.user";
+const DEFAULT_PAIR_PROMPT_PATH   : &str       = "/etc/sudo_pair.prompt.pair";
+const DEFAULT_SOCKET_DIR         : &str       = "/var/run/sudo_pair";
+const DEFAULT_GIDS_ENFORCED      : [gid_t; 1] = [0];
+const DEFAULT_OUTPUT_BUFFER_SIZE : usize      = 1024 * 1024;
+const DEFAULT_OUTPUT_OVERFLOW    : Overflow   = Overflow::Block;
+const DEFAULT_HEARTBEAT_INTERVAL : Duration   = Duration::from_secs(30);
+const DEFAULT_HEARTBEAT_TIMEOUT  : Duration   = Duration::from_secs(60);
+const DEFAULT_RECONFIRM_INTERVAL : Duration   = Duration::from_secs(0);
+const DEFAULT_RECONFIRM_TIMEOUT  : Duration   = Duration::from_secs(60);
+const DEFAULT_MAX_SESSION        : Duration   = Duration::from_secs(0);
+const DEFAULT_MAX_IDLE           : Duration   = Duration::from_secs(0);
+const DEFAULT_DENY_SUSPEND       : bool       = false;
+const DEFAULT_APPROVAL_CACHE_TTL : Duration   = Duration::from_secs(0);
+const DEFAULT_ALLOW_TICKETS      : bool       = false;
+
+// the subdirectories of `socket_dir` where prior approvals and
+// pre-approval tickets are recorded
+const APPROVAL_CACHE_DIR : &str = "approvals";
+const TICKETS_DIR        : &str = "tickets";
+
+// how long we're willing to wait for a pair to read any remaining
+// buffered output once the command has exited
+const CLOSE_FLUSH_TIMEOUT : Duration = Duration::from_secs(5);
+
+// how often the session's health is checked when nothing else is
+// happening, which bounds how late a missed heartbeat or an exceeded
+// limit is noticed
+const SESSION_CHECK_INTERVAL : Duration = Duration::from_secs(1);
+
+const DEFAULT_USER_PROMPT : &[u8] = b"%B %s\n";
+const DEFAULT_PAIR_PROMPT : &[u8] = b"[%s] %U@%h:%d$ %C\ny/n? [n]: ";
+
+sudo_io_plugin!{ sudo_pair : SudoPairIo }
+sudo_approval_plugin!{ sudo_pair_approval : SudoPairApproval }
+
+thread_local! {
+    // the events `sudo` runs on behalf of the paired session; events
+    // can't be sent to other threads, so they're kept here rather than
+    // in the plugin, and are dropped when the plugin is closed
+    static EVENTS: RefCell<Option<SessionEvents>> = const { RefCell::new(None) };
+}
+
+/// A session that's been paired by the approval plugin, waiting for the
+/// I/O plugin to pick it up; `sudo` opens I/O plugins right after the
+/// command has been approved, in the same process. This can't be thread
+/// local, since glibc destroys thread locals before running `atexit`
+/// handlers, and a session that's never picked up is ended from one.
+static APPROVED_PAIR: Mutex<Option<SudoPair>> = Mutex::new(None);
+
+/// The process in which the approval plugin paired a session. `sudo`
+/// forks before running the command, and only this process may end a
+/// session that was never handed off.
+static APPROVING_PID: AtomicU32 = AtomicU32::new(0);
+
+/// Ensures that unmirrored sessions are only registered to be ended at
+/// exit once.
+static ABANDON_AT_EXIT: Once = Once::new();
+
+/// The I/O plugin entry point. The session is shared with the events
+/// `sudo` runs on its behalf, since they outlive any one callback.
+struct SudoPairIo {
+    pair: Arc<SudoPair>,
+}
 
-const DEFAULT_BINARY_PATH      : &str       = "/usr/bin/sudo_approve";
-const DEFAULT_USER_PROMPT_PATH : &str       = "/etc/sudo_pair.prompt.user";
-const DEFAULT_PAIR_PROMPT_PATH : &str       = "/etc/sudo_pair.prompt.pair";
-const DEFAULT_SOCKET_DIR       : &str       = "/var/run/sudo_pair";
-const DEFAULT_GIDS_ENFORCED    : [gid_t; 1] = [0];
+struct SudoPair {
+    env:        Env,
+    options:    PluginOptions,
+    session_id: SessionId,
+    socket:     Option<Mutex<Socket>>,
+    output:     Mutex<OutputBuffer>,
 
-const DEFAULT_USER_PROMPT : &[u8] = b"%B %u %p\n";
-const DEFAULT_PAIR_PROMPT : &[u8] = b"%U@%h:%d$ %C\ny/n? [n]: ";
+    /// command output produced while the pair is being asked to
+    /// reconfirm the session, which isn't sent until they have
+    held: Mutex<OutputBuffer>,
 
-sudo_io_plugin!{ sudo_pair : SudoPair }
+    heartbeat:  Mutex<Heartbeat>,
+    reconfirm:  Mutex<Reconfirmation>,
 
-struct SudoPair {
-    env:     &'static IoEnv,
-    options: PluginOptions,
-    socket:  Option<RefCell<Socket>>,
+    output_redactor: Option<Mutex<StreamRedactor>>,
+
+    /// the shell-quoted command line `sudo` was invoked with, after
+    /// redaction
+    invocation: Vec<u8>,
+
+    started:      Instant,
+    last_active:  Mutex<Instant>,
+    output_bytes: AtomicU64,
+    input_bytes:  AtomicU64,
+
+    /// why the session has been ended, if it was ended somewhere that
+    /// couldn't report an error to `sudo` (or if `sudo` ignored it)
+    ended: Mutex<Option<ErrorKind>>,
+
+    /// whether the session is being paired by the approval plugin,
+    /// rather than by the I/O plugin when it's opened
+    via_approval: bool,
 
     slog: slog::Logger,
 }
 
-impl IoPlugin for SudoPair {
+impl IoPlugin for SudoPairIo {
     type Error = Error;
 
     const NAME: &'static str = "sudo_pair";
 
-    fn open(env: &'static IoEnv) -> Result<Self> {
-        let mut slog = slog(Self::NAME, Self::VERSION);
+    fn open(env: Arc<IoEnv>) -> Result<Self> {
+        // if the approval plugin has already paired this session, all
+        // that's left to do is to mirror it to the pair
+        let approved = lock(&APPROVED_PAIR).take();
+
+        let pair = if let Some(pair) = approved {
+            slog::debug!(pair.slog, "pair session handed off from approval plugin");
+
+            pair
+        } else {
+            let mut pair = SudoPair::new(
+                Env::from_io_env(&env),
+                &env.plugin_options,
+                false,
+            )?;
+
+            pair.authorize()?;
+
+            pair
+        };
+
+        let pair = Arc::new(pair);
+
+        SudoPair::schedule_events(&pair, &env);
+
+        Ok(Self { pair })
+    }
+
+    fn close(self, exit_status: i32, error: i32) {
+        // `sudo` won't run any more events once the plugin is closed
+        drop(EVENTS.with(RefCell::take));
+
+        self.pair.close(exit_status, error);
+    }
+
+    fn log_ttyin(&self, log: &[u8]) -> Result<()> {
+        lock(&self.reconfirm).record_input(log.len());
+        let _ = self.input_bytes.fetch_add(log.len() as u64, Ordering::Relaxed);
+        self.check_session()?;
+        *lock(&self.last_active) = Instant::now();
+
+        // output is normally flushed as soon as the pair can accept it,
+        // but versions of `sudo` without plugin events leave this as
+        // the only chance to catch them up while the command is quiet
+        self.drain_output()
+    }
+
+    fn log_ttyout(&self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+        self.log_output(log)
+    }
+
+    fn log_stdout(&self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+        self.log_output(log)
+    }
+
+    fn log_stderr(&self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+        self.log_output(log)
+    }
+
+    fn log_stdin(&self, _: &[u8]) -> Result<()> {
+        // if we're exempt, don't disable stdin
+        if self.is_exempt() {
+            return Ok(());
+        }
+
+        Err(ErrorKind::StdinRedirected.into())
+    }
+
+    fn change_winsize(&self, lines: u64, cols: u64) -> Result<()> {
+        self.check_session()?;
+
+        // this is generated by us rather than by the command, so
+        // there's nothing in it to redact
+        self.send_output(format!("\x1b[8;{};{}t", lines, cols).as_bytes())
+    }
+
+    fn log_suspend(&self, signal: i32) -> Result<()> {
+        // exempt sessions have nobody to tell
+        if self.socket.is_none() {
+            return Ok(());
+        }
+
+        let resumed = signal == libc::SIGCONT;
+        let name    = signal_name(signal);
+
+        if resumed {
+            slog::info!(self.slog, "session resumed"; "signal" => &name);
+        } else {
+            slog::info!(self.slog, "session suspended"; "signal" => &name);
+        }
+
+        // `sudo` may carry on with suspending the command even if we
+        // refuse, so the session is also marked so that it's ended the
+        // next time we're given a chance
+        if self.options.deny_suspend && !resumed {
+            return Err(self.end_session(ErrorKind::SuspendDenied));
+        }
+
+        let message = format!(
+            "\r\n[sudo_pair] the user {} the session ({})\r\n",
+            if resumed { "resumed" } else { "suspended" },
+            name,
+        );
+
+        self.send_output(message.as_bytes())
+    }
+}
+
+impl Deref for SudoPairIo {
+    type Target = SudoPair;
+
+    fn deref(&self) -> &SudoPair {
+        &self.pair
+    }
+}
+
+/// The events `sudo` runs on behalf of a paired session, which look
+/// after it in between the plugin's callbacks.
+struct SessionEvents {
+    /// sends buffered output to the pair once they're able to accept it
+    flush: Event,
+
+    /// periodically checks on the health of the session, if anything
+    /// needs to be checked; this is only held so that it's cancelled
+    /// when the session is closed
+    #[allow(dead_code)]
+    checks: Option<Event>,
+}
+
+impl SessionEvents {
+    fn new(pair: &Arc<SudoPair>, env: &IoEnv, fd: RawFd) -> io::Result<Self> {
+        let mut flush   = env.event()?;
+        let     session = Arc::downgrade(pair);
+
+        // this isn't persistent, since the socket is writable nearly
+        // all of the time; it's only added while output is waiting
+        flush.set(fd, Events::WRITE, move |_, _| {
+            if let Some(pair) = Weak::upgrade(&session) {
+                pair.flush_output();
+            }
+        })?;
+
+        let session = Arc::downgrade(pair);
+        let checks  = if pair.options.is_checked_periodically() {
+            Some(env.every(SESSION_CHECK_INTERVAL, move || {
+                if let Some(pair) = Weak::upgrade(&session) {
+                    pair.check_from_event();
+                }
+            })?)
+        } else {
+            None
+        };
+
+        Ok(Self { flush, checks })
+    }
+
+    /// Asks `sudo` to let us know once the pair is able to accept more
+    /// of the buffered output.
+    fn flush_when_writable() {
+        EVENTS.with(|events| {
+            if let Some(events) = events.borrow_mut().as_mut() {
+                if !events.flush.pending(Events::WRITE) {
+                    let _ = events.flush.add(None);
+                }
+            }
+        });
+    }
+
+    /// Breaks out of `sudo`'s event loop, which terminates the command.
+    fn loopbreak() {
+        EVENTS.with(|events| {
+            if let Some(events) = events.borrow().as_ref() {
+                events.flush.loopbreak();
+            }
+        });
+    }
+}
+
+impl SudoPair {
+    fn new(env: Env, plugin_options: &OptionMap, via_approval: bool) -> Result<Self> {
+        let mut slog = slog(SudoPairIo::NAME, SudoPairIo::VERSION);
+
+        // generate the session ID before logging anything, so that
+        // every record for this session can be tied back to it
+        let session_id = SessionId::generate()
+            .context(ErrorKind::SessionIdUnavailable)?;
+
+        slog = slog::Logger::new(&slog, slog::o!(
+            "session_id" => session_id,
+        ));
 
         slog::debug!(slog, "plugin initializing");
 
-        let args : Vec<_> = env.cmdline.iter()
-            .skip(1)
-            .map (|arg| arg.to_string_lossy())
-            .collect();
+        let options = PluginOptions::from_option_map(plugin_options)
+            .map_err(|e| {
+                slog::error!(slog, "invalid plugin options";
+                    "error" => e.to_string(),
+                );
+
+                e
+            })?;
+
+        // the redaction patterns are needed before we can log the
+        // command's arguments
+        let redactor = match options.redact_patterns_path {
+            Some(ref path) => match Redactor::load(path) {
+                Ok(redactor) => Some(redactor),
+                Err(e)       => {
+                    slog::error!(slog, "couldn't load redaction patterns";
+                        "redact_patterns_path" => path.to_string_lossy().into_owned(),
+                        "error"                => e.to_string(),
+                    );
+
+                    return Err(ErrorKind::RedactionPatternsInvalid.into());
+                },
+            },
+
+            None => None,
+        };
+
+        // the command line is redacted as a whole (rather than one
+        // argument at a time), so that patterns spanning arguments are
+        // masked wherever it's shown
+        let invocation = redact(redactor.as_ref(), &env.invocation())
+            .into_owned();
 
         slog = slog::Logger::new(&slog, slog::o!(
-            "uid"           => &env.user_info.uid,
-            "runas_euid"    => &env.command_info.runas_euid,
-            "runas_egid"    => &env.command_info.runas_egid,
+            "uid"           => env.user_info.uid,
+            "runas_euid"    => env.command_info.runas_euid,
+            "runas_egid"    => env.command_info.runas_egid,
             "command"       => env.command_info.command.to_string_lossy().into_owned(),
-            "args"          => format!("{:?}", args),
+            "args"          => String::from_utf8_lossy(&invocation).into_owned(),
+            "intercept"     => env.command_info.intercept,
         ));
 
-        let options = PluginOptions::from(&env.plugin_options);
-
         slog::debug!(slog, "initialized with plugin options:";
              "plugin_options" => &options
         );
 
+        let output = Mutex::new(
+            OutputBuffer::new(options.output_buffer_size)
+        );
+
+        let held = Mutex::new(
+            OutputBuffer::new(options.output_buffer_size)
+        );
+
+        let heartbeat = Mutex::new(Heartbeat::new(
+            options.heartbeat_interval,
+            options.heartbeat_timeout,
+            Instant::now(),
+        ));
+
+        let reconfirm = Mutex::new(Reconfirmation::new(
+            options.reconfirm_interval,
+            options.reconfirm_timeout,
+            Instant::now(),
+        ));
+
         // TODO: convert all outgoing errors to be unauthorized errors
-        let mut pair = Self {
+        Ok(Self {
             env,
             options,
+            session_id,
             socket: None,
+            output,
+            held,
+            heartbeat,
+            reconfirm,
+
+            output_redactor: redactor.map(|redactor|
+                Mutex::new(StreamRedactor::new(redactor))
+            ),
+            invocation,
+
+            started:      Instant::now(),
+            last_active:  Mutex::new(Instant::now()),
+            output_bytes: AtomicU64::new(0),
+            input_bytes:  AtomicU64::new(0),
+            ended:        Mutex::new(None),
+
+            via_approval,
 
             slog,
+        })
+    }
+
+    /// Closes the session, telling the pair how it ended. This only
+    /// needs shared access, so that the session can be closed even if
+    /// something else is still holding onto it.
+    fn close(&self, exit_status: i32, error: i32) {
+        // if we have a socket, close it
+        if let Some(socket) = self.socket.as_ref() {
+            slog::trace!(self.slog, "pair session ending");
+
+            let summary = Summary {
+                outcome:      Outcome::new(exit_status, error),
+                duration:     self.started.elapsed(),
+                output_bytes: self.output_bytes.load(Ordering::Relaxed),
+                input_bytes:  self.input_bytes.load(Ordering::Relaxed),
+            };
+
+            let message = format!(
+                "\r\n[sudo_pair] session {} ended: {}\r\n",
+                self.session_id,
+                summary,
+            );
+
+            // give the pair a chance to see whatever output is still
+            // buffered, but don't let them hold up the exit of `sudo`
+            // indefinitely
+            let mut socket = lock(socket);
+            let _          = socket.set_nonblocking(false);
+            let _          = socket.set_write_timeout(Some(CLOSE_FLUSH_TIMEOUT));
+
+            // anything the redactor was holding back is now known to be
+            // complete, so it can be sent after everything else
+            let remaining = self.output_redactor.as_ref()
+                .map(|redactor| lock(redactor).flush())
+                .unwrap_or_default();
+
+            if let Err(e) = lock(&self.output).flush(&mut *socket)
+                .and_then(|()| socket.write_all(&remaining))
+                .and_then(|()| socket.write_all(message.as_bytes()))
+            {
+                slog::warn!(self.slog, "buffered output not delivered to pair";
+                    "error" => e.to_string(),
+                );
+            }
+
+            let _ = socket.close();
+            slog::info!(self.slog, "pair session ended";
+                "outcome"      => summary.outcome.to_string(),
+                "duration"     => summary.duration.as_secs(),
+                "output_bytes" => summary.output_bytes,
+                "input_bytes"  => summary.input_bytes,
+            );
+        }
+    }
+
+    /// Ends a session that was approved but never mirrored to the pair,
+    /// telling both the user and the pair why.
+    fn abandon(self) {
+        slog::error!(self.slog, "approved pair session was never mirrored to the pair");
+
+        let _ = self.end_session(ErrorKind::SessionUnmirrored);
+    }
+
+    /// Registers the events that look after the session in between
+    /// callbacks. Versions of `sudo` without support for plugin events
+    /// fall back to having everything done from within callbacks.
+    fn schedule_events(pair: &Arc<Self>, env: &IoEnv) {
+        // exempt sessions have nothing to look after
+        let Some(socket) = pair.socket.as_ref() else {
+            return;
         };
 
-        if pair.is_exempt() {
-            slog::info!(pair.slog, "pair session exempt from pairing requirements");
+        let fd = lock(socket).as_raw_fd();
+
+        match SessionEvents::new(pair, env, fd) {
+            Ok(events) => EVENTS.with(|e| *e.borrow_mut() = Some(events)),
+            Err(e)     => slog::debug!(pair.slog, "plugin events unavailable";
+                "error" => e.to_string(),
+            ),
+        }
+    }
+
+    /// Determines whether or not the session needs a pair and, if it
+    /// does, waits for one to approve it.
+    fn authorize(&mut self) -> Result<()> {
+        if self.is_exempt() {
+            slog::info!(self.slog, "pair session exempt from pairing requirements");
 
-            return Ok(pair)
+            return Ok(())
         }
 
-        slog::info!(pair.slog, "pair session required");
+        slog::info!(self.slog, "pair session required");
 
-        if pair.is_sudoing_to_user_and_group() {
-            slog::error!(pair.slog, "both -u and -g were provided to sudo"; slog::o!(
-                "user"  => &pair.env.settings.runas_user,
-                "group" => &pair.env.settings.runas_group,
+        if self.is_sudoing_to_user_and_group() {
+            slog::error!(self.slog, "both -u and -g were provided to sudo"; slog::o!(
+                "user"  => &self.env.settings.runas_user,
+                "group" => &self.env.settings.runas_group,
             ));
 
             return Err(ErrorKind::SudoToUserAndGroup.into());
         }
 
-        let template_spec = pair.template_spec();
+        if let Some(approved) = self.cached_approval() {
+            slog::info!(self.slog, "pair session exempt by prior approval";
+                "approved_session_id" => approved,
+            );
 
-        pair.local_pair_prompt(&template_spec);
-        pair.remote_pair_connect()?;
-        pair.remote_pair_prompt(&template_spec)?;
+            return Ok(())
+        }
+
+        if self.consume_ticket() {
+            return Ok(())
+        }
+
+        let template_spec = self.template_spec();
+
+        self.local_pair_prompt(&template_spec);
+        self.remote_pair_connect()?;
+        self.remote_pair_prompt(&template_spec)?;
+        self.remote_pair_nonblocking()?;
+        self.cache_approval();
+
+        // the clocks for session limits and reconfirmation start once
+        // the pair has approved the session, not when they were first
+        // prompted
+        self.started = Instant::now();
+        *get_mut(&mut self.last_active) = self.started;
+        get_mut(&mut self.reconfirm).confirmed(self.started);
 
         // TODO(security): provide a configurable option to deny or log
         // if the remote euid is the same as the local euid. For some
@@ -192,56 +671,422 @@ impl IoPlugin for SudoPair {
         // through providing the token from their original user). This
         // shouldn't be too hard, but I haven't gotten around to it yet.
 
-        slog::info!(pair.slog, "pair session started");
+        slog::info!(self.slog, "pair session started");
 
-        Ok(pair)
+        Ok(())
     }
 
-    fn close(mut self, _: i32, _: i32) {
-        // if we have a socket, close it
-        if let Some(mut socket) = self.socket.take() {
-            slog::trace!(self.slog, "pair session ending");
-            let _ = socket.get_mut().close();
-            slog::info!(self.slog, "pair session ended");
+    fn log_output(&self, log: &[u8]) -> Result<()> {
+        // if we don't have a socket, there's nobody to send output to
+        if self.socket.is_none() {
+            return Ok(());
         }
+
+        let _ = self.output_bytes.fetch_add(log.len() as u64, Ordering::Relaxed);
+
+        let log : Cow<'_, [u8]> = match self.output_redactor.as_ref() {
+            Some(redactor) => lock(redactor).feed(log).into(),
+            None           => log.into(),
+        };
+
+        self.send_output(&log)
     }
 
-    fn log_ttyout(&self, log: &[u8]) -> Result<()> {
-        self.log_output(log)
+    fn send_output(&self, log: &[u8]) -> Result<()> {
+        // if we don't have a socket, there's nobody to send output to
+        let Some(socket) = self.socket.as_ref() else {
+            return Ok(());
+        };
+
+        let awaiting_reconfirmation = {
+            let mut reconfirm = lock(&self.reconfirm);
+
+            reconfirm.record_output(log.len());
+            reconfirm.is_awaiting()
+        };
+
+        *lock(&self.last_active) = Instant::now();
+
+        // a pair who's being asked whether they're still watching might
+        // not be, so nothing more is shown to them until they say so
+        if awaiting_reconfirmation {
+            return self.hold_output(log);
+        }
+
+        let mut socket = lock(socket);
+        let mut output = lock(&self.output);
+
+        // output larger than the buffer could ever hold is split up, so
+        // that it can still be buffered (or dropped) a piece at a time
+        let chunk_len = output.max_chunk_len();
+
+        for chunk in log.chunks(chunk_len) {
+            self.buffer_output(&mut socket, &mut output, chunk)?;
+        }
+
+        // whatever the pair can't accept right now is sent once they're
+        // able to take more
+        drain(&mut output, &mut socket)
     }
 
-    fn log_stdout(&self, log: &[u8]) -> Result<()> {
-       self.log_output(log)
+    /// Holds on to `log` until the pair has reconfirmed the session.
+    fn hold_output(&self, log: &[u8]) -> Result<()> {
+        let mut held = lock(&self.held);
+
+        let chunk_len = held.max_chunk_len();
+
+        for chunk in log.chunks(chunk_len) {
+            if held.push(chunk) {
+                continue;
+            }
+
+            match self.options.output_overflow {
+                Overflow::Drop => {
+                    slog::warn!(self.slog, "held output buffer full, dropping output";
+                        "held"    => held.len(),
+                        "dropped" => chunk.len(),
+                    );
+
+                    held.discard(chunk.len());
+                },
+
+                // there's nothing to wait for until the pair answers, so
+                // blocking would only stall the command until the
+                // reconfirmation times out
+                Overflow::Block | Overflow::Terminate => {
+                    slog::error!(self.slog, "held output buffer full, terminating session";
+                        "held" => held.len(),
+                    );
+
+                    return Err(ErrorKind::OutputOverflow.into());
+                },
+            }
+        }
+
+        Ok(())
     }
 
-    fn log_stderr(&self, log: &[u8]) -> Result<()> {
-        self.log_output(log)
+    fn buffer_output(
+        &self,
+        socket: &mut Socket,
+        output: &mut OutputBuffer,
+        log:    &[u8],
+    ) -> Result<()> {
+        // make as much room as we can before trying to fit in the new
+        // output
+        output.drain(&mut *socket)
+            .context(ErrorKind::SessionTerminated)?;
+
+        if output.push(log) {
+            slog::trace!(self.slog, "{{{} bytes buffered}}", log.len());
+        } else {
+            match self.options.output_overflow {
+                Overflow::Block => {
+                    slog::debug!(self.slog, "output buffer full, waiting for pair";
+                        "buffered" => output.len(),
+                    );
+
+                    socket.set_nonblocking(false)
+                        .context(ErrorKind::CommunicationError)?;
+
+                    output.flush(&mut *socket)
+                        .context(ErrorKind::SessionTerminated)?;
+
+                    socket.write_all(log)
+                        .context(ErrorKind::SessionTerminated)?;
+
+                    socket.set_nonblocking(true)
+                        .context(ErrorKind::CommunicationError)?;
+
+                    slog::trace!(self.slog, "{{{} bytes sent}}", log.len());
+                },
+
+                Overflow::Drop => {
+                    slog::warn!(self.slog, "output buffer full, dropping output";
+                        "buffered" => output.len(),
+                        "dropped"  => log.len(),
+                    );
+
+                    output.discard(log.len());
+                },
+
+                Overflow::Terminate => {
+                    slog::error!(self.slog, "output buffer full, terminating session";
+                        "buffered" => output.len(),
+                    );
+
+                    return Err(ErrorKind::OutputOverflow.into());
+                },
+            }
+        }
+
+        Ok(())
     }
 
-    fn log_stdin(&self, _: &[u8]) -> Result<()> {
-        // if we're exempt, don't disable stdin
-        if self.is_exempt() {
+    /// Performs all of the periodic checks on the health of the
+    /// session, ending it if any of them fail.
+    fn check_session(&self) -> Result<()> {
+        if let Some(kind) = lock(&self.ended).clone() {
+            return Err(kind.into());
+        }
+
+        self.check_limits()
+            .and_then(|()| self.check_pair())
+            .and_then(|()| self.check_reconfirm())
+            .map_err(|e| self.end_session(e.kind().clone()))
+    }
+
+    /// Checks that the session hasn't run for longer than it's allowed
+    /// to, and that it hasn't sat idle for too long.
+    fn check_limits(&self) -> Result<()> {
+        // limits only apply to sessions with a pair
+        if self.socket.is_none() {
             return Ok(());
         }
 
-        Err(ErrorKind::StdinRedirected.into())
+        let now = Instant::now();
+
+        if is_limit_exceeded(self.options.max_session_duration, now.saturating_duration_since(self.started)) {
+            slog::warn!(self.slog, "session reached its maximum duration";
+                "max_session_duration" => self.options.max_session_duration.as_secs(),
+            );
+
+            return Err(ErrorKind::SessionExpired.into());
+        }
+
+        if is_limit_exceeded(self.options.max_idle, now.saturating_duration_since(*lock(&self.last_active))) {
+            slog::warn!(self.slog, "session was idle for too long";
+                "max_idle" => self.options.max_idle.as_secs(),
+            );
+
+            return Err(ErrorKind::SessionIdle.into());
+        }
+
+        Ok(())
     }
 
-    fn change_winsize(&self, lines: u64, cols: u64) -> Result<()> {
-        self.log_output(format!("\x1b[8;{};{}t", lines, cols).as_bytes())
+    /// Tells both the user and the pair why the session is being ended,
+    /// and returns the error that will end it. Once ended, the session
+    /// stays that way, and every later check fails for the same reason.
+    fn end_session(&self, kind: ErrorKind) -> Error {
+        {
+            let mut ended = lock(&self.ended);
+
+            // the session only needs to be announced as ended once
+            if let Some(ended) = ended.as_ref() {
+                return ended.clone().into();
+            }
+
+            *ended = Some(kind.clone());
+        }
+
+        let message = format!(
+            "\r\n[sudo_pair] session {} ended: {}\r\n",
+            self.session_id,
+            kind,
+        );
+
+        let _ = self.env.tty().as_mut().map(|tty|
+            tty.write_all(message.as_bytes())
+        );
+
+        // this is best-effort; anything still buffered will be given
+        // one last chance to be delivered when the session is closed
+        if lock(&self.output).push(message.as_bytes()) {
+            let _ = self.drain_output();
+        }
+
+        kind.into()
     }
-}
 
-impl SudoPair {
-    fn log_output(&self, log: &[u8]) -> Result<()> {
-        // if we have a socket, write to it
-        self.socket.as_ref().map_or(Ok(()), |socket| {
-            socket.borrow_mut().write_all(log)
-        }).context(ErrorKind::SessionTerminated)?;
+    /// Ends the session from within one of the events `sudo` runs on
+    /// our behalf, where there's no callback to return an error from.
+    fn end_session_from_event(&self, kind: ErrorKind) {
+        let _ = self.end_session(kind);
 
-        slog::trace!(self.slog, "{{{} bytes sent}}", log.len());
+        SessionEvents::loopbreak();
+    }
 
-        Ok(())
+    /// Checks on the session from a timer, so that problems are noticed
+    /// even while the command is quiet and the user isn't typing.
+    fn check_from_event(&self) {
+        if self.check_session().is_err() {
+            SessionEvents::loopbreak();
+        }
+    }
+
+    /// Checks that the pair is still connected and responsive, and
+    /// sends them a new heartbeat probe if one is due.
+    fn check_pair(&self) -> Result<()> {
+        let Some(socket) = self.socket.as_ref() else {
+            return Ok(());
+        };
+
+        let mut socket    = lock(socket);
+        let mut heartbeat = lock(&self.heartbeat);
+        let mut response  = [0; 64];
+        let     now       = Instant::now();
+
+        // the socket is non-blocking by now, so consume everything the
+        // pair has sent us since the last check; for heartbeats, we
+        // don't care what it is, only that they're still there to send
+        // it, but it may also be their answer to a reconfirmation
+        loop {
+            match socket.read(&mut response) {
+                Ok(0) => {
+                    slog::warn!(self.slog, "pair disconnected");
+
+                    return Err(ErrorKind::SessionTerminated.into());
+                },
+
+                Ok(n) => {
+                    heartbeat.response_received();
+                    lock(&self.reconfirm).feed(&response[..n]);
+                },
+
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock  => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => break,
+                Err(e) => {
+                    slog::warn!(self.slog, "pair connection failed";
+                        "error" => e.to_string(),
+                    );
+
+                    return Err(ErrorKind::SessionTerminated.into());
+                },
+            }
+        }
+
+        if heartbeat.is_expired(now) {
+            slog::error!(self.slog, "pair stopped responding to heartbeats";
+                "heartbeat_timeout" => self.options.heartbeat_timeout.as_secs(),
+            );
+
+            return Err(ErrorKind::SessionTerminated.into());
+        }
+
+        if !heartbeat.is_probe_due(now) {
+            return Ok(());
+        }
+
+        let mut output = lock(&self.output);
+
+        // if the output buffer is full, the probe will be tried again
+        // next time; the pair is still reading, just slowly
+        if output.push(heartbeat::PROBE) {
+            slog::trace!(self.slog, "heartbeat sent");
+
+            heartbeat.probe_sent(now);
+        }
+
+        drain(&mut output, &mut socket)
+    }
+
+    /// If it's time for the pair to reconfirm the session, prompts them
+    /// to do so. The command carries on while they're asked, but its
+    /// output is held back until they've answered; if they decline or
+    /// don't answer in time, the held output is discarded and the
+    /// session is terminated. Their answer is read along with
+    /// everything else they send.
+    fn check_reconfirm(&self) -> Result<()> {
+        let Some(socket) = self.socket.as_ref() else {
+            return Ok(());
+        };
+
+        let mut reconfirm = lock(&self.reconfirm);
+        let     now       = Instant::now();
+
+        if reconfirm.is_awaiting() {
+            match reconfirm.answer().map(|byte| byte.to_ascii_lowercase()) {
+                Some(b'y') => (),
+                Some(_)    => {
+                    slog::warn!(self.slog, "remote pair declined to reconfirm session");
+                    let _ = lock(&self.held).take();
+                    return Err(ErrorKind::ReconfirmationFailed.into());
+                },
+                None if reconfirm.is_expired(now) => {
+                    slog::warn!(self.slog, "remote pair didn't reconfirm session in time";
+                        "reconfirm_timeout" => self.options.reconfirm_timeout.as_secs(),
+                    );
+                    let _ = lock(&self.held).take();
+                    return Err(ErrorKind::ReconfirmationFailed.into());
+                },
+                None => return Ok(()),
+            }
+
+            reconfirm.confirmed(now);
+
+            slog::info!(self.slog, "remote pair reconfirmed session");
+
+            let mut socket = lock(socket);
+            let mut output = lock(&self.output);
+
+            // echo back out the response, since the client is
+            // anticipated to be noecho
+            let _ = output.push(b"y\r\n");
+
+            // now that the pair is known to be watching, they're shown
+            // everything the command output while they were being asked
+            let held      = lock(&self.held).take();
+            let chunk_len = output.max_chunk_len();
+
+            for chunk in held.chunks(chunk_len) {
+                self.buffer_output(&mut socket, &mut output, chunk)?;
+            }
+
+            return drain(&mut output, &mut socket);
+        }
+
+        if !reconfirm.is_due(now) {
+            return Ok(());
+        }
+
+        let summary = reconfirm.summary(now);
+        let prompt  = format!(
+            "\r\n[{}] this session is still running ({})\r\ncontinue monitoring? y/n [n]: ",
+            self.session_id,
+            summary,
+        );
+
+        let mut output = lock(&self.output);
+
+        // the pair needs to see everything that happened before they're
+        // asked to confirm it, so the prompt is queued up behind it; if
+        // there isn't room yet, they'll be asked next time
+        if !output.push(prompt.as_bytes()) {
+            return Ok(());
+        }
+
+        reconfirm.prompted(now);
+
+        slog::info!(self.slog, "remote pair reconfirmation required";
+            "activity" => &summary,
+        );
+
+        // let the user know why the session may be about to end
+        let _ = self.env.tty().as_mut().map(|tty|
+            tty.write_all(b"\r\n[sudo_pair] asking pair to reconfirm this session\r\n")
+        );
+
+        drain(&mut output, &mut lock(socket))
+    }
+
+    /// Sends the pair as much of the buffered output as they'll accept,
+    /// once `sudo` has told us they're able to accept more.
+    fn flush_output(&self) {
+        if let Err(e) = self.drain_output() {
+            slog::warn!(self.slog, "pair connection failed";
+                "error" => e.to_string(),
+            );
+
+            self.end_session_from_event(ErrorKind::SessionTerminated);
+        }
+    }
+
+    fn drain_output(&self) -> Result<()> {
+        match self.socket.as_ref() {
+            Some(socket) => drain(&mut lock(&self.output), &mut lock(socket)),
+            None         => Ok(()),
+        }
     }
 
     fn local_pair_prompt(&self, template_spec: &Spec) {
@@ -327,7 +1172,7 @@ impl SudoPair {
             self.socket_mode(),
         ).context(ErrorKind::CommunicationError)?;
 
-        self.socket = Some(RefCell::new(socket));
+        self.socket = Some(Mutex::new(socket));
 
         slog::info!(slog, "socket connected");
 
@@ -349,8 +1194,8 @@ impl SudoPair {
 
         let mut socket = self.socket
             .as_ref()
-            .ok_or(ErrorKind::CommunicationError)?
-            .borrow_mut();
+            .map(lock)
+            .ok_or(ErrorKind::CommunicationError)?;
 
         socket.write_all(&prompt[..])
             .context(ErrorKind::CommunicationError)?;
@@ -367,36 +1212,169 @@ impl SudoPair {
         // bytes actually read from `read`
         let mut response : [u8; 1] = [b'n'];
 
-        slog::debug!(self.slog, "remote prompt awaiting response...");
+        slog::debug!(self.slog, "remote prompt awaiting response...");
+
+        // read exactly one byte back from the socket for the
+        // response (`read_exact` isn't used because it will capture
+        // Ctrl-C and retry the read); we don't need to check the return
+        // value because if the read was successful, we're guaranteed to
+        // have read at least one byte
+        let _ = socket.read(&mut response)
+            .context(ErrorKind::SessionDeclined)?;
+
+        slog::debug!(self.slog, "remote pair responded";
+            "response" => String::from_utf8_lossy(&response[..]).into_owned(),
+        );
+
+        // echo back out the response, since the client is anticipated
+        // to be noecho
+        let _ = socket.write_all(&response[..]);
+        let _ = socket.write_all(b"\n");
+
+        match &response {
+            b"y" | b"Y" => (),
+            _           => {
+                slog::warn!(self.slog, "remote pair declined session");
+                return Err(ErrorKind::SessionDeclined.into());
+            }
+        };
+
+        slog::info!(self.slog, "remote pair approved session");
+
+        Ok(())
+    }
+
+    fn remote_pair_nonblocking(&self) -> Result<()> {
+        // now that the session has been approved, output is buffered and
+        // written to the pair without blocking so that a slow (or
+        // stalled) pair doesn't also stall the command
+        self.socket
+            .as_ref()
+            .map(lock)
+            .ok_or(ErrorKind::CommunicationError)?
+            .set_nonblocking(true)
+            .context(ErrorKind::CommunicationError)?;
+
+        Ok(())
+    }
+
+    /// Returns the ID of an earlier session that was approved to run
+    /// this exact command, if that approval can be reused.
+    fn cached_approval(&self) -> Option<String> {
+        let cache = self.approval_cache()?;
+
+        match cache.lookup(&self.approval_cache_key()) {
+            Ok(approved) => approved,
+            Err(e)       => {
+                // a broken cache shouldn't stop anyone from finding a
+                // pair the usual way
+                slog::warn!(self.slog, "couldn't read approval cache";
+                    "error" => e.to_string(),
+                );
+
+                None
+            },
+        }
+    }
+
+    /// Records that this session was approved, so that it can be
+    /// repeated without another pair until the approval expires.
+    fn cache_approval(&self) {
+        let cache = match self.approval_cache() {
+            Some(cache) => cache,
+            None        => return,
+        };
+
+        if let Err(e) = cache.record(&self.approval_cache_key(), self.session_id) {
+            slog::warn!(self.slog, "couldn't record approval in cache";
+                "error" => e.to_string(),
+            );
+        }
+    }
+
+    /// Uses up a pre-approval ticket for this session, if an approver
+    /// has issued one that permits it. Returns whether or not the
+    /// session was pre-approved.
+    fn consume_ticket(&self) -> bool {
+        if !self.options.allow_tickets {
+            return false;
+        }
+
+        let store   = TicketStore::new(self.options.socket_dir.join(TICKETS_DIR));
+        let command = self.ticket_command();
+
+        let request = Request {
+            requester: self.env.user_info.uid,
+            runas_uid: if self.is_sudoing_to_user() { Some(self.env.command_info.runas_euid) } else { None },
+            runas_gid: if self.is_sudoing_to_explicit_group() { Some(self.env.command_info.runas_egid) } else { None },
+            command:   &command,
+        };
 
-        // read exactly one byte back from the socket for the
-        // response (`read_exact` isn't used because it will capture
-        // Ctrl-C and retry the read); we don't need to check the return
-        // value because if the read was successful, we're guaranteed to
-        // have read at least one byte
-        let _ = socket.read(&mut response)
-            .context(ErrorKind::SessionDeclined)?;
+        match store.consume(&request, SystemTime::now()) {
+            Ok(Some(ticket)) => {
+                slog::info!(self.slog, "pair session exempt by pre-approval ticket";
+                    "ticket"         => ticket.id,
+                    "approver_uid"   => ticket.approver,
+                    "uses_remaining" => ticket.uses,
+                );
 
-        slog::debug!(self.slog, "remote pair responded";
-            "response" => String::from_utf8_lossy(&response[..]).into_owned(),
-        );
+                true
+            },
 
-        // echo back out the response, since the client is anticipated
-        // to be noecho
-        let _ = socket.write_all(&response[..]);
-        let _ = socket.write_all(b"\n");
+            Ok(None) => false,
 
-        match &response {
-            b"y" | b"Y" => (),
-            _           => {
-                slog::warn!(self.slog, "remote pair declined session");
-                return Err(ErrorKind::SessionDeclined.into());
-            }
-        };
+            Err(e) => {
+                // a broken ticket store shouldn't stop anyone from
+                // finding a pair the usual way
+                slog::warn!(self.slog, "couldn't read pre-approval tickets";
+                    "error" => e.to_string(),
+                );
 
-        slog::info!(self.slog, "remote pair approved session");
+                false
+            },
+        }
+    }
 
-        Ok(())
+    /// The command line that pre-approval tickets are matched against:
+    /// the full path to the command, followed by its arguments. Each
+    /// argument is kept separate, so that a pattern can't match across
+    /// them.
+    fn ticket_command(&self) -> Vec<&[u8]> {
+        iter::once(self.env.command_info.command.as_os_str().as_bytes())
+            .chain(self.env.cmdline.iter().skip(1).map(CString::as_bytes))
+            .collect()
+    }
+
+    fn approval_cache(&self) -> Option<ApprovalCache> {
+        if self.options.approval_cache_ttl == Duration::from_secs(0) {
+            return None;
+        }
+
+        Some(ApprovalCache::new(
+            self.options.socket_dir.join(APPROVAL_CACHE_DIR),
+            self.options.approval_cache_ttl,
+        ))
+    }
+
+    fn approval_cache_key(&self) -> CacheKey {
+        let tty = self.env.user_info.tty.as_ref()
+            .map(|tty| tty.as_os_str().as_bytes())
+            .unwrap_or_default();
+
+        // an approval only carries over to the same user, in the same
+        // terminal session, becoming the same target, to run exactly
+        // the same command line; the cwd is included since it can
+        // change what the arguments refer to
+        CacheKey::new(&[
+            self.env.user_info.uid.to_string().as_bytes(),
+            tty,
+            self.env.user_info.sid.to_string().as_bytes(),
+            self.env.command_info.runas_euid.to_string().as_bytes(),
+            self.env.command_info.runas_egid.to_string().as_bytes(),
+            self.env.command_info.command.as_os_str().as_bytes(),
+            &self.env.invocation(),
+            self.env.cwd().as_os_str().as_bytes(),
+        ])
     }
 
     fn is_exempt(&self) -> bool {
@@ -431,8 +1409,10 @@ impl SudoPair {
             return true;
         }
 
-        // policy plugins can inform us that logging is unnecessary
-        if self.is_exempted_from_logging() {
+        // policy plugins can inform us that logging is unnecessary; the
+        // approval plugin requires a pair regardless, since it doesn't
+        // rely on sudoers to be configured to log the session
+        if !self.via_approval && self.is_exempted_from_logging() {
             slog::debug!(self.slog, "sudo command exempted from logging");
 
             return true;
@@ -560,11 +1540,15 @@ impl SudoPair {
         // note that we want the *`uid`* and not the `euid` here since
         // we want to know who the real user is and not the `uid` of the
         // owner of `sudo`
+        //
+        // the session ID is included so that the approval command can
+        // find the socket given only the ID the user was shown
         self.options.socket_dir.join(
             format!(
-                "{}.{}.sock",
+                "{}.{}.{}.sock",
                 self.env.user_info.uid,
                 self.env.user_info.pid,
+                self.session_id,
             )
         )
     }
@@ -636,11 +1620,15 @@ impl SudoPair {
 
         // the full _C_ommand `sudo` was invoked as (recreated as
         // best-effort for now)
-        spec.replace(b'C', self.env.invocation());
+        spec.replace(b'C', &self.invocation[..]);
 
         // the cw_d_ of the command being run under `sudo`
         spec.replace(b'd', self.env.cwd().as_os_str().as_bytes());
 
+        // whether the command runs in _I_ntercept mode, where any
+        // commands it runs in turn are also subject to the policy
+        spec.replace(b'I', if self.env.command_info.intercept { "yes" } else { "no" });
+
         // the _h_ostname of the machine `sudo` is being executed on
         spec.replace(b'h', self.env.user_info.host.as_bytes());
 
@@ -653,6 +1641,9 @@ impl SudoPair {
         // the _p_id of this `sudo` process
         spec.replace(b'p', self.env.user_info.pid.to_string());
 
+        // the randomly-generated _s_ession ID for this `sudo` session
+        spec.replace(b's', self.session_id.to_string());
+
         // the real _u_id of the user invoking `sudo`
         spec.replace(b'u', self.env.user_info.uid.to_string());
 
@@ -666,18 +1657,82 @@ impl SudoPair {
     }
 }
 
+/// The approval plugin entry point. This pairs sessions before the
+/// command is started, then hands them off to the I/O plugin so that
+/// their output can be mirrored to the pair.
+struct SudoPairApproval {
+    env: Arc<ApprovalEnv>,
+}
+
+impl ApprovalPlugin for SudoPairApproval {
+    type Error = Error;
+
+    const NAME: &'static str = "sudo_pair";
+
+    fn open(env: Arc<ApprovalEnv>) -> Result<Self> {
+        Ok(Self { env })
+    }
+
+    fn check(
+        &self,
+        command_info: &CommandInfo,
+        run_argv:     &[CString],
+        _:            &OptionMap,
+    ) -> Result<()> {
+        let mut pair = SudoPair::new(
+            Env::from_approval_env(&self.env, command_info, run_argv),
+            &self.env.plugin_options,
+            true,
+        )?;
+
+        pair.authorize()?;
+
+        // `sudo` closes approval plugins before it opens any I/O
+        // plugins, so the only chance to notice that no I/O plugin ever
+        // picked the session up is when `sudo` exits
+        APPROVING_PID.store(process::id(), Ordering::Relaxed);
+        ABANDON_AT_EXIT.call_once(|| {
+            // SAFETY: `abandon_approved_pair` is a plain function that
+            // never unwinds back into C
+            let _ = unsafe { libc::atexit(abandon_approved_pair) };
+        });
+
+        *lock(&APPROVED_PAIR) = Some(pair);
+
+        Ok(())
+    }
+}
+
+/// Ends any session the approval plugin paired that was never handed
+/// off to the I/O plugin (e.g., because `sudo.conf` doesn't load it),
+/// so that the pair isn't left believing they watched it. Run when
+/// `sudo` exits.
+extern "C" fn abandon_approved_pair() {
+    if process::id() != APPROVING_PID.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let approved = lock(&APPROVED_PAIR).take();
+
+    if let Some(pair) = approved {
+        // panics can't be allowed to unwind into `exit(3)`
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| pair.abandon()));
+    }
+}
+
 impl Drop for SudoPair {
     fn drop(&mut self) {
         slog::debug!(self.slog, "plugin exiting");
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, SudoOptions)]
 struct PluginOptions {
     /// `binary_path` is the location of the approval binary, so that we
     /// can bypass the approval process for invoking it.
     ///
     /// Default: `"/usr/bin/sudo_approve"`
+    #[sudo_option(default = "DEFAULT_BINARY_PATH.into()")]
     binary_path: PathBuf,
 
     /// `user_prompt_path` is the location of the prompt template to
@@ -685,6 +1740,7 @@ struct PluginOptions {
     /// this location, an extremely minimal default will be printed.
     ///
     /// Default: `"/etc/sudo_pair.prompt.user"`
+    #[sudo_option(default = "DEFAULT_USER_PROMPT_PATH.into()")]
     user_prompt_path: PathBuf,
 
     /// `pair_prompt_path` is the location of the prompt template to
@@ -693,12 +1749,14 @@ struct PluginOptions {
     /// default will be printed.
     ///
     /// Default: `"/etc/sudo_pair.prompt.pair"`
+    #[sudo_option(default = "DEFAULT_PAIR_PROMPT_PATH.into()")]
     pair_prompt_path: PathBuf,
 
     /// `socket_dir` is the path where this plugin will store sockets for
     /// sessions that are pending approval.
     ///
     /// Default: `"/var/run/sudo_pair"`
+    #[sudo_option(default = "DEFAULT_SOCKET_DIR.into()")]
     socket_dir: PathBuf,
 
     /// `gids_enforced` is a comma-separated list of gids that sudo_pair
@@ -707,6 +1765,7 @@ struct PluginOptions {
     /// pair approve their session.
     ///
     /// Default: `[0]` (e.g., root)
+    #[sudo_option(default = "DEFAULT_GIDS_ENFORCED.iter().copied().collect()")]
     gids_enforced: HashSet<gid_t>,
 
     /// `gids_exempted` is a comma-separated list of gids whose users
@@ -719,6 +1778,107 @@ struct PluginOptions {
     ///
     /// Default: `[]` (however, root is *always* exempt)
     gids_exempted: HashSet<gid_t>,
+
+    /// `output_buffer_size` is the number of bytes of command output
+    /// that will be held in memory while waiting for the pair to read
+    /// it. Must be at least `1085` bytes, so there's always room for a
+    /// chunk of output alongside the marker for any dropped output.
+    ///
+    /// Default: `1048576` (e.g., 1MiB)
+    #[sudo_option(default = "DEFAULT_OUTPUT_BUFFER_SIZE", validate = "buffer::is_valid_capacity")]
+    output_buffer_size: usize,
+
+    /// `output_overflow` determines what happens when the output buffer
+    /// is full: `block` stalls the command until the pair catches up,
+    /// `drop` discards output (and tells the pair how much was lost),
+    /// and `terminate` ends the session.
+    ///
+    /// Default: `block`
+    #[sudo_option(default = "DEFAULT_OUTPUT_OVERFLOW")]
+    output_overflow: Overflow,
+
+    /// `heartbeat_interval` is how often, in seconds, the pair's
+    /// terminal is probed to make sure they're still there. A value of
+    /// `0` disables heartbeats.
+    ///
+    /// Default: `30`
+    #[sudo_option(default = "DEFAULT_HEARTBEAT_INTERVAL")]
+    heartbeat_interval: Duration,
+
+    /// `heartbeat_timeout` is how long, in seconds, the pair has to
+    /// respond to a heartbeat before the session is terminated.
+    ///
+    /// Default: `60`
+    #[sudo_option(default = "DEFAULT_HEARTBEAT_TIMEOUT")]
+    heartbeat_timeout: Duration,
+
+    /// `reconfirm_interval` is how often, in seconds, the pair must
+    /// reconfirm that they're still monitoring the session. A value of
+    /// `0` disables reconfirmation.
+    ///
+    /// Default: `0`
+    #[sudo_option(default = "DEFAULT_RECONFIRM_INTERVAL")]
+    reconfirm_interval: Duration,
+
+    /// `reconfirm_timeout` is how long, in seconds, the pair has to
+    /// reconfirm the session once prompted before the session is
+    /// terminated.
+    ///
+    /// Default: `60`
+    #[sudo_option(default = "DEFAULT_RECONFIRM_TIMEOUT")]
+    reconfirm_timeout: Duration,
+
+    /// `max_session_duration` is the longest, in seconds, that a paired
+    /// session may run before it is terminated. A value of `0` allows
+    /// sessions to run indefinitely.
+    ///
+    /// Default: `0`
+    #[sudo_option(default = "DEFAULT_MAX_SESSION")]
+    max_session_duration: Duration,
+
+    /// `max_idle` is the longest, in seconds, that a paired session may
+    /// go without any input or output before it is terminated. A value
+    /// of `0` allows sessions to sit idle indefinitely.
+    ///
+    /// Default: `0`
+    #[sudo_option(default = "DEFAULT_MAX_IDLE")]
+    max_idle: Duration,
+
+    /// `redact_patterns_path` is the location of a file of patterns to
+    /// mask in the command's arguments and output, both in what's shown
+    /// to the pair and in what's logged. Each line is either a
+    /// `literal:` string or a `regex:`.
+    ///
+    /// Default: none
+    redact_patterns_path: Option<PathBuf>,
+
+    /// `deny_suspend` ends paired sessions if the user suspends the
+    /// command (e.g., with Ctrl-Z), rather than notifying the pair and
+    /// allowing it. Requires a version of sudo that supports version
+    /// 1.13 of the plugin API.
+    ///
+    /// Default: `false`
+    #[sudo_option(default = "DEFAULT_DENY_SUSPEND")]
+    deny_suspend: bool,
+
+    /// `approval_cache_ttl` is how long, in seconds, an approved session
+    /// may be repeated without another approval, as long as it's the
+    /// same user in the same terminal session running exactly the same
+    /// command as the same target. Approvals are recorded under the
+    /// `approvals` subdirectory of `socket_dir`. A value of `0` disables
+    /// reuse of approvals.
+    ///
+    /// Default: `0`
+    #[sudo_option(default = "DEFAULT_APPROVAL_CACHE_TTL")]
+    approval_cache_ttl: Duration,
+
+    /// `allow_tickets` allows sessions to be approved ahead of time
+    /// through pre-approval tickets issued by an approver, which are
+    /// recorded under the `tickets` subdirectory of `socket_dir`.
+    ///
+    /// Default: `false`
+    #[sudo_option(default = "DEFAULT_ALLOW_TICKETS")]
+    allow_tickets: bool,
 }
 
 impl PluginOptions {
@@ -727,33 +1887,68 @@ impl PluginOptions {
             self.binary_path.as_os_str()
         ).as_bytes()
     }
+
+    /// Returns true if anything about a paired session needs to be
+    /// checked on a timer.
+    fn is_checked_periodically(&self) -> bool {
+        self.heartbeat_interval   != Duration::from_secs(0) ||
+        self.reconfirm_interval   != Duration::from_secs(0) ||
+        self.max_session_duration != Duration::from_secs(0) ||
+        self.max_idle             != Duration::from_secs(0)
+    }
+}
+
+/// Masks anything in `bytes` matching the configured redaction
+/// patterns, if there are any.
+fn redact<'a>(redactor: Option<&Redactor>, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+    match redactor {
+        Some(redactor) => redactor.redact(bytes).into(),
+        None           => bytes.into(),
+    }
 }
 
-// TODO: single_use_lifetimes was committed, but I'm not sure there's
-// actually a way to satisfy the linter for the time being
-#[allow(single_use_lifetimes)]
-impl<'a> From<&'a OptionMap> for PluginOptions {
-    fn from(map: &'a OptionMap) -> Self {
-        Self {
-            binary_path: map.get("binary_path")
-                .unwrap_or_else(|_| DEFAULT_BINARY_PATH.into()),
+/// Returns a human-readable name for the signals `sudo` may report to
+/// `log_suspend`.
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGCONT => "SIGCONT".into(),
+        libc::SIGSTOP => "SIGSTOP".into(),
+        libc::SIGTSTP => "SIGTSTP".into(),
+        libc::SIGTTIN => "SIGTTIN".into(),
+        libc::SIGTTOU => "SIGTTOU".into(),
+        _             => format!("signal {}", signal),
+    }
+}
 
-            user_prompt_path: map.get("user_prompt_path")
-                .unwrap_or_else(|_| DEFAULT_USER_PROMPT_PATH.into()),
+/// Writes as much buffered output to the pair as they'll currently
+/// accept, and asks to be told once they're able to take the rest.
+fn drain(output: &mut OutputBuffer, socket: &mut Socket) -> Result<()> {
+    output.drain(socket)
+        .context(ErrorKind::SessionTerminated)?;
 
-            pair_prompt_path: map.get("pair_prompt_path")
-                .unwrap_or_else(|_| DEFAULT_PAIR_PROMPT_PATH.into()),
+    if !output.is_empty() {
+        SessionEvents::flush_when_writable();
+    }
 
-            socket_dir: map.get("socket_dir")
-                .unwrap_or_else(|_| DEFAULT_SOCKET_DIR.into()),
+    Ok(())
+}
 
-            gids_enforced: map.get("gids_enforced")
-                .unwrap_or_else(|_| DEFAULT_GIDS_ENFORCED.iter().copied().collect()),
+/// Locks `mutex`, even if it was poisoned. Panics inside of callbacks
+/// are caught at the FFI boundary, so the state behind the lock is no
+/// less usable than it would otherwise have been.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-            gids_exempted: map.get("gids_exempted")
-                .unwrap_or_default(),
-        }
-    }
+/// Like [`lock`], but for when we already have exclusive access.
+fn get_mut<T>(mutex: &mut Mutex<T>) -> &mut T {
+    mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns true if `elapsed` exceeds `limit`, where a `limit` of zero
+/// means there isn't one.
+fn is_limit_exceeded(limit: Duration, elapsed: Duration) -> bool {
+    limit != Duration::from_secs(0) && elapsed > limit
 }
 
 impl slog::Value for PluginOptions {
@@ -804,3 +1999,516 @@ fn slog(name: &str, version: &str) -> slog::Logger {
         "plugin_version" => version.to_owned()
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_plugin::errors::Error as PluginError;
+    use sudo_plugin::options::{Settings, UserInfo};
+    use sudo_plugin::testing::MockSudo;
+
+    use crate::state::TempDir;
+
+    use std::fs;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::ptr;
+    use std::thread;
+
+    fn plugin() -> &'static sudo_plugin::sys::io_plugin {
+        unsafe { &*ptr::addr_of!(sudo_pair) }
+    }
+
+    /// Builds a pair session for `alice` (uid and gid 1000) running
+    /// `/usr/bin/id -u` as root, without opening the plugin.
+    fn pair(
+        settings:       Settings,
+        command_info:   CommandInfo,
+        plugin_options: OptionMap,
+    ) -> SudoPair {
+        let env = IoEnv::builder()
+            .cmdline(vec![
+                CString::new("/usr/bin/id").unwrap(),
+                CString::new("-u").unwrap(),
+            ])
+            .settings(settings)
+            .user_info(UserInfo::builder()
+                .user("alice")
+                .uid(1000)
+                .euid(0)
+                .gid(1000)
+                .groups(vec![1000])
+                .host("build01")
+                .cwd("/home/alice")
+                .build()
+            )
+            .command_info(command_info)
+            .plugin_options(plugin_options)
+            .build();
+
+        SudoPair::new(Env::from_io_env(&env), &env.plugin_options, false).unwrap()
+    }
+
+    /// Connects `pair` to a socket, returning the pair's end of it.
+    fn connect(pair: &mut SudoPair) -> UnixStream {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+
+        ours.set_nonblocking(true).unwrap();
+        theirs.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        pair.socket = Some(Mutex::new(Socket::from(ours)));
+
+        theirs
+    }
+
+    fn to_root() -> CommandInfo {
+        CommandInfo::builder()
+            .command("/usr/bin/id")
+            .runas_uid(0)
+            .runas_gid(0)
+            .runas_groups(vec![0])
+            .iolog_ttyout(true)
+            .build()
+    }
+
+    #[test]
+    fn root_is_exempt_from_pairing() {
+        let mut sudo = MockSudo::new();
+
+        let _ = sudo
+            .user_info("uid",  0_u32)
+            .user_info("euid", 0_u32)
+            .command_info("iolog_ttyout", true);
+
+        let mut session = unsafe { sudo.open_io(plugin()) };
+
+        assert_eq!(1, session.status());
+        assert_eq!(1, session.log_ttyout(b"uid=0(root)\n"));
+        assert_eq!(1, session.change_winsize(50, 132));
+
+        assert!(session.close(0, 0).stderr.is_empty());
+    }
+
+    #[test]
+    fn sessions_without_logging_are_exempt_from_pairing() {
+        let mut session = unsafe { MockSudo::new().open_io(plugin()) };
+
+        assert_eq!(1, session.status());
+        assert_eq!(1, session.log_stdout(b"output"));
+    }
+
+    #[test]
+    fn sudo_to_user_and_group_is_denied() {
+        let mut sudo = MockSudo::new();
+
+        let _ = sudo
+            .setting("runas_group", "wheel")
+            .command_info("runas_groups", "0")
+            .command_info("iolog_ttyout", true);
+
+        let session = unsafe { sudo.open_io(plugin()) };
+
+        assert_eq!(-1, session.status());
+
+        assert!(session.errstr().unwrap().to_string_lossy().contains(
+            "the -u and -g options may not both be specified"
+        ));
+
+        let stderr = session.close(0, 0).stderr;
+
+        assert!(String::from_utf8_lossy(&stderr).contains(
+            "the -u and -g options may not both be specified"
+        ));
+    }
+
+    #[test]
+    fn errstr_is_not_reported_to_older_sudos() {
+        let mut sudo = MockSudo::new();
+
+        let _ = sudo
+            .api_version(1, 14)
+            .setting("runas_group", "wheel")
+            .command_info("iolog_ttyout", true);
+
+        let session = unsafe { sudo.open_io(plugin()) };
+
+        assert_eq!(-1, session.status());
+        assert!(session.errstr().is_none());
+    }
+
+    #[test]
+    fn version_is_shown_without_a_command() {
+        let mut sudo = MockSudo::new();
+
+        let _ = sudo.command("", &[]);
+
+        let mut session = unsafe { sudo.open_io(plugin()) };
+
+        assert_eq!(1, session.status());
+        assert_eq!(1, session.show_version(false));
+
+        assert_eq!(
+            format!("sudo_pair I/O plugin version {}\n", SudoPairIo::VERSION).into_bytes(),
+            session.close(0, 0).stdout,
+        );
+    }
+
+    #[test]
+    fn sudo_to_root_requires_a_user_writable_socket() {
+        let pair = pair(Settings::builder().build(), to_root(), OptionMap::new());
+
+        assert!(!pair.is_exempt());
+        assert_eq!(libc::S_IWUSR, pair.socket_mode());
+    }
+
+    #[test]
+    fn sudo_to_group_requires_a_group_writable_socket() {
+        let pair = pair(
+            Settings::builder().runas_group("wheel").build(),
+            CommandInfo::builder()
+                .command("/usr/bin/id")
+                .runas_uid(1000)
+                .runas_gid(0)
+                .runas_groups(vec![0])
+                .iolog_ttyout(true)
+                .build(),
+            OptionMap::new(),
+        );
+
+        assert!(!pair.is_exempt());
+        assert_eq!(libc::S_IWGRP, pair.socket_mode());
+    }
+
+    #[test]
+    fn members_of_exempted_groups_are_exempt() {
+        let mut options = OptionMap::new();
+
+        options.insert("gids_exempted", "1000");
+
+        assert!(pair(Settings::builder().build(), to_root(), options).is_exempt());
+    }
+
+    #[test]
+    fn sudo_to_unenforced_groups_is_exempt() {
+        let mut options = OptionMap::new();
+
+        options.insert("gids_enforced", "27");
+
+        assert!(pair(Settings::builder().build(), to_root(), options).is_exempt());
+    }
+
+    #[test]
+    fn template_spec_describes_the_session() {
+        let pair = pair(Settings::builder().build(), to_root(), OptionMap::new());
+        let spec = pair.template_spec();
+
+        assert_eq!(
+            &b"alice (1000) on build01 ran `sudo /usr/bin/id -u` in /home/alice"[..],
+            &spec.expand(b"%U (%u) on %h ran `%C` in %d")[..],
+        );
+    }
+
+    #[test]
+    fn template_spec_describes_intercept_mode() {
+        let intercepted = CommandInfo::builder()
+            .command("/usr/bin/id")
+            .runas_uid(0)
+            .runas_gid(0)
+            .iolog_ttyout(true)
+            .intercept(true)
+            .build();
+
+        let direct = pair(Settings::builder().build(), to_root(),   OptionMap::new());
+        let nested = pair(Settings::builder().build(), intercepted, OptionMap::new());
+
+        assert_eq!(&b"intercept: no"[..],  &direct.template_spec().expand(b"intercept: %I")[..]);
+        assert_eq!(&b"intercept: yes"[..], &nested.template_spec().expand(b"intercept: %I")[..]);
+    }
+
+    #[test]
+    fn invocation_is_shell_quoted() {
+        let env = IoEnv::builder()
+            .cmdline(vec![
+                CString::new("sh").unwrap(),
+                CString::new("-c").unwrap(),
+                CString::new("rm -rf /tmp/x y").unwrap(),
+            ])
+            .settings(Settings::builder()
+                .runas_user("root")
+                .timeout("5m")
+                .remote_host("db01")
+                .build()
+            )
+            .build();
+
+        assert_eq!(
+            "sudo --user root --command-timeout 5m --host db01 sh -c 'rm -rf /tmp/x y'",
+            String::from_utf8_lossy(&Env::from_io_env(&env).invocation()),
+        );
+    }
+
+    #[test]
+    fn invocation_is_redacted_across_arguments() {
+        let dir      = TempDir::new("redacted-invocation");
+        let patterns = dir.path().join("patterns");
+
+        fs::create_dir_all(dir.path()).unwrap();
+        fs::write(&patterns, "regex:--password \\S+\n").unwrap();
+
+        let mut options = OptionMap::new();
+
+        options.insert("redact_patterns_path", &patterns);
+
+        let env = IoEnv::builder()
+            .cmdline(vec![
+                CString::new("mysql").unwrap(),
+                CString::new("--password").unwrap(),
+                CString::new("hunter2").unwrap(),
+            ])
+            .settings(Settings::builder().build())
+            .user_info(UserInfo::builder().cwd("/home/alice").build())
+            .command_info(to_root())
+            .plugin_options(options)
+            .build();
+
+        let pair = SudoPair::new(Env::from_io_env(&env), &env.plugin_options, false).unwrap();
+
+        assert_eq!(&b"sudo mysql [REDACTED]"[..], &pair.invocation[..]);
+        assert_eq!(pair.invocation, pair.template_spec().expand(b"%C"));
+    }
+
+    #[test]
+    fn plugin_options_default_when_not_provided() {
+        let options = PluginOptions::from_option_map(&OptionMap::new()).unwrap();
+
+        assert_eq!(PathBuf::from(DEFAULT_SOCKET_DIR), options.socket_dir);
+        assert_eq!(vec![0].into_iter().collect::<HashSet<_>>(), options.gids_enforced);
+        assert!(options.gids_exempted.is_empty());
+        assert_eq!(DEFAULT_HEARTBEAT_INTERVAL, options.heartbeat_interval);
+        assert_eq!(None, options.redact_patterns_path);
+    }
+
+    #[test]
+    fn limits_are_checked_periodically() {
+        let mut map = OptionMap::new();
+
+        map.insert("heartbeat_interval", 0_u64);
+
+        assert!(!PluginOptions::from_option_map(&map).unwrap().is_checked_periodically());
+
+        map.insert("max_idle", 300_u64);
+
+        assert!(PluginOptions::from_option_map(&map).unwrap().is_checked_periodically());
+    }
+
+    #[test]
+    fn plugin_options_report_every_invalid_option() {
+        let mut map = OptionMap::new();
+
+        map.insert("gids_enforced",      "0,wheel");
+        map.insert("heartbeat_interval", "soon");
+        map.insert("output_buffer_size", 0_u64);
+        map.insert("max_idle",           300_u64);
+
+        let errors = match PluginOptions::from_option_map(&map) {
+            Err(PluginError::OptionsInvalid(errors)) => errors,
+            other                                    => panic!("unexpected result: {:?}", other),
+        };
+
+        assert_eq!(3, errors.len());
+        assert!(errors.contains(&PluginError::OptionInvalid {
+            key:   "gids_enforced".into(),
+            value: "0,wheel".into(),
+        }));
+        assert!(errors.contains(&PluginError::OptionInvalid {
+            key:   "output_buffer_size".into(),
+            value: "0".into(),
+        }));
+    }
+
+    #[test]
+    fn output_buffer_must_fit_dropped_marker() {
+        let mut map = OptionMap::new();
+
+        map.insert("output_buffer_size", (buffer::MIN_CAPACITY - 1).to_string());
+
+        assert!(PluginOptions::from_option_map(&map).is_err());
+
+        map.insert("output_buffer_size", buffer::MIN_CAPACITY.to_string());
+
+        assert!(PluginOptions::from_option_map(&map).is_ok());
+    }
+
+    #[test]
+    fn output_larger_than_buffer_is_split() {
+        let mut options = OptionMap::new();
+
+        options.insert("output_buffer_size", buffer::MIN_CAPACITY.to_string());
+        options.insert("output_overflow",    "drop");
+
+        let mut pair   = pair(Settings::builder().build(), to_root(), options);
+        let mut theirs = connect(&mut pair);
+        let     output = vec![b'x'; buffer::MIN_CAPACITY * 3];
+
+        pair.send_output(&output).unwrap();
+
+        let mut received = vec![0; output.len()];
+
+        theirs.read_exact(&mut received).unwrap();
+
+        assert_eq!(output, received);
+    }
+
+    #[test]
+    fn output_is_held_until_reconfirmed() {
+        let mut pair   = pair(Settings::builder().build(), to_root(), OptionMap::new());
+        let mut theirs = connect(&mut pair);
+
+        lock(&pair.reconfirm).prompted(Instant::now());
+
+        pair.send_output(b"while asked").unwrap();
+
+        theirs.set_nonblocking(true).unwrap();
+        assert_eq!(
+            io::ErrorKind::WouldBlock,
+            theirs.read(&mut [0; 64]).unwrap_err().kind(),
+        );
+        theirs.set_nonblocking(false).unwrap();
+
+        lock(&pair.reconfirm).feed(b"y");
+        pair.check_reconfirm().unwrap();
+
+        let mut received = [0; 14];
+
+        theirs.read_exact(&mut received).unwrap();
+
+        assert_eq!(b"y\r\nwhile asked", &received);
+    }
+
+    #[test]
+    fn held_output_is_dropped_if_not_reconfirmed() {
+        let mut pair = pair(Settings::builder().build(), to_root(), OptionMap::new());
+        let _theirs  = connect(&mut pair);
+
+        lock(&pair.reconfirm).prompted(Instant::now());
+
+        pair.send_output(b"while asked").unwrap();
+
+        lock(&pair.reconfirm).feed(b"n");
+
+        assert_eq!(
+            ErrorKind::ReconfirmationFailed,
+            *pair.check_reconfirm().unwrap_err().kind(),
+        );
+        assert!(lock(&pair.held).is_empty());
+    }
+
+    #[test]
+    fn invalid_plugin_options_are_a_usage_error() {
+        let mut sudo = MockSudo::new();
+
+        let _ = sudo
+            .plugin_option("output_overflow", "explode")
+            .command_info("iolog_ttyout", true);
+
+        let session = unsafe { sudo.open_io(plugin()) };
+
+        assert_eq!(-2, session.status());
+
+        let stderr = session.close(0, 0).stderr;
+
+        assert!(String::from_utf8_lossy(&stderr).contains("output_overflow: explode"));
+    }
+
+    #[test]
+    fn session_is_closed_while_still_shared() {
+        let mut pair   = pair(Settings::builder().build(), to_root(), OptionMap::new());
+        let mut theirs = connect(&mut pair);
+        let     pair   = Arc::new(pair);
+        let     shared = Arc::clone(&pair);
+
+        SudoPairIo { pair }.close(0, 0);
+
+        let mut received = vec![];
+
+        let _ = theirs.read_to_end(&mut received).unwrap();
+
+        assert!(String::from_utf8_lossy(&received).contains(
+            &format!("session {} ended", shared.session_id)
+        ));
+    }
+
+    /// Set in the environment of the child process that
+    /// `unmirrored_approved_pair_is_ended_at_exit` runs itself in, to
+    /// the path of the socket it should connect to as the pair.
+    const ABANDON_SOCKET_VAR : &str = "SUDO_PAIR_TEST_ABANDON_SOCKET";
+
+    #[test]
+    fn unmirrored_approved_pair_is_ended_at_exit() {
+        // in the child, play the part of `sudo` exiting after approving
+        // a session that no I/O plugin ever picked up
+        if let Some(path) = std::env::var_os(ABANDON_SOCKET_VAR) {
+            let mut pair = pair(Settings::builder().build(), to_root(), OptionMap::new());
+
+            pair.socket = Some(Mutex::new(Socket::from(UnixStream::connect(path).unwrap())));
+
+            *lock(&APPROVED_PAIR) = Some(pair);
+
+            // pairs approved by another process are left alone
+            APPROVING_PID.store(process::id() + 1, Ordering::Relaxed);
+            abandon_approved_pair();
+            assert!(lock(&APPROVED_PAIR).is_some());
+
+            APPROVING_PID.store(process::id(), Ordering::Relaxed);
+            ABANDON_AT_EXIT.call_once(|| {
+                let _ = unsafe { libc::atexit(abandon_approved_pair) };
+            });
+
+            process::exit(0);
+        }
+
+        let dir    = TempDir::new("abandon-at-exit");
+        let socket = dir.path().join("pair.sock");
+
+        fs::create_dir_all(dir.path()).unwrap();
+
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let mut child = process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::unmirrored_approved_pair_is_ended_at_exit"])
+            .env(ABANDON_SOCKET_VAR, &socket)
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // don't wait forever if the child dies before connecting
+        listener.set_nonblocking(true).unwrap();
+
+        let deadline   = Instant::now() + Duration::from_secs(5);
+        let mut theirs = loop {
+            match listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(child.try_wait().unwrap().is_none(), "child exited before connecting");
+                    assert!(Instant::now() < deadline,           "child never connected");
+
+                    thread::sleep(Duration::from_millis(10));
+                },
+                Err(e) => panic!("couldn't accept connection: {}", e),
+            }
+        };
+
+        theirs.set_nonblocking(false).unwrap();
+        theirs.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let mut received = vec![];
+
+        let _ = theirs.read_to_end(&mut received).unwrap();
+
+        assert!(child.wait().unwrap().success());
+        assert!(String::from_utf8_lossy(&received).contains(
+            &ErrorKind::SessionUnmirrored.to_string()
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::summary::format_duration;
+
+use std::time::{Duration, Instant};
+
+/// Tracks how long it's been since the pair last confirmed that they're
+/// watching the session, and what has happened in the meantime.
+#[derive(Debug)]
+pub(crate) struct Reconfirmation {
+    interval: Duration,
+    timeout:  Duration,
+
+    last_confirmed: Instant,
+    output_bytes:   usize,
+    input_bytes:    usize,
+
+    /// when the pair was asked to reconfirm, if they haven't answered
+    prompted: Option<Instant>,
+    answer:   Option<u8>,
+    filter:   EscapeFilter,
+}
+
+impl Reconfirmation {
+    /// Creates a new tracker that requires the pair to reconfirm every
+    /// `interval`, giving them `timeout` to answer each time they're
+    /// asked. An `interval` of zero never requires reconfirmation.
+    pub(crate) fn new(interval: Duration, timeout: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            timeout,
+            last_confirmed: now,
+            output_bytes:   0,
+            input_bytes:    0,
+            prompted:       None,
+            answer:         None,
+            filter:         EscapeFilter::new(),
+        }
+    }
+
+    /// Returns true if the pair needs to be asked to reconfirm the
+    /// session.
+    pub(crate) fn is_due(&self, now: Instant) -> bool {
+        if self.interval == Duration::from_secs(0) || self.is_awaiting() {
+            return false;
+        }
+
+        now.saturating_duration_since(self.last_confirmed) >= self.interval
+    }
+
+    pub(crate) fn record_output(&mut self, len: usize) {
+        self.output_bytes += len;
+    }
+
+    pub(crate) fn record_input(&mut self, len: usize) {
+        self.input_bytes += len;
+    }
+
+    /// Records that the pair has been asked to reconfirm the session.
+    /// Their answer is read from whatever they send from now on.
+    pub(crate) fn prompted(&mut self, now: Instant) {
+        self.prompted = Some(now);
+        self.answer   = None;
+        self.filter   = EscapeFilter::new();
+    }
+
+    /// Returns true if the pair has been asked to reconfirm the session
+    /// and hasn't yet been confirmed.
+    pub(crate) fn is_awaiting(&self) -> bool {
+        self.prompted.is_some()
+    }
+
+    /// Returns true if the pair has failed to answer within the
+    /// timeout.
+    pub(crate) fn is_expired(&self, now: Instant) -> bool {
+        self.prompted.map_or(false, |prompted| {
+            now.saturating_duration_since(prompted) > self.timeout
+        })
+    }
+
+    /// Looks for the pair's answer in `input` they've sent, ignoring
+    /// any escape sequences sent by their terminal (e.g., responses to
+    /// heartbeats). Input is ignored unless they've been asked.
+    pub(crate) fn feed(&mut self, input: &[u8]) {
+        if !self.is_awaiting() || self.answer.is_some() {
+            return;
+        }
+
+        self.answer = input.iter().find_map(|byte| self.filter.feed(*byte));
+    }
+
+    /// Returns the pair's answer, once they've given one.
+    pub(crate) fn answer(&self) -> Option<u8> {
+        self.answer
+    }
+
+    /// Records that the pair has confirmed the session, and resets the
+    /// activity since the last confirmation.
+    pub(crate) fn confirmed(&mut self, now: Instant) {
+        self.last_confirmed = now;
+        self.output_bytes   = 0;
+        self.input_bytes    = 0;
+        self.prompted       = None;
+        self.answer         = None;
+    }
+
+    /// Describes what has happened since the last confirmation, for
+    /// display to the pair.
+    pub(crate) fn summary(&self, now: Instant) -> String {
+        format!(
+            "{} since last confirmation, {} bytes of output, {} bytes of input",
+            format_duration(now.saturating_duration_since(self.last_confirmed)),
+            self.output_bytes,
+            self.input_bytes,
+        )
+    }
+}
+
+/// Strips terminal escape sequences out of the pair's input, so that
+/// automatic responses from their terminal (e.g., to heartbeats) aren't
+/// mistaken for an answer to the prompt.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EscapeFilter {
+    state: EscapeState,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EscapeState {
+    Text,
+    Escape,
+    Control,
+}
+
+impl EscapeFilter {
+    pub(crate) fn new() -> Self {
+        Self { state: EscapeState::Text }
+    }
+
+    /// Returns `byte` if it's part of the pair's actual input, or
+    /// `None` if it's part of an escape sequence.
+    pub(crate) fn feed(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (EscapeState::Text,    0x1b)        => self.state = EscapeState::Escape,
+            (EscapeState::Text,    _)           => return Some(byte),
+            (EscapeState::Escape,  b'[')        => self.state = EscapeState::Control,
+            (EscapeState::Control, 0x40..=0x7e) |
+            (EscapeState::Escape,  _)           => self.state = EscapeState::Text,
+            (EscapeState::Control, _)           => (),
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL : Duration = Duration::from_secs(3600);
+    const TIMEOUT  : Duration = Duration::from_secs(60);
+
+    #[test]
+    fn due_after_interval() {
+        let start  = Instant::now();
+        let reconf = Reconfirmation::new(INTERVAL, TIMEOUT, start);
+
+        assert!(!reconf.is_due(start));
+        assert!( reconf.is_due(start + INTERVAL));
+    }
+
+    #[test]
+    fn disabled() {
+        let start  = Instant::now();
+        let reconf = Reconfirmation::new(Duration::from_secs(0), TIMEOUT, start);
+
+        assert!(!reconf.is_due(start + INTERVAL * 100));
+    }
+
+    #[test]
+    fn confirmed_resets() {
+        let start      = Instant::now();
+        let mut reconf = Reconfirmation::new(INTERVAL, TIMEOUT, start);
+
+        reconf.record_output(10);
+        reconf.record_input(2);
+        reconf.confirmed(start + INTERVAL);
+
+        assert!(!reconf.is_due(start + INTERVAL));
+        assert_eq!(
+            "0h00m00s since last confirmation, 0 bytes of output, 0 bytes of input",
+            reconf.summary(start + INTERVAL),
+        );
+    }
+
+    #[test]
+    fn answer_read_once_prompted() {
+        let start      = Instant::now();
+        let mut reconf = Reconfirmation::new(INTERVAL, TIMEOUT, start);
+
+        reconf.feed(b"y");
+        assert_eq!(None, reconf.answer());
+
+        reconf.prompted(start + INTERVAL);
+        assert!(!reconf.is_due(start + INTERVAL));
+
+        reconf.feed(b"\x1b[0n");
+        assert_eq!(None, reconf.answer());
+
+        reconf.feed(b"\x1b[0nn");
+        assert_eq!(Some(b'n'), reconf.answer());
+
+        reconf.feed(b"y");
+        assert_eq!(Some(b'n'), reconf.answer());
+    }
+
+    #[test]
+    fn expires_without_answer() {
+        let start      = Instant::now();
+        let mut reconf = Reconfirmation::new(INTERVAL, TIMEOUT, start);
+
+        assert!(!reconf.is_expired(start + INTERVAL * 100));
+
+        reconf.prompted(start + INTERVAL);
+
+        assert!(!reconf.is_expired(start + INTERVAL + TIMEOUT));
+        assert!( reconf.is_expired(start + INTERVAL + TIMEOUT * 2));
+
+        reconf.confirmed(start + INTERVAL + TIMEOUT);
+
+        assert!(!reconf.is_awaiting());
+        assert!(!reconf.is_expired(start + INTERVAL + TIMEOUT * 2));
+    }
+
+    #[test]
+    fn escape_filter() {
+        let mut filter = EscapeFilter::new();
+
+        let input : Vec<u8> = b"\x1b[0n\x1b[0ny\x1b[0n".iter()
+            .filter_map(|b| filter.feed(*b))
+            .collect();
+
+        assert_eq!(b"y", &input[..]);
+    }
+
+    #[test]
+    fn summary() {
+        let start      = Instant::now();
+        let mut reconf = Reconfirmation::new(INTERVAL, TIMEOUT, start);
+
+        reconf.record_output(1024);
+        reconf.record_output(1024);
+        reconf.record_input(7);
+
+        assert_eq!(
+            "1h01m05s since last confirmation, 2048 bytes of output, 7 bytes of input",
+            reconf.summary(start + Duration::from_secs(3665)),
+        );
+    }
+}
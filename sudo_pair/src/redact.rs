@@ -0,0 +1,360 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use regex::bytes::Regex;
+
+/// The text shown in place of anything that has been redacted.
+pub(crate) const MARKER : &[u8] = b"[REDACTED]";
+
+/// The number of bytes of already-emitted output that are kept around
+/// so that a pattern can still match when its sensitive part arrives in
+/// a later buffer than the text that precedes it (e.g., a secret
+/// printed after a `password: ` label).
+const CONTEXT_LEN : usize = 256;
+
+/// The most output that will be held back waiting to see if it's part
+/// of a secret. Without a limit, a command that never prints whitespace
+/// could make us buffer its output indefinitely.
+const MAX_HOLD_LEN : usize = 4096;
+
+/// Masks any text matching a configured set of patterns.
+#[derive(Clone, Debug)]
+pub(crate) struct Redactor {
+    regex: Regex,
+}
+
+impl Redactor {
+    /// Loads patterns from the file at `path`. Each non-empty line that
+    /// isn't a `#` comment is a pattern, and must be prefixed with
+    /// either `literal:` or `regex:`.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(patterns: &str) -> Result<Self> {
+        let mut alternatives = vec![];
+
+        for line in patterns.lines() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            if let Some(literal) = line.strip_prefix("literal:") {
+                alternatives.push(regex::escape(literal));
+            } else if let Some(regex) = line.strip_prefix("regex:") {
+                alternatives.push(format!("(?:{})", regex));
+            } else {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "redaction pattern must start with `literal:` or `regex:`: {}",
+                    line,
+                )));
+            }
+        }
+
+        if alternatives.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "no redaction patterns were provided",
+            ));
+        }
+
+        let regex = Regex::new(&alternatives.join("|")).map_err(|e|
+            Error::new(ErrorKind::InvalidData, e.to_string())
+        )?;
+
+        Ok(Self { regex })
+    }
+
+    /// Returns a copy of `input` with anything matching one of the
+    /// patterns replaced by a marker.
+    pub(crate) fn redact(&self, input: &[u8]) -> Vec<u8> {
+        self.regex.replace_all(input, MARKER).into_owned()
+    }
+}
+
+/// Redacts a stream of output that arrives in arbitrarily-sized pieces.
+///
+/// A secret may be split across two (or more) pieces of output, so the
+/// trailing word of each piece is held back until the next piece (or
+/// whitespace) shows where it ends.
+#[derive(Debug)]
+pub(crate) struct StreamRedactor {
+    redactor: Redactor,
+
+    /// output that has already been emitted, kept so that patterns may
+    /// match against what came before the pending output
+    context: Vec<u8>,
+
+    /// output that has been received but not yet emitted
+    pending: Vec<u8>,
+
+    /// whether the end of the output was part of a match that had to be
+    /// redacted before it was complete, in which case the rest of its
+    /// word is redacted as it arrives
+    redacting: bool,
+}
+
+impl StreamRedactor {
+    pub(crate) fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor,
+            context:   vec![],
+            pending:   vec![],
+            redacting: false,
+        }
+    }
+
+    /// Accepts the next piece of the stream, returning whatever output
+    /// is now known to be safe to emit.
+    pub(crate) fn feed(&mut self, input: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(input);
+
+        let hold = trailing_word_len(&self.pending).min(MAX_HOLD_LEN);
+
+        self.emit(self.pending.len() - hold, false)
+    }
+
+    /// Returns everything that's been held back, redacted. This should
+    /// be called when no more output is expected.
+    pub(crate) fn flush(&mut self) -> Vec<u8> {
+        self.emit(self.pending.len(), true)
+    }
+
+    /// Emits up to `len` bytes of the pending output, redacting any
+    /// matches. Unless this is the `last` of the output, fewer bytes
+    /// will be emitted if that would split a match which might continue
+    /// beyond them. No more than `MAX_HOLD_LEN` bytes are ever held
+    /// back: a match that keeps growing past that is redacted early.
+    fn emit(&mut self, len: usize, last: bool) -> Vec<u8> {
+        // anything continuing a match that was already redacted is
+        // dropped, since the marker has been emitted in its place
+        let len = if self.redacting {
+            let run = self.pending.iter()
+                .take_while(|b| !b.is_ascii_whitespace())
+                .count();
+
+            drop(self.pending.drain(.. run));
+            self.redacting = self.pending.is_empty();
+
+            len.saturating_sub(run)
+        } else {
+            len
+        };
+
+        let offset = self.context.len();
+        let data   = [&self.context[..], &self.pending[..]].concat();
+
+        let matches : Vec<_> = self.redactor.regex.find_iter(&data)
+            .map(|m| (m.start(), m.end()))
+            .filter(|&(_, end)| end > offset)
+            .collect();
+
+        // a match extending past the end of what we're about to emit
+        // may only be a prefix of a longer match, so hold it back too
+        let mut split = offset + len;
+
+        if !last {
+            for &(start, end) in &matches {
+                if end > split || end == data.len() {
+                    split = split.min(start.max(offset));
+                }
+            }
+        }
+
+        // a match can't be held back forever waiting for it to end, so
+        // once too much has been held the whole thing is redacted
+        if !last && data.len() - split > MAX_HOLD_LEN {
+            split          = data.len();
+            self.redacting = matches.last().map_or(false, |&(_, end)| end == data.len());
+        }
+
+        let mut output = Vec::with_capacity(split - offset);
+        let mut cursor = offset;
+
+        for &(start, end) in &matches {
+            let start = start.max(offset);
+
+            if start >= split {
+                break;
+            }
+
+            output.extend_from_slice(&data[cursor .. start]);
+            output.extend_from_slice(MARKER);
+
+            cursor = end.min(split);
+        }
+
+        output.extend_from_slice(&data[cursor .. split]);
+
+        self.pending = data[split ..].to_vec();
+        self.context = data[split.saturating_sub(CONTEXT_LEN) .. split].to_vec();
+
+        output
+    }
+}
+
+/// Returns the length of the run of non-whitespace bytes at the end of
+/// `data`.
+fn trailing_word_len(data: &[u8]) -> usize {
+    data.iter()
+        .rev()
+        .take_while(|b| !b.is_ascii_whitespace())
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS : &str = "
+        # comments and blank lines are ignored
+
+literal:hunter2
+regex:token=[0-9a-f]+
+regex:password: \\S+
+";
+
+    fn redactor() -> Redactor {
+        Redactor::parse(PATTERNS).unwrap()
+    }
+
+    fn stream(pieces: &[&[u8]]) -> Vec<u8> {
+        let mut stream = StreamRedactor::new(redactor());
+        let mut output = vec![];
+
+        for piece in pieces {
+            output.extend(stream.feed(piece));
+        }
+
+        output.extend(stream.flush());
+        output
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(
+            &b"echo [REDACTED]"[..],
+            &redactor().redact(b"echo hunter2")[..],
+        );
+    }
+
+    #[test]
+    fn literal_is_escaped() {
+        let redactor = Redactor::parse("literal:a.b").unwrap();
+
+        assert_eq!(&b"axb [REDACTED]"[..], &redactor.redact(b"axb a.b")[..]);
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(
+            &b"curl -H [REDACTED] [REDACTED]"[..],
+            &redactor().redact(b"curl -H token=deadbeef hunter2")[..],
+        );
+    }
+
+    #[test]
+    fn no_matches() {
+        assert_eq!(&b"ls -l"[..], &redactor().redact(b"ls -l")[..]);
+    }
+
+    #[test]
+    fn invalid_prefix() {
+        assert!(Redactor::parse("hunter2").is_err());
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Redactor::parse("regex:(").is_err());
+    }
+
+    #[test]
+    fn empty() {
+        assert!(Redactor::parse("# nothing here\n").is_err());
+    }
+
+    #[test]
+    fn stream_single_piece() {
+        assert_eq!(
+            &b"the password is [REDACTED]\n"[..],
+            &stream(&[b"the password is hunter2\n"])[..],
+        );
+    }
+
+    #[test]
+    fn stream_split_literal() {
+        assert_eq!(
+            &b"the password is [REDACTED]\n"[..],
+            &stream(&[b"the password is hun", b"ter", b"2\n"])[..],
+        );
+    }
+
+    #[test]
+    fn stream_split_after_label() {
+        // the label was already sent by the time we knew it was part of
+        // a match, but the secret itself is still masked
+        assert_eq!(
+            &b"password: [REDACTED]\n"[..],
+            &stream(&[b"password: ", b"s3cr3t\n"])[..],
+        );
+    }
+
+    #[test]
+    fn stream_split_regex() {
+        assert_eq!(
+            &b"[REDACTED] ok\n"[..],
+            &stream(&[b"token=dead", b"beef", b" ok\n"])[..],
+        );
+    }
+
+    #[test]
+    fn stream_holds_trailing_word() {
+        let mut stream = StreamRedactor::new(redactor());
+
+        assert_eq!(&b"one two "[..], &stream.feed(b"one two thr")[..]);
+        assert_eq!(&b"three\n"[..],  &stream.feed(b"ee\n")[..]);
+        assert!(stream.flush().is_empty());
+    }
+
+    #[test]
+    fn stream_bounded_hold() {
+        let mut stream = StreamRedactor::new(redactor());
+        let     input  = vec![b'x'; MAX_HOLD_LEN * 2];
+
+        assert_eq!(MAX_HOLD_LEN, stream.feed(&input).len());
+    }
+
+    #[test]
+    fn stream_bounded_match() {
+        let mut stream = StreamRedactor::new(redactor());
+        let mut output = stream.feed(b"token=");
+
+        // a secret that never ends is redacted once too much of it has
+        // been held, and so is anything that continues it
+        for _ in 0 .. MAX_HOLD_LEN {
+            output.extend(stream.feed(b"deadbeef"));
+
+            assert!(stream.pending.len() <= MAX_HOLD_LEN);
+        }
+
+        output.extend(stream.feed(b" ok\n"));
+        output.extend(stream.flush());
+
+        assert_eq!(&b"[REDACTED] ok\n"[..], &output[..]);
+    }
+}
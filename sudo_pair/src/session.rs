@@ -0,0 +1,84 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{Read, Result};
+
+/// The source of randomness for new session IDs. This is available on
+/// every platform we support, and unlike `getrandom(2)` doesn't require
+/// us to care about which libc we've been linked against.
+const RANDOM_SOURCE : &str = "/dev/urandom";
+
+/// The number of random bytes in a session ID. This is enough to make
+/// collisions across every session a fleet will ever run vanishingly
+/// unlikely, while keeping the hex-encoded form short enough for a
+/// human to read aloud and for the socket path to stay well under the
+/// `sun_path` length limit.
+const SESSION_ID_LEN : usize = 8;
+
+/// A random identifier for a single `sudo` session.
+///
+/// Unlike the `<uid>.<pid>` pair, this is unique across reboots, so it
+/// can be used to tie together the user's prompt, the pair's prompt,
+/// the socket they connect to, and every log record for the session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct SessionId([u8; SESSION_ID_LEN]);
+
+impl SessionId {
+    pub(crate) fn generate() -> Result<Self> {
+        let mut bytes = [0; SESSION_ID_LEN];
+
+        File::open(RANDOM_SOURCE)?.read_exact(&mut bytes)?;
+
+        Ok(Self(bytes))
+    }
+}
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl slog::Value for SessionId {
+    fn serialize(&self, _: &slog::Record<'_>, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_is_lowercase_hex() {
+        let id = SessionId([0x7f, 0x3a, 0x00, 0x01, 0xab, 0xcd, 0xef, 0x10]);
+
+        assert_eq!("7f3a0001abcdef10", id.to_string());
+    }
+
+    #[test]
+    fn generate_is_random() {
+        let a = SessionId::generate().unwrap();
+        let b = SessionId::generate().unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(SESSION_ID_LEN * 2, a.to_string().len());
+    }
+}
@@ -26,6 +26,7 @@ use std::os::unix::net::{UnixListener, UnixStream};
 use std::mem;
 use std::path::Path;
 use std::ptr;
+use std::time::Duration;
 
 use libc::{self, gid_t, mode_t, uid_t};
 
@@ -128,6 +129,20 @@ impl Socket {
         self.socket.shutdown(Shutdown::Both)
     }
 
+    /// Toggles whether or not reads and writes will block. Once a
+    /// session has been approved, output is written without blocking so
+    /// that a slow pair can't stall the command being run.
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Sets a limit on how long a blocking write may take before it
+    /// fails with an error. A `timeout` of `None` allows writes to block
+    /// indefinitely.
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.socket.set_write_timeout(timeout)
+    }
+
     fn unlink(path: &Path) -> Result<()> {
         match fs::metadata(&path).map(|md| md.file_type().is_socket()) {
             // file exists, is a socket; delete it
@@ -226,12 +241,25 @@ impl Socket {
     }
 }
 
+#[cfg(test)]
+impl From<UnixStream> for Socket {
+    fn from(socket: UnixStream) -> Self {
+        Self { socket }
+    }
+}
+
 impl Drop for Socket {
     fn drop(&mut self) {
         let _ = self.close();
     }
 }
 
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
 impl Read for Socket {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         // read() will block until someone writes on the other side
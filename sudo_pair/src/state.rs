@@ -0,0 +1,130 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Helpers for the state the plugin keeps on disk between sessions
+//! (e.g., cached approvals and pre-approval tickets). Anyone able to
+//! write this state could approve their own sessions, so it must only
+//! ever be trusted if it's owned by root and writable by nobody else.
+
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use std::process;
+
+/// Creates the directory at `path` if it doesn't already exist, and
+/// ensures that it's safe to trust its contents.
+pub(crate) fn create_dir(path: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)?;
+
+    enforce_ownership(path, true)
+}
+
+/// Writes `contents` to a file named `name` in `dir`, replacing any
+/// file already there. The contents are written to a temporary file
+/// and moved into place so that nobody ever reads a partially-written
+/// file.
+pub(crate) fn write_atomically(dir: &Path, name: &str, contents: &[u8]) -> Result<()> {
+    let path = dir.join(name);
+    let temp = dir.join(format!(".{}.{}.tmp", name, process::id()));
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|()| fs::rename(&temp, &path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Ensures that `path` is owned by the user we're running as (which
+/// for `sudo` is root) and that nobody else can write to it.
+pub(crate) fn enforce_ownership(path: &Path, is_dir: bool) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    // SAFETY: `geteuid` is always successful
+    let euid = unsafe { libc::geteuid() };
+
+    if is_dir && !metadata.file_type().is_dir() {
+        return Err(Error::new(ErrorKind::Other, format!(
+            "{} is not a directory",
+            path.to_string_lossy(),
+        )));
+    }
+
+    if !is_dir && !metadata.file_type().is_file() {
+        return Err(Error::new(ErrorKind::Other, format!(
+            "{} is not a regular file",
+            path.to_string_lossy(),
+        )));
+    }
+
+    if metadata.uid() != euid {
+        return Err(Error::new(ErrorKind::Other, format!(
+            "{} is not owned by root",
+            path.to_string_lossy(),
+        )));
+    }
+
+    // group- or world-writable
+    if metadata.mode() & 0o022 != 0 {
+        return Err(Error::new(ErrorKind::Other, format!(
+            "{} has insecure permissions",
+            path.to_string_lossy(),
+        )));
+    }
+
+    Ok(())
+}
+
+/// A directory for tests to keep on-disk state in, named uniquely for
+/// each test process and removed when it's dropped.
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Reserves a directory for the test named `name`. Anything left
+    /// behind by a previous run is removed, but the directory itself
+    /// isn't created.
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "sudo_pair-{}-{}", name, process::id(),
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use std::time::Duration;
+
+/// How the command ended, as reported by `sudo` when the plugin is
+/// closed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Outcome {
+    /// The command exited normally with the given status.
+    Exited(i32),
+
+    /// The command was killed by the given signal.
+    Signaled {
+        signal:      i32,
+        core_dumped: bool,
+    },
+
+    /// The command couldn't be executed at all, failing with the given
+    /// `errno`.
+    ExecFailed(i32),
+}
+
+impl Outcome {
+    /// Interprets the `exit_status` and `error` that `sudo` passes to
+    /// the plugin's `close` callback. If `error` is nonzero, the
+    /// command couldn't be executed and `exit_status` is meaningless;
+    /// otherwise, `exit_status` is a wait status as returned by
+    /// `waitpid(2)`.
+    pub(crate) fn new(exit_status: i32, error: i32) -> Self {
+        if error != 0 {
+            return Self::ExecFailed(error);
+        }
+
+        if libc::WIFSIGNALED(exit_status) {
+            return Self::Signaled {
+                signal:      libc::WTERMSIG(exit_status),
+                core_dumped: libc::WCOREDUMP(exit_status),
+            };
+        }
+
+        Self::Exited(libc::WEXITSTATUS(exit_status))
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match *self {
+            Self::Exited(status) => write!(f, "exited with status {}", status),
+
+            Self::Signaled { signal, core_dumped } => write!(
+                f, "was killed by signal {}{}",
+                signal,
+                if core_dumped { " (core dumped)" } else { "" },
+            ),
+
+            Self::ExecFailed(errno) => write!(
+                f, "couldn't be executed: {}",
+                io::Error::from_raw_os_error(errno),
+            ),
+        }
+    }
+}
+
+/// What happened over the course of a paired session, reported to the
+/// pair and to the logs once the command is done.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Summary {
+    pub(crate) outcome:      Outcome,
+    pub(crate) duration:     Duration,
+    pub(crate) output_bytes: u64,
+    pub(crate) input_bytes:  u64,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f, "command {} after {}, {} bytes of output, {} bytes of input",
+            self.outcome,
+            format_duration(self.duration),
+            self.output_bytes,
+            self.input_bytes,
+        )
+    }
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exited() {
+        assert_eq!(Outcome::Exited(0), Outcome::new(0,      0));
+        assert_eq!(Outcome::Exited(3), Outcome::new(3 << 8, 0));
+    }
+
+    #[test]
+    fn signaled() {
+        assert_eq!(
+            Outcome::Signaled { signal: libc::SIGKILL, core_dumped: false },
+            Outcome::new(libc::SIGKILL, 0),
+        );
+
+        assert_eq!(
+            Outcome::Signaled { signal: libc::SIGSEGV, core_dumped: true },
+            Outcome::new(libc::SIGSEGV | 0x80, 0),
+        );
+    }
+
+    #[test]
+    fn exec_failed() {
+        assert_eq!(Outcome::ExecFailed(libc::ENOENT), Outcome::new(0, libc::ENOENT));
+    }
+
+    #[test]
+    fn summary() {
+        let summary = Summary {
+            outcome:      Outcome::Exited(1),
+            duration:     Duration::from_secs(3665),
+            output_bytes: 2048,
+            input_bytes:  7,
+        };
+
+        assert_eq!(
+            "command exited with status 1 after 1h01m05s, 2048 bytes of output, 7 bytes of input",
+            summary.to_string(),
+        );
+    }
+}
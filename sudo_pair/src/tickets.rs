@@ -0,0 +1,510 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+// these warnings are unavoidable with names like `uid` and `gid`, and
+// such names are natural to use for this problem domain so should not
+// be avoided
+#![allow(clippy::similar_names)]
+
+use crate::state;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::{gid_t, uid_t};
+
+/// A session for which a pre-approval ticket is being sought.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Request<'a> {
+    /// the real `uid` of the user invoking `sudo`
+    pub(crate) requester: uid_t,
+
+    /// the `uid` being sudoed to, if the user is sudoing to a new user
+    pub(crate) runas_uid: Option<uid_t>,
+
+    /// the `gid` being sudoed to, if the user is explicitly sudoing to
+    /// a new group
+    pub(crate) runas_gid: Option<gid_t>,
+
+    /// the full path to the command followed by its arguments, one
+    /// per element
+    pub(crate) command: &'a [&'a [u8]],
+}
+
+/// Approval, issued ahead of time by an approver, for a user to run a
+/// command (or set of commands) within some window of time without a
+/// live pair.
+///
+/// Tickets are stored as files of `key=value` lines, which are written
+/// by the approval command once an approver has proven that they would
+/// be eligible to approve the session live.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Ticket {
+    pub(crate) id:       String,
+    pub(crate) approver: uid_t,
+    pub(crate) uses:     u64,
+
+    requester:  uid_t,
+    runas_uid:  Option<uid_t>,
+    runas_gid:  Option<gid_t>,
+    not_before: SystemTime,
+    not_after:  SystemTime,
+    command:    String,
+}
+
+impl Ticket {
+    fn parse(id: &str, contents: &str) -> Result<Self> {
+        let mut approver   = None;
+        let mut uses       = None;
+        let mut requester  = None;
+        let mut runas_uid  = None;
+        let mut runas_gid  = None;
+        let mut not_before = None;
+        let mut not_after  = None;
+        let mut command    = None;
+        let mut seen       = HashSet::new();
+
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = match line.find('=') {
+                Some(i) => (&line[..i], &line[i + 1 ..]),
+                None    => return Err(invalid(id, line)),
+            };
+
+            // a repeated key could otherwise override what the approver
+            // actually agreed to
+            if !seen.insert(key) {
+                return Err(invalid(id, line));
+            }
+
+            match key {
+                "approver"   => approver   = value.parse().ok(),
+                "uses"       => uses       = value.parse().ok(),
+                "requester"  => requester  = value.parse().ok(),
+                "runas_uid"  => runas_uid  = value.parse().ok(),
+                "runas_gid"  => runas_gid  = value.parse().ok(),
+                "not_before" => not_before = value.parse().ok().map(timestamp),
+                "not_after"  => not_after  = value.parse().ok().map(timestamp),
+                "command"    => command    = Some(value.to_owned()),
+                _            => return Err(invalid(id, line)),
+            }
+        }
+
+        // a ticket must be for exactly one of a user or a group, in the
+        // same way a live session must be
+        if runas_uid.is_some() == runas_gid.is_some() {
+            return Err(invalid(id, "exactly one of runas_uid or runas_gid"));
+        }
+
+        Ok(Self {
+            id:         id.to_owned(),
+            approver:   approver  .ok_or_else(|| invalid(id, "approver"))?,
+            uses:       uses      .ok_or_else(|| invalid(id, "uses"))?,
+            requester:  requester .ok_or_else(|| invalid(id, "requester"))?,
+            runas_uid,
+            runas_gid,
+            not_before: not_before.ok_or_else(|| invalid(id, "not_before"))?,
+            not_after:  not_after .ok_or_else(|| invalid(id, "not_after"))?,
+            command:    command   .ok_or_else(|| invalid(id, "command"))?,
+        })
+    }
+
+    fn serialize(&self) -> String {
+        let mut ticket = String::new();
+
+        // writing to a `String` can't fail
+        let _ = writeln!(ticket, "approver={}",  self.approver);
+        let _ = writeln!(ticket, "uses={}",      self.uses);
+        let _ = writeln!(ticket, "requester={}", self.requester);
+
+        if let Some(uid) = self.runas_uid {
+            let _ = writeln!(ticket, "runas_uid={}", uid);
+        }
+
+        if let Some(gid) = self.runas_gid {
+            let _ = writeln!(ticket, "runas_gid={}", gid);
+        }
+
+        let _ = writeln!(ticket, "not_before={}", seconds(self.not_before));
+        let _ = writeln!(ticket, "not_after={}",  seconds(self.not_after));
+        let _ = writeln!(ticket, "command={}",    self.command);
+
+        ticket
+    }
+
+    /// Returns true if this ticket allows `request` to be run at `now`.
+    /// Users may never approve their own sessions, so a ticket whose
+    /// approver is also its requester permits nothing.
+    fn permits(&self, request: &Request<'_>, now: SystemTime) -> bool {
+        self.uses > 0                         &&
+        self.approver  != self.requester      &&
+        self.requester == request.requester   &&
+        self.runas_uid == request.runas_uid   &&
+        self.runas_gid == request.runas_gid   &&
+        self.not_before <= now                &&
+        now <= self.not_after                 &&
+        matches_command(&self.command, request.command)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.uses == 0 || now > self.not_after
+    }
+}
+
+/// The set of outstanding pre-approval tickets, stored one per file in
+/// `dir`.
+#[derive(Clone, Debug)]
+pub(crate) struct TicketStore {
+    dir: PathBuf,
+}
+
+impl TicketStore {
+    pub(crate) fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Finds a ticket permitting `request` and uses it up, returning
+    /// the ticket with the number of uses it has left.
+    ///
+    /// A ticket is claimed by renaming it before it's used, so that two
+    /// concurrent sessions can never both spend its last use. While one
+    /// session holds the claim, others won't see the ticket and will
+    /// fall back to asking for a live pair.
+    ///
+    /// Tickets that can't be parsed are ignored, and tickets that have
+    /// expired are removed.
+    pub(crate) fn consume(&self, request: &Request<'_>, now: SystemTime) -> Result<Option<Ticket>> {
+        if !self.dir.exists() {
+            return Ok(None);
+        }
+
+        state::enforce_ownership(&self.dir, true)?;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+
+            // temporary files and claims are dotted
+            let id = match name.to_str() {
+                Some(id) if !id.contains('.') => id,
+                _                             => continue,
+            };
+
+            let ticket = match self.read(id) {
+                Ok(ticket) => ticket,
+                Err(_)     => continue,
+            };
+
+            if ticket.is_expired(now) {
+                let _ = fs::remove_file(self.dir.join(id));
+                continue;
+            }
+
+            if !ticket.permits(request, now) {
+                continue;
+            }
+
+            let claim = format!(".{}.{}.claim", id, process::id());
+
+            // somebody else got to it first
+            if fs::rename(self.dir.join(id), self.dir.join(&claim)).is_err() {
+                continue;
+            }
+
+            let result = self.spend(&claim, id, request, now);
+            let _      = fs::remove_file(self.dir.join(&claim));
+
+            if let Some(ticket) = result? {
+                return Ok(Some(ticket));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Spends one use of the ticket that has been claimed under the
+    /// name `claim`, returning the remainder to the store as `id` if
+    /// there are uses left.
+    fn spend(&self, claim: &str, id: &str, request: &Request<'_>, now: SystemTime) -> Result<Option<Ticket>> {
+        // the ticket is read again now that we hold the claim, since
+        // it may have been spent in between
+        let mut ticket = self.read(claim)?;
+
+        if !ticket.permits(request, now) {
+            return Ok(None);
+        }
+
+        id.clone_into(&mut ticket.id);
+        ticket.uses -= 1;
+
+        if ticket.uses > 0 {
+            state::write_atomically(&self.dir, id, ticket.serialize().as_bytes())?;
+        }
+
+        Ok(Some(ticket))
+    }
+
+    fn read(&self, name: &str) -> Result<Ticket> {
+        let path = self.dir.join(name);
+
+        state::enforce_ownership(&path, false)?;
+
+        Ticket::parse(name, &fs::read_to_string(&path)?)
+    }
+}
+
+/// Returns true if `command` matches `pattern`. The pattern is split on
+/// spaces into words, each of which is matched against exactly one
+/// argument of the command (with the full path to the command itself
+/// first), so the two must have the same number of words and arguments.
+///
+/// A `*` never matches across arguments, which means an argument that
+/// itself contains spaces can only be matched by a `*`. Otherwise, a
+/// pattern like `/bin/echo * b` would permit `/bin/echo 'a b'`.
+fn matches_command(pattern: &str, command: &[&[u8]]) -> bool {
+    pattern.split(' ').count() == command.len() &&
+    pattern.split(' ').zip(command).all(|(word, arg)|
+        matches(word.as_bytes(), arg)
+    )
+}
+
+/// Returns true if `text` matches `pattern`, where a `*` in `pattern`
+/// matches any sequence of bytes (including none) and every other byte
+/// matches only itself.
+fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+
+    // the position of the last `*` seen, and the position in the text
+    // it's currently matched up to
+    let mut star : Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p   += 1;
+            },
+
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+
+            // backtrack, letting the last `*` swallow one more byte
+            _ => match star {
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p    = sp + 1;
+                    t    = st + 1;
+                },
+
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn timestamp(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn invalid(id: &str, detail: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!(
+        "invalid pre-approval ticket {}: {}",
+        id,
+        detail,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::TempDir;
+
+    const TICKET : &str = "\
+approver=1001
+uses=2
+requester=1000
+runas_uid=0
+not_before=1000
+not_after=2000
+command=/usr/bin/systemctl restart *
+";
+
+    fn request<'a>(command: &'a [&'a [u8]]) -> Request<'a> {
+        Request {
+            requester: 1000,
+            runas_uid: Some(0),
+            runas_gid: None,
+            command,
+        }
+    }
+
+    fn store(name: &str) -> (TempDir, TicketStore) {
+        let dir = TempDir::new(name);
+
+        state::create_dir(dir.path()).unwrap();
+        state::write_atomically(dir.path(), "abc123", TICKET.as_bytes()).unwrap();
+
+        let store = TicketStore::new(dir.path());
+
+        (dir, store)
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let ticket = Ticket::parse("abc123", TICKET).unwrap();
+
+        assert_eq!(1001, ticket.approver);
+        assert_eq!(2,    ticket.uses);
+        assert_eq!(TICKET, ticket.serialize());
+    }
+
+    #[test]
+    fn parse_requires_one_runas() {
+        assert!(Ticket::parse("x", &TICKET.replace("runas_uid=0\n", "")).is_err());
+        assert!(Ticket::parse("x", &TICKET.replace("runas_uid=0\n", "runas_uid=0\nrunas_gid=0\n")).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        assert!(Ticket::parse("x", &format!("{}uses_left=9\n", TICKET)).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_repeated_keys() {
+        assert!(Ticket::parse("x", &format!("{TICKET}uses=9\n")).is_err());
+        assert!(Ticket::parse("x", &format!("{TICKET}requester=0\n")).is_err());
+        assert!(Ticket::parse("x", &format!("{TICKET}command=*\n")).is_err());
+        assert!(Ticket::parse("x", &TICKET.replace("uses=2", "uses=x\nuses=2")).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_numbers() {
+        assert!(Ticket::parse("x", &TICKET.replace("uses=2",          "uses=-1")).is_err());
+        assert!(Ticket::parse("x", &TICKET.replace("not_before=1000", "not_before=now")).is_err());
+        assert!(Ticket::parse("x", &TICKET.replace("not_after=2000",  "not_after=")).is_err());
+    }
+
+    #[test]
+    fn permits() {
+        let ticket = Ticket::parse("abc123", TICKET).unwrap();
+        let now    = timestamp(1500);
+
+        assert!( ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]), now));
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl", b"stop", b"nginx"]),    now));
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]), timestamp(999)));
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]), timestamp(2001)));
+
+        assert!(!ticket.permits(&Request {
+            runas_uid: Some(1),
+            ..request(&[b"/usr/bin/systemctl", b"restart", b"nginx"])
+        }, now));
+    }
+
+    #[test]
+    fn self_approved_permits_nothing() {
+        let ticket = Ticket::parse("x", &TICKET.replace("approver=1001", "approver=1000")).unwrap();
+
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]), timestamp(1500)));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!( matches(b"*",         b""));
+        assert!( matches(b"*",         b"anything at all"));
+        assert!( matches(b"/bin/ls *", b"/bin/ls -l /tmp"));
+        assert!( matches(b"a*b*c",     b"aXbYbZc"));
+        assert!(!matches(b"a*b*c",     b"aXbYbZ"));
+        assert!(!matches(b"/bin/ls",   b"/bin/ls -l"));
+        assert!(!matches(b"/bin/ls *", b"/bin/lsof"));
+    }
+
+    #[test]
+    fn wildcards_match_one_argument_at_a_time() {
+        assert!( matches_command("/bin/ls *",   &[b"/bin/ls", b"-l"]));
+        assert!( matches_command("/bin/ls *",   &[b"/bin/ls", b""]));
+        assert!( matches_command("/bin/echo *", &[b"/bin/echo", b"a b"]));
+        assert!(!matches_command("/bin/ls *",   &[b"/bin/ls", b"-l", b"/tmp"]));
+        assert!(!matches_command("/bin/ls *",   &[b"/bin/ls"]));
+        assert!(!matches_command("/bin/ls -l",  &[b"/bin/ls -l"]));
+    }
+
+    #[test]
+    fn arguments_with_spaces_are_not_split() {
+        let ticket = Ticket::parse("abc123", TICKET).unwrap();
+        let now    = timestamp(1500);
+
+        assert!( ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx php-fpm"]), now));
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl restart", b"nginx"]),            now));
+
+        let ticket = Ticket::parse("x", &TICKET.replace("restart *", "restart * now")).unwrap();
+
+        assert!( ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx", b"now"]), now));
+        assert!(!ticket.permits(&request(&[b"/usr/bin/systemctl", b"restart", b"nginx now"]),    now));
+    }
+
+    #[test]
+    fn consume_spends_uses() {
+        let (_dir, store) = store("tickets-consume");
+
+        let request = request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]);
+        let now     = timestamp(1500);
+
+        assert_eq!(1, store.consume(&request, now).unwrap().unwrap().uses);
+        assert_eq!(0, store.consume(&request, now).unwrap().unwrap().uses);
+        assert_eq!(None, store.consume(&request, now).unwrap());
+    }
+
+    #[test]
+    fn consume_ignores_unpermitted() {
+        let (_dir, store) = store("tickets-unpermitted");
+
+        let now = timestamp(1500);
+
+        assert_eq!(None, store.consume(&request(&[b"/bin/sh"]), now).unwrap());
+        assert!(store.dir.join("abc123").exists());
+    }
+
+    #[test]
+    fn consume_refuses_self_approved() {
+        let (_dir, store) = store("tickets-self-approved");
+
+        let request = request(&[b"/usr/bin/systemctl", b"restart", b"nginx"]);
+        let ticket  = TICKET.replace("approver=1001", "approver=1000");
+
+        state::write_atomically(&store.dir, "abc123", ticket.as_bytes()).unwrap();
+
+        assert_eq!(None, store.consume(&request, timestamp(1500)).unwrap());
+    }
+
+    #[test]
+    fn consume_removes_expired() {
+        let (_dir, store) = store("tickets-expired");
+
+        assert_eq!(None, store.consume(&request(&[b"/bin/sh"]), timestamp(2001)).unwrap());
+        assert!(!store.dir.join("abc123").exists());
+    }
+}
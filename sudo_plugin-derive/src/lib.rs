@@ -0,0 +1,345 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Derive macros for the [`sudo_plugin`](https://docs.rs/sudo_plugin)
+//! crate. These are re-exported by `sudo_plugin` when its `derive`
+//! feature is enabled, and shouldn't need to be depended upon directly.
+
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2021_compatibility)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(missing_docs)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unreachable_pub)]
+#![warn(unsafe_code)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+#![warn(variant_size_differences)]
+
+#![warn(rustdoc::all)]
+
+#![warn(clippy::cargo)]
+#![warn(clippy::complexity)]
+#![warn(clippy::correctness)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::perf)]
+#![warn(clippy::style)]
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse_macro_input,
+    Data, DeriveInput, Error, Expr, Field, Fields, GenericArgument, Lit,
+    Meta, NestedMeta, Path, PathArguments, Result, Type,
+};
+
+/// Derives `sudo_plugin::options::SudoOptions` for a struct with named
+/// fields, parsing each field from the plugin's `OptionMap` with
+/// `FromSudoOption`.
+///
+/// Each field is read from the option with the same name, and may be
+/// configured with `#[sudo_option(...)]` attributes:
+///
+/// * `key = "name"` reads the field from the option `name` instead.
+/// * `required` reports an error if the option isn't provided.
+/// * `default = "expr"` uses `expr` for the field if the option isn't
+///   provided. Otherwise, missing options use the field type's
+///   `Default` implementation (so `Option` fields are `None`).
+/// * `separator = ':'` parses a `Vec` or `HashSet` field by splitting
+///   the value on the given character rather than the element type's
+///   `FromSudoOptionList::SEPARATOR`.
+/// * `validate = "path::to::fn"` rejects any value for which the
+///   function, given a reference to the parsed value, returns `false`.
+///   `sudo_plugin::options::is_nonzero` is provided for the common case.
+///
+/// Every option that is missing or invalid is reported together in a
+/// single `Error::OptionsInvalid`.
+#[proc_macro_derive(SudoOptions, attributes(sudo_option))]
+pub fn derive_sudo_options(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// How a field is populated when its option isn't provided.
+enum Fallback {
+    Trait,
+    Expr(Box<Expr>),
+    Required,
+}
+
+/// The configuration of a single field, from its `#[sudo_option]`
+/// attributes.
+struct FieldOptions {
+    key:       String,
+    default:   Fallback,
+    separator: Option<char>,
+    validate:  Option<Path>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let Data::Struct(ref data) = input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "SudoOptions can only be derived for structs",
+        ));
+    };
+
+    let Fields::Named(ref fields) = data.fields else {
+        return Err(Error::new(
+            Span::call_site(),
+            "SudoOptions can only be derived for structs with named fields",
+        ));
+    };
+
+    let fields = &fields.named;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut parsers = Vec::with_capacity(fields.len());
+    let mut values  = Vec::with_capacity(fields.len());
+
+    for field in fields {
+        let (parser, value) = expand_field(field)?;
+
+        parsers.push(parser);
+        values .push(value);
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::sudo_plugin::options::SudoOptions for #name #ty_generics #where_clause {
+            fn from_option_map(
+                map: &::sudo_plugin::options::OptionMap,
+            ) -> ::std::result::Result<Self, ::sudo_plugin::errors::Error> {
+                let mut errors = ::std::vec::Vec::new();
+
+                #( #parsers )*
+
+                if !errors.is_empty() {
+                    return ::std::result::Result::Err(
+                        ::sudo_plugin::errors::Error::OptionsInvalid(errors)
+                    );
+                }
+
+                ::std::result::Result::Ok(Self {
+                    #( #values, )*
+                })
+            }
+        }
+    })
+}
+
+/// Returns the statement that parses a field's option into a local
+/// variable, and the field initializer that builds the field from it.
+fn expand_field(field: &Field) -> Result<(TokenStream2, TokenStream2)> {
+    let ident   = field.ident.as_ref().expect("named fields have names");
+    let options = FieldOptions::parse(field)?;
+    let inner   = option_inner(&field.ty);
+    let ty      = inner.unwrap_or(&field.ty);
+    let key     = &options.key;
+
+    let required = matches!(options.default, Fallback::Required);
+
+    let parse = if let Some(separator) = options.separator {
+        quote! {
+            ::sudo_plugin::options::sudo_options::parse_list::<#ty>(value, #separator)
+        }
+    } else {
+        quote! {
+            ::sudo_plugin::options::FromSudoOption::from_sudo_option(value).ok()
+        }
+    };
+
+    let validate = options.validate.map(|validate| quote! {
+        .filter(|value| #validate(value))
+    });
+
+    let parser = quote! {
+        let #ident = ::sudo_plugin::options::sudo_options::parse(
+            map, #key, #required, &mut errors, |value| {
+                let value : ::std::option::Option<#ty> = #parse;
+
+                value #validate
+            },
+        );
+    };
+
+    let wrap = inner.map(|_| quote! {
+        .map(::std::option::Option::Some)
+    });
+
+    let value = match options.default {
+        Fallback::Trait => quote! {
+            #ident: #ident #wrap .unwrap_or_default()
+        },
+
+        Fallback::Expr(default) => quote! {
+            #ident: #ident #wrap .unwrap_or_else(|| #default)
+        },
+
+        // missing required options were reported as errors above, so
+        // this can't be reached without a value
+        Fallback::Required => quote! {
+            #ident: match #ident #wrap {
+                ::std::option::Option::Some(value) => value,
+                ::std::option::Option::None        => ::std::unreachable!(),
+            }
+        },
+    };
+
+    Ok((parser, value))
+}
+
+impl FieldOptions {
+    fn parse(field: &Field) -> Result<Self> {
+        let ident = field.ident.as_ref().expect("named fields have names");
+        let key   = ident.to_string();
+
+        let mut options = Self {
+            key:       key.trim_start_matches("r#").into(),
+            default:   Fallback::Trait,
+            separator: None,
+            validate:  None,
+        };
+
+        let mut required = None;
+        let mut default  = None;
+
+        for attr in &field.attrs {
+            if !attr.path.is_ident("sudo_option") {
+                continue;
+            }
+
+            let meta_list = match attr.parse_meta()? {
+                Meta::List(meta_list) => meta_list,
+                meta                  => return Err(Error::new_spanned(
+                    meta, "expected #[sudo_option(...)]",
+                )),
+            };
+
+            for nested in meta_list.nested {
+                let meta = match nested {
+                    NestedMeta::Meta(meta) => meta,
+                    NestedMeta::Lit(lit)   => return Err(Error::new_spanned(
+                        lit, "expected an option, not a literal",
+                    )),
+                };
+
+                match meta {
+                    Meta::Path(ref path) if path.is_ident("required") => {
+                        required = Some(path.clone());
+                    },
+
+                    Meta::Path(ref path) if path.is_ident("default") => {
+                        default = Some((path.clone(), Fallback::Trait));
+                    },
+
+                    Meta::NameValue(ref nv) if nv.path.is_ident("key") => {
+                        options.key = match nv.lit {
+                            Lit::Str(ref s) => s.value(),
+                            ref lit         => return Err(Error::new_spanned(
+                                lit, "expected the key as a string",
+                            )),
+                        };
+                    },
+
+                    Meta::NameValue(ref nv) if nv.path.is_ident("default") => {
+                        let expr = match nv.lit {
+                            Lit::Str(ref s) => Box::new(s.parse()?),
+                            ref lit         => return Err(Error::new_spanned(
+                                lit, "expected the default as a string containing an expression",
+                            )),
+                        };
+
+                        default = Some((nv.path.clone(), Fallback::Expr(expr)));
+                    },
+
+                    Meta::NameValue(ref nv) if nv.path.is_ident("separator") => {
+                        options.separator = match nv.lit {
+                            Lit::Char(ref c) => Some(c.value()),
+                            ref lit          => return Err(Error::new_spanned(
+                                lit, "expected the separator as a character",
+                            )),
+                        };
+                    },
+
+                    Meta::NameValue(ref nv) if nv.path.is_ident("validate") => {
+                        options.validate = match nv.lit {
+                            Lit::Str(ref s) => Some(s.parse()?),
+                            ref lit         => return Err(Error::new_spanned(
+                                lit, "expected the validator as a string containing a path",
+                            )),
+                        };
+                    },
+
+                    meta => return Err(Error::new_spanned(
+                        meta, "unrecognized sudo_option",
+                    )),
+                }
+            }
+        }
+
+        options.default = match (required, default) {
+            (Some(_), Some((path, _))) => return Err(Error::new_spanned(
+                path, "a required option can't have a default",
+            )),
+
+            (Some(_), None)            => Fallback::Required,
+            (None, Some((_, default))) => default,
+            (None, None)               => Fallback::Trait,
+        };
+
+        Ok(options)
+    }
+}
+
+/// Returns `T` if `ty` is (syntactically) an `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let path = match *ty {
+        Type::Path(ref ty) if ty.qself.is_none() => &ty.path,
+        _                                        => return None,
+    };
+
+    let segment = path.segments.last()?;
+
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    let args = match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => &args.args,
+        _                                       => return None,
+    };
+
+    match args.first() {
+        Some(GenericArgument::Type(ref ty)) if args.len() == 1 => Some(ty),
+        _                                                      => None,
+    }
+}
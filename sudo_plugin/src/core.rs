@@ -21,12 +21,22 @@
 #![allow(clippy::must_use_candidate)]
 
 use crate::errors::{Error, SudoError};
+use crate::hooks::Hooks;
 use crate::output::PrintFacility;
+use crate::options::{CommandInfo, CStringArray, OptionMap};
+use crate::plugin::{ApprovalEnv, ApprovalPlugin};
+use crate::plugin::{AuditEnv, AuditPlugin, CloseStatus};
 use crate::plugin::{IoEnv, IoPlugin, IoState};
+use crate::plugin::{PluginState, PolicyEnv, PolicyOutput, PolicyPlugin};
 use crate::sys;
+use crate::version::{Capability, Version};
 
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
 use std::os::raw;
 use std::path::PathBuf;
+use std::slice;
 use std::panic::{catch_unwind, UnwindSafe};
 
 /// Return codes understood by the `io_plugin.open` callback.
@@ -83,6 +93,31 @@ pub enum LogStatus {
     Disable = -1,
 }
 
+/// Return codes understood by the `approval_plugin.check` callback, as
+/// described in the [`sudo_plugin(8)` manpage][manpage]. The same codes
+/// are understood by the `policy_plugin.check_policy`, `list`,
+/// `validate`, and `init_session` callbacks.
+///
+/// [manpage]: https://www.sudo.ws/man/1.9.0/sudo_plugin.man.html
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum CheckStatus {
+    /// The command has been approved and may be run.
+    Approve = 1,
+
+    /// The command has been rejected and must not be run.
+    Reject = 0,
+
+    /// The plugin encountered an error while deciding whether or not to
+    /// approve the command. The command will not be run.
+    Error = -1,
+
+    /// The `sudo` command was invoked incorrectly and will be
+    /// terminated. Basic usage information will be presented to the
+    /// user.
+    Usage = -2,
+}
+
 impl<T, E: SudoError> From<Result<T, E>> for OpenStatus {
     fn from(result: Result<T, E>) -> Self {
         match result {
@@ -101,6 +136,15 @@ impl<T, E: SudoError> From<Result<T, E>> for LogStatus {
     }
 }
 
+impl<T, E: Into<CheckStatus>> From<Result<T, E>> for CheckStatus {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(_)  => CheckStatus::Approve,
+            Err(e) => e.into(),
+        }
+    }
+}
+
 impl<T: Into<OpenStatus>> From<std::thread::Result<T>> for OpenStatus {
     fn from(result: std::thread::Result<T>) -> Self {
         match result {
@@ -119,6 +163,15 @@ impl<T: Into<LogStatus>> From<std::thread::Result<T>> for LogStatus {
     }
 }
 
+impl<T: Into<CheckStatus>> From<std::thread::Result<T>> for CheckStatus {
+    fn from(result: std::thread::Result<T>) -> Self {
+        match result {
+            Ok(v)  => v.into(),
+            Err(_) => Error::UncaughtPanic.into(),
+        }
+    }
+}
+
 fn catch_unwind_open<T: std::fmt::Debug + Into<OpenStatus>, F: FnOnce() -> T + UnwindSafe>(f: F) -> i32 {
     Into::<OpenStatus>::into(catch_unwind(f)) as _
 }
@@ -127,6 +180,87 @@ fn catch_unwind_log<T: Into<LogStatus>, F: FnOnce() -> T + UnwindSafe>(f: F) ->
     Into::<LogStatus>::into(catch_unwind(f)) as _
 }
 
+fn catch_unwind_check<T: Into<CheckStatus>, F: FnOnce() -> T + UnwindSafe>(f: F) -> i32 {
+    Into::<CheckStatus>::into(catch_unwind(f)) as _
+}
+
+/// Collects an `argc`-length array of C strings into owned `CString`s.
+/// A NULL array or a negative count is treated as empty.
+///
+/// # Safety
+///
+/// The array must contain at least `argc` entries, each of which must
+/// point to a valid NUL-terminated string.
+unsafe fn cstrings_from_argv(argc: raw::c_int, argv: *const *mut raw::c_char) -> Vec<CString> {
+    let argc = usize::try_from(argc).unwrap_or(0);
+
+    if argv.is_null() {
+        return vec![];
+    }
+
+    slice::from_raw_parts(argv, argc)
+        .iter()
+        .map(|ptr| CStr::from_ptr(*ptr).to_owned())
+        .collect()
+}
+
+/// Collects a NULL-terminated array of C strings (e.g., an `argv`) into
+/// owned `CString`s. A NULL array is treated as empty.
+///
+/// # Safety
+///
+/// The array must be terminated by a NULL pointer, and each entry
+/// before it must point to a valid NUL-terminated string.
+pub(crate) unsafe fn cstrings_from_raw(mut ptr: *const *mut raw::c_char) -> Vec<CString> {
+    let mut strings = vec![];
+
+    if ptr.is_null() {
+        return strings;
+    }
+
+    while !(*ptr).is_null() {
+        strings.push(CStr::from_ptr(*ptr).to_owned());
+        ptr = ptr.add(1);
+    }
+
+    strings
+}
+
+/// Converts a C string into a Rust string, replacing any invalid UTF-8.
+/// A NULL pointer is treated as an empty string.
+///
+/// # Safety
+///
+/// The pointer must either be NULL or point to a valid NUL-terminated
+/// string.
+unsafe fn str_from_raw<'a>(ptr: *const raw::c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        return Cow::Borrowed("");
+    }
+
+    CStr::from_ptr(ptr).to_string_lossy()
+}
+
+/// Reports an error to `sudo` through a callback's `errstr`
+/// out-parameter, so that it can be shown to the user and logged in
+/// `sudo`'s own audit trail. This is only done for versions of the
+/// plugin API that support [`Capability::Errstr`].
+///
+/// # Safety
+///
+/// The pointer must either be NULL or point to writable memory.
+unsafe fn write_errstr<E: SudoError>(
+    errstr:  *mut *const raw::c_char,
+    version: Version,
+    error:   &E,
+) {
+    if errstr.is_null() || !version.supports(Capability::Errstr) {
+        return;
+    }
+
+    *errstr = error.errstr().as_ptr();
+}
+
 #[doc(hidden)]
 pub unsafe extern "C" fn open<P: IoPlugin, S: IoState<P>>(
     version:                        raw::c_uint,
@@ -139,7 +273,7 @@ pub unsafe extern "C" fn open<P: IoPlugin, S: IoState<P>>(
     argv:               *const *mut raw::c_char,
     user_env_ptr:       *const *mut raw::c_char,
     plugin_options_ptr: *const *mut raw::c_char,
-    _errstr:            *mut *const raw::c_char,
+    errstr:             *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_open(|| {
         // create our own PrintFacility to log to in case IoEnv
@@ -163,25 +297,35 @@ pub unsafe extern "C" fn open<P: IoPlugin, S: IoState<P>>(
         );
 
         let io_env = match io_env {
-            Ok(v)   => v,
-            Err(e)  => {
+            Ok(mut v) => {
+                // older versions of `sudo` never fill in `event_alloc`,
+                // so there's nothing to be found there
+                if v.api_version.supports(Capability::EventAlloc) {
+                    v.event_alloc = S::event_alloc();
+                }
+
+                v
+            },
+            Err(e)    => {
                 let _ = stderr.write_error(&e);
                 let e : P::Error = e.into();
+                write_errstr(errstr, Version::from(version), &e);
                 return Into::<OpenStatus>::into(e);
             }
         };
 
-        S::init(io_env, |env| {
+        S::storage().init(io_env, |env| {
             // even though we're avoiding instantiating the plugin
-            // fully, we need to make sure it makes its way into static
-            // storage before returning, which is why we put this check
-            // inside `S::init`
+            // fully, we need to make sure the environment makes its way
+            // into storage before returning, which is why we put this
+            // check inside `init`
             if env.command_info.command == PathBuf::default() {
-                return Err(OpenStatus::Ok);
+                return Ok(None);
             }
 
-            P::open(env).map_err(|e| {
+            P::open(env).map(Some).map_err(|e| {
                 let _ = stderr.write_error(&e);
+                write_errstr(errstr, Version::from(version), &e);
                 Into::<OpenStatus>::into(e)
             })
         })
@@ -194,7 +338,13 @@ pub unsafe extern "C" fn close<P: IoPlugin, S: IoState<P>>(
     error:       raw::c_int,
 ) {
     drop(catch_unwind(|| {
-        S::drop(|plugin| plugin.close(exit_status, error));
+        let hooks = S::storage()
+            .close(|plugin| plugin.close(exit_status, error))
+            .flatten();
+
+        if let Some(hooks) = hooks {
+            hooks.release();
+        }
     }));
 }
 
@@ -203,7 +353,11 @@ pub unsafe extern "C" fn show_version<P: IoPlugin, S: IoState<P>>(
     verbose: raw::c_int,
 ) -> raw::c_int {
     catch_unwind_open(|| {
-        P::show_version(S::io_env(), verbose != 0);
+        let Some(env) = S::storage().env() else {
+            return Into::<OpenStatus>::into(P::Error::from(Error::Uninitialized));
+        };
+
+        P::show_version(&env, verbose != 0);
 
         OpenStatus::Ok
     })
@@ -213,25 +367,25 @@ pub unsafe extern "C" fn show_version<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn log_ttyin<P: IoPlugin, S: IoState<P>>(
     buf:          *const raw::c_char,
     len:                 raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
-
-        if !env.command_info.iolog_ttyin && !P::IGNORE_IOLOG_HINTS {
-            return Ok(());
-        }
+        S::storage().with(|env, plugin| {
+            if !env.command_info.iolog_ttyin && !P::IGNORE_IOLOG_HINTS {
+                return Ok(());
+            }
 
-        let slice = ::std::slice::from_raw_parts(
-            buf.cast(),
-            len as _,
-        );
+            let slice = ::std::slice::from_raw_parts(
+                buf.cast(),
+                len as _,
+            );
 
-        plugin.log_ttyin(slice).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
-        })
+            plugin.log_ttyin(slice).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
     })
 }
 
@@ -239,25 +393,25 @@ pub unsafe extern "C" fn log_ttyin<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn log_ttyout<P: IoPlugin, S: IoState<P>>(
     buf:          *const raw::c_char,
     len:                 raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
-
-        if !env.command_info.iolog_ttyout && !P::IGNORE_IOLOG_HINTS {
-            return Ok(());
-        }
+        S::storage().with(|env, plugin| {
+            if !env.command_info.iolog_ttyout && !P::IGNORE_IOLOG_HINTS {
+                return Ok(());
+            }
 
-        let slice = ::std::slice::from_raw_parts(
-            buf.cast(),
-            len as _,
-        );
+            let slice = ::std::slice::from_raw_parts(
+                buf.cast(),
+                len as _,
+            );
 
-        plugin.log_ttyout(slice).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
-        })
+            plugin.log_ttyout(slice).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
     })
 }
 
@@ -265,25 +419,25 @@ pub unsafe extern "C" fn log_ttyout<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn log_stdin<P: IoPlugin, S: IoState<P>>(
     buf:          *const raw::c_char,
     len:                 raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
-
-        if !env.command_info.iolog_stdin && !P::IGNORE_IOLOG_HINTS {
-            return Ok(());
-        }
+        S::storage().with(|env, plugin| {
+            if !env.command_info.iolog_stdin && !P::IGNORE_IOLOG_HINTS {
+                return Ok(());
+            }
 
-        let slice = ::std::slice::from_raw_parts(
-            buf.cast(),
-            len as _,
-        );
+            let slice = ::std::slice::from_raw_parts(
+                buf.cast(),
+                len as _,
+            );
 
-        plugin.log_stdin(slice).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
-        })
+            plugin.log_stdin(slice).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
     })
 }
 
@@ -291,25 +445,25 @@ pub unsafe extern "C" fn log_stdin<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn log_stdout<P: IoPlugin, S: IoState<P>>(
     buf:          *const raw::c_char,
     len:                 raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
-
-        if !env.command_info.iolog_stdout && !P::IGNORE_IOLOG_HINTS {
-            return Ok(());
-        }
+        S::storage().with(|env, plugin| {
+            if !env.command_info.iolog_stdout && !P::IGNORE_IOLOG_HINTS {
+                return Ok(());
+            }
 
-        let slice = ::std::slice::from_raw_parts(
-            buf.cast(),
-            len as _,
-        );
+            let slice = ::std::slice::from_raw_parts(
+                buf.cast(),
+                len as _,
+            );
 
-        plugin.log_stdout(slice).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
-        })
+            plugin.log_stdout(slice).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
     })
 }
 
@@ -317,25 +471,25 @@ pub unsafe extern "C" fn log_stdout<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn log_stderr<P: IoPlugin, S: IoState<P>>(
     buf:          *const raw::c_char,
     len:                 raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
+        S::storage().with(|env, plugin| {
+            if !env.command_info.iolog_stderr && !P::IGNORE_IOLOG_HINTS {
+                return Ok(());
+            }
 
-        if !env.command_info.iolog_stderr && !P::IGNORE_IOLOG_HINTS {
-            return Ok(());
-        }
+            let slice = ::std::slice::from_raw_parts(
+                buf.cast(),
+                len as _,
+            );
 
-        let slice = ::std::slice::from_raw_parts(
-            buf.cast(),
-            len as _,
-        );
-
-        plugin.log_stderr(slice).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
-        })
+            plugin.log_stderr(slice).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
     })
 }
 
@@ -343,15 +497,574 @@ pub unsafe extern "C" fn log_stderr<P: IoPlugin, S: IoState<P>>(
 pub unsafe extern "C" fn change_winsize<P: IoPlugin, S: IoState<P>>(
     line:                raw::c_uint,
     cols:                raw::c_uint,
-    _errstr: *mut *const raw::c_char,
+    errstr:  *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_log(|| {
+        S::storage().with(|env, plugin| {
+            if !env.api_version.supports(Capability::ChangeWinsize) {
+                return Ok(());
+            }
+
+            plugin.change_winsize(u64::from(line), u64::from(cols)).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn log_suspend<P: IoPlugin, S: IoState<P>>(
+    signal:              raw::c_int,
+    errstr:  *mut *const raw::c_char,
 ) -> raw::c_int {
     catch_unwind_log(|| {
-        let env    = S::io_env();
-        let plugin = S::io_plugin();
+        S::storage().with(|env, plugin| {
+            // `sudo` shouldn't call this if it doesn't know about it,
+            // but we err on the side of caution
+            if !env.api_version.supports(Capability::LogSuspend) {
+                return Ok(());
+            }
+
+            plugin.log_suspend(signal).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn register_hooks<P: IoPlugin, S: IoState<P>>(
+    version:       raw::c_int,
+    register_hook: Option<unsafe extern "C" fn(hook: *mut sys::sudo_hook) -> raw::c_int>,
+) {
+    drop(catch_unwind(|| {
+        let Some(register_hook) = register_hook else {
+            return;
+        };
+
+        // hooks from an incompatible major version can't be understood
+        #[allow(clippy::cast_sign_loss)]
+        if (version as u32) >> 16 != sys::SUDO_HOOK_VERSION_MAJOR {
+            return;
+        }
+
+        let supported = S::storage().env().map_or(false, |env| {
+            env.api_version.supports(Capability::Hooks)
+        });
+
+        if !supported {
+            return;
+        }
+
+        // the plugin may not have been fully opened (e.g., for
+        // `sudo -V`), in which case it has no hooks to register
+        let _ = S::storage().with_extra(|_, plugin, hooks| {
+            let hooks = hooks.get_or_insert_with(Hooks::default);
+
+            plugin.register_hooks(hooks);
+
+            for hook in hooks.raw_hooks() {
+                let _ = (register_hook)(hook);
+            }
+        });
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn deregister_hooks<P: IoPlugin, S: IoState<P>>(
+    _version:        raw::c_int,
+    deregister_hook: Option<unsafe extern "C" fn(hook: *mut sys::sudo_hook) -> raw::c_int>,
+) {
+    drop(catch_unwind(|| {
+        let Some(mut hooks) = S::storage().with_extra(|_, _, hooks| hooks.take()).flatten() else {
+            return;
+        };
+
+        if let Some(deregister_hook) = deregister_hook {
+            hooks.deregister(|hook| {
+                let _ = (deregister_hook)(hook);
+            });
+        }
+
+        hooks.release();
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn approval_open<P: ApprovalPlugin, S: PluginState<ApprovalEnv, P>>(
+    version:                        raw::c_uint,
+    conversation:                   sys::sudo_conv_t,
+    plugin_printf:                  sys::sudo_printf_t,
+    settings_ptr:       *const *mut raw::c_char,
+    user_info_ptr:      *const *mut raw::c_char,
+    submit_optind:                  raw::c_int,
+    submit_argv:        *const *mut raw::c_char,
+    submit_envp:        *const *mut raw::c_char,
+    plugin_options_ptr: *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        // create our own PrintFacility to log to in case ApprovalEnv
+        // initialization fails
+        let (_, mut stderr) = PrintFacility::new(
+            Some(P::NAME), plugin_printf
+        );
+
+        let approval_env = ApprovalEnv::new(
+            P::NAME,
+            P::VERSION,
+            version,
+            plugin_printf,
+            conversation,
+            settings_ptr,
+            user_info_ptr,
+            submit_optind,
+            submit_argv,
+            submit_envp,
+            plugin_options_ptr,
+            Capability::Approval,
+        );
+
+        let approval_env = match approval_env {
+            Ok(v)   => v,
+            Err(e)  => {
+                let _ = stderr.write_error(&e);
+                let e : P::Error = e.into();
+                write_errstr(errstr, Version::from(version), &e);
+                return Into::<OpenStatus>::into(e);
+            }
+        };
+
+        S::storage().init(approval_env, |env| {
+            P::open(env).map(Some).map_err(|e| {
+                let _ = stderr.write_error(&e);
+                write_errstr(errstr, Version::from(version), &e);
+                Into::<OpenStatus>::into(e)
+            })
+        })
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn approval_close<P: ApprovalPlugin, S: PluginState<ApprovalEnv, P>>() {
+    drop(catch_unwind(|| {
+        let _ = S::storage().close(ApprovalPlugin::close);
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn approval_show_version<P: ApprovalPlugin, S: PluginState<ApprovalEnv, P>>(
+    verbose: raw::c_int,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        let Some(env) = S::storage().env() else {
+            return Into::<OpenStatus>::into(P::Error::from(Error::Uninitialized));
+        };
+
+        P::show_version(&env, verbose != 0);
+
+        OpenStatus::Ok
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn approval_check<P: ApprovalPlugin, S: PluginState<ApprovalEnv, P>>(
+    command_info_ptr:   *const *mut raw::c_char,
+    run_argv:           *const *mut raw::c_char,
+    run_envp:           *const *mut raw::c_char,
+    errstr:        *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_check(|| {
+        S::storage().with(|env, plugin| {
+            let command_info = CommandInfo::try_from(
+                OptionMap::from_raw(command_info_ptr.cast())
+            );
+
+            let command_info = match command_info {
+                Ok(v)  => v,
+                Err(e) => {
+                    let _ = env.stderr().write_error(&e);
+                    let e : P::Error = e.into();
+                    write_errstr(errstr, env.api_version, &e);
+                    return Into::<CheckStatus>::into(e);
+                }
+            };
+
+            let run_argv = cstrings_from_raw(run_argv);
+            let run_env  = OptionMap::from_raw(run_envp.cast());
+
+            plugin.check(&command_info, &run_argv, &run_env).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            }).into()
+        }).unwrap_or_else(|| P::Error::from(Error::Uninitialized).into())
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_open<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    version:                        raw::c_uint,
+    conversation:                   sys::sudo_conv_t,
+    plugin_printf:                  sys::sudo_printf_t,
+    settings_ptr:       *const *mut raw::c_char,
+    user_info_ptr:      *const *mut raw::c_char,
+    submit_optind:                  raw::c_int,
+    submit_argv:        *const *mut raw::c_char,
+    submit_envp:        *const *mut raw::c_char,
+    plugin_options_ptr: *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        // create our own PrintFacility to log to in case AuditEnv
+        // initialization fails
+        let (_, mut stderr) = PrintFacility::new(
+            Some(P::NAME), plugin_printf
+        );
+
+        let audit_env = AuditEnv::new(
+            P::NAME,
+            P::VERSION,
+            version,
+            plugin_printf,
+            conversation,
+            settings_ptr,
+            user_info_ptr,
+            submit_optind,
+            submit_argv,
+            submit_envp,
+            plugin_options_ptr,
+            Capability::Audit,
+        );
+
+        let audit_env = match audit_env {
+            Ok(v)   => v,
+            Err(e)  => {
+                let _ = stderr.write_error(&e);
+                let e : P::Error = e.into();
+                write_errstr(errstr, Version::from(version), &e);
+                return Into::<OpenStatus>::into(e);
+            }
+        };
+
+        S::storage().init(audit_env, |env| {
+            P::open(env).map(Some).map_err(|e| {
+                let _ = stderr.write_error(&e);
+                write_errstr(errstr, Version::from(version), &e);
+                Into::<OpenStatus>::into(e)
+            })
+        })
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_close<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    status_type: raw::c_int,
+    status:      raw::c_int,
+) {
+    drop(catch_unwind(|| {
+        let _ = S::storage().close(|plugin| plugin.close(CloseStatus::from_ffi(status_type, status)));
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_show_version<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    verbose: raw::c_int,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        let Some(env) = S::storage().env() else {
+            return Into::<OpenStatus>::into(P::Error::from(Error::Uninitialized));
+        };
+
+        P::show_version(&env, verbose != 0);
+
+        OpenStatus::Ok
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_accept<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    plugin_name:        *const raw::c_char,
+    plugin_type:               raw::c_uint,
+    command_info_ptr:   *const *mut raw::c_char,
+    run_argv:           *const *mut raw::c_char,
+    run_envp:           *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        S::storage().with(|env, plugin| {
+            let command_info = CommandInfo::try_from(
+                OptionMap::from_raw(command_info_ptr.cast())
+            );
+
+            let command_info = match command_info {
+                Ok(v)  => v,
+                Err(e) => {
+                    let _ = env.stderr().write_error(&e);
+                    let e : P::Error = e.into();
+                    write_errstr(errstr, env.api_version, &e);
+                    return Into::<OpenStatus>::into(e);
+                }
+            };
+
+            let run_argv = cstrings_from_raw(run_argv);
+            let run_env  = OptionMap::from_raw(run_envp.cast());
+
+            plugin.accept(
+                &str_from_raw(plugin_name),
+                plugin_type.into(),
+                &command_info,
+                &run_argv,
+                &run_env,
+            ).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            }).into()
+        }).unwrap_or_else(|| P::Error::from(Error::Uninitialized).into())
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_reject<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    plugin_name:        *const raw::c_char,
+    plugin_type:               raw::c_uint,
+    audit_msg:          *const raw::c_char,
+    command_info_ptr:   *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        S::storage().with(|env, plugin| {
+            plugin.reject(
+                &str_from_raw(plugin_name),
+                plugin_type.into(),
+                &str_from_raw(audit_msg),
+                &OptionMap::from_raw(command_info_ptr.cast()),
+            ).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn audit_error<P: AuditPlugin, S: PluginState<AuditEnv, P>>(
+    plugin_name:        *const raw::c_char,
+    plugin_type:               raw::c_uint,
+    audit_msg:          *const raw::c_char,
+    command_info_ptr:   *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        S::storage().with(|env, plugin| {
+            plugin.error(
+                &str_from_raw(plugin_name),
+                plugin_type.into(),
+                &str_from_raw(audit_msg),
+                &OptionMap::from_raw(command_info_ptr.cast()),
+            ).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_open<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    version:                        raw::c_uint,
+    conversation:                   sys::sudo_conv_t,
+    plugin_printf:                  sys::sudo_printf_t,
+    settings_ptr:       *const *mut raw::c_char,
+    user_info_ptr:      *const *mut raw::c_char,
+    user_env_ptr:       *const *mut raw::c_char,
+    plugin_options_ptr: *const *mut raw::c_char,
+    errstr:             *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        // create our own PrintFacility to log to in case PolicyEnv
+        // initialization fails
+        let (_, mut stderr) = PrintFacility::new(
+            Some(P::NAME), plugin_printf
+        );
 
-        plugin.change_winsize(u64::from(line), u64::from(cols)).map_err(|err| {
-            let _ = env.stderr().write_error(&err);
-            err
+        let policy_env = PolicyEnv::new(
+            P::NAME,
+            P::VERSION,
+            version,
+            plugin_printf,
+            conversation,
+            settings_ptr,
+            user_info_ptr,
+            user_env_ptr,
+            plugin_options_ptr,
+        );
+
+        let policy_env = match policy_env {
+            Ok(v)   => v,
+            Err(e)  => {
+                let _ = stderr.write_error(&e);
+                let e : P::Error = e.into();
+                write_errstr(errstr, Version::from(version), &e);
+                return Into::<OpenStatus>::into(e);
+            }
+        };
+
+        S::storage().init(policy_env, |env| {
+            P::open(env).map(Some).map_err(|e| {
+                let _ = stderr.write_error(&e);
+                write_errstr(errstr, Version::from(version), &e);
+                Into::<OpenStatus>::into(e)
+            })
         })
     })
 }
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_close<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    exit_status: raw::c_int,
+    error:       raw::c_int,
+) {
+    drop(catch_unwind(|| {
+        // the output handed to `sudo` is only released once the plugin
+        // has been closed
+        drop(S::storage().close(|plugin| plugin.close(exit_status, error)));
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_show_version<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    verbose: raw::c_int,
+) -> raw::c_int {
+    catch_unwind_open(|| {
+        let Some(env) = S::storage().env() else {
+            return Into::<OpenStatus>::into(P::Error::from(Error::Uninitialized));
+        };
+
+        P::show_version(&env, verbose != 0);
+
+        OpenStatus::Ok
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_check_policy<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    argc:                              raw::c_int,
+    argv:              *const *mut raw::c_char,
+    env_add:             *mut *mut raw::c_char,
+    command_info_out:  *mut *mut *mut raw::c_char,
+    argv_out:          *mut *mut *mut raw::c_char,
+    user_env_out:      *mut *mut *mut raw::c_char,
+    errstr:               *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_check(|| {
+        S::storage().with_extra(|env, plugin, policy_output| {
+            let argv    = cstrings_from_argv(argc, argv);
+            let env_add = OptionMap::from_raw(env_add as *const *const _);
+
+            let accepted = plugin.check_policy(&argv, &env_add).and_then(|accepted| {
+                let command_info = OptionMap::from(&accepted.command_info);
+
+                Ok(PolicyOutput {
+                    command_info: CStringArray::try_from(&command_info)?,
+                    argv:         CStringArray::new(accepted.argv),
+                    user_env:     CStringArray::try_from(&accepted.user_env)?,
+                })
+            });
+
+            let mut output = match accepted {
+                Ok(v)  => v,
+                Err(e) => {
+                    let _ = env.stderr().write_error(&e);
+                    write_errstr(errstr, env.api_version, &e);
+                    return Into::<CheckStatus>::into(e);
+                }
+            };
+
+            *command_info_out = output.command_info.as_mut_ptr();
+            *argv_out         = output.argv.as_mut_ptr();
+            *user_env_out     = output.user_env.as_mut_ptr();
+
+            // the arrays are heap-allocated, so the pointers handed to
+            // `sudo` remain valid once the output is moved into storage,
+            // where it lives until the plugin is closed
+            *policy_output = Some(output);
+
+            CheckStatus::Approve
+        }).unwrap_or_else(|| P::Error::from(Error::Uninitialized).into())
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_list<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    argc:                   raw::c_int,
+    argv:   *const *mut raw::c_char,
+    verbose:                raw::c_int,
+    list_user:  *const raw::c_char,
+    errstr:    *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_check(|| {
+        S::storage().with(|env, plugin| {
+            let argv      = cstrings_from_argv(argc, argv);
+            let list_user = if list_user.is_null() {
+                None
+            } else {
+                Some(str_from_raw(list_user))
+            };
+
+            plugin.list(&argv, verbose != 0, list_user.as_deref()).map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_validate<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    errstr:  *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_check(|| {
+        S::storage().with(|env, plugin| {
+            plugin.validate().map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_invalidate<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    remove: raw::c_int,
+) {
+    drop(catch_unwind(|| {
+        let _ = S::storage().with(|_, plugin| plugin.invalidate(remove != 0));
+    }));
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn policy_init_session<P: PolicyPlugin, S: PluginState<PolicyEnv, P, Option<PolicyOutput>>>(
+    _pwd:                  *mut sys::passwd,
+    _user_env_out: *mut *mut *mut raw::c_char,
+    errstr:           *mut *const raw::c_char,
+) -> raw::c_int {
+    catch_unwind_check(|| {
+        S::storage().with(|env, plugin| {
+            plugin.init_session().map_err(|err| {
+                let _ = env.stderr().write_error(&err);
+                write_errstr(errstr, env.api_version, &err);
+                err
+            })
+        }).unwrap_or_else(|| Err(Error::Uninitialized.into()))
+    })
+}
@@ -16,11 +16,16 @@
 
 // TODO: use error types as directly defined by sudo_plugin(8).
 
-use crate::core::{OpenStatus, LogStatus};
+use crate::core::{CheckStatus, OpenStatus, LogStatus};
 use crate::version::Version;
 
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
 use std::result::Result as StdResult;
 use std::error::Error as StdError;
+use std::sync::{Mutex, PoisonError};
+
+use once_cell::sync::Lazy;
 use thiserror::Error;
 
 /// Errors that can be produced by plugin internals.
@@ -54,12 +59,23 @@ pub enum Error {
         value: String,
     },
 
+    /// One or more options were missing or invalid. Each problem is
+    /// reported separately, as an `OptionMissing` or `OptionInvalid`
+    /// error, so that they can all be fixed at once.
+    #[error("{}", join(.0))]
+    OptionsInvalid(Vec<Error>),
+
     /// A plugin method panicked and the panic was captured at the FFI
     /// boundary. Panics can't cross into C, so we have to capture it
     /// and turn it into an appropriate return code.
     #[error("uncaught internal error")]
     UncaughtPanic,
 
+    /// `sudo` invoked a plugin callback before the plugin was opened
+    /// (or after it was closed).
+    #[error("sudo called plugin before it was initialized")]
+    Uninitialized,
+
     /// A generic error identified only by a provided string. This may
     /// be used by plugin implementors who don't wish to provide their
     /// own custom error types, and instead are happy to simply use
@@ -70,19 +86,67 @@ pub enum Error {
 
 pub(crate) type Result<T> = StdResult<T, Error>;
 
+/// Joins the descriptions of several errors into one.
+fn join(errors: &[Error]) -> String {
+    errors.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 /// The type for errors that can be returned from plugin callbacks.
 /// Plugin authors are expected to provide an implementation of coercion
 /// `From<sudo_plugin::errors::Error>` for their own custom error types
 /// as well coercions `Into<OpenStatus>` and `Into<LogStatus>` to
 /// specify how those errors should be treated by `sudo`.
-pub trait SudoError: StdError + From<Error> + Into<OpenStatus> + Into<LogStatus> { }
+pub trait SudoError: StdError + From<Error> + Into<OpenStatus> + Into<LogStatus> {
+    /// Returns a description of this error to be handed to `sudo`
+    /// through the `errstr` parameter of plugin callbacks, which newer
+    /// versions of `sudo` show to the user and record in their own
+    /// logs.
+    ///
+    /// The description is the error's `Display` output, truncated at
+    /// any NUL byte. Descriptions are interned, so they remain valid
+    /// for the life of the process.
+    fn errstr(&self) -> &'static CStr {
+        intern(&self.to_string())
+    }
+}
 
 impl<T: StdError + From<Error> + Into<OpenStatus> + Into<LogStatus>> SudoError for T {}
 
+/// Returns a C string with the same contents as `message`, allocating
+/// and leaking one only the first time a given message is seen.
+fn intern(message: &str) -> &'static CStr {
+    static INTERNED: Lazy<Mutex<HashMap<String, &'static CStr>>> = Lazy::new(Mutex::default);
+
+    let mut interned = INTERNED.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(errstr) = interned.get(message) {
+        return errstr;
+    }
+
+    // C strings can't contain interior NULs, so everything after the
+    // first one is dropped
+    let bytes  = message.split('\0').next().unwrap_or_default();
+    let errstr = CString::new(bytes).unwrap_or_default();
+    let errstr = Box::leak(errstr.into_boxed_c_str());
+
+    let _ = interned.insert(message.to_owned(), errstr);
+
+    errstr
+}
+
 impl From<Error> for OpenStatus {
-    fn from(_: Error) -> Self {
-        // by default, abort `sudo` on all errors
-        OpenStatus::Deny
+    fn from(error: Error) -> Self {
+        match error {
+            // the plugin was misconfigured, which sudo reports as a
+            // usage error
+            Error::OptionsInvalid(_) => OpenStatus::Usage,
+
+            // by default, abort `sudo` on all other errors
+            _ => OpenStatus::Deny,
+        }
     }
 }
 
@@ -92,3 +156,10 @@ impl From<Error> for LogStatus {
         LogStatus::Deny
     }
 }
+
+impl From<Error> for CheckStatus {
+    fn from(_: Error) -> Self {
+        // by default, reject the command on all errors
+        CheckStatus::Reject
+    }
+}
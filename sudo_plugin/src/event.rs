@@ -0,0 +1,361 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A safe wrapper around the event loop `sudo` runs while a command is
+//! executing, which allows plugins to schedule timers and to wait for
+//! file descriptors to become readable or writable without blocking
+//! `sudo` itself.
+//!
+//! Events are allocated through
+//! [`IoEnv::event`](crate::plugin::IoEnv::event) (or one of its
+//! convenience wrappers) and require a version of sudo that supports
+//! version 1.15 or later of the plugin API. An `Event` is removed from
+//! the event loop and freed when it's dropped.
+
+use crate::sys;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::RawFd;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+/// The signature of the function `sudo` provides for allocating events.
+pub(crate) type EventAlloc = Option<unsafe extern "C" fn() -> *mut sys::sudo_plugin_event>;
+
+type Callback = Box<dyn FnMut(RawFd, Events)>;
+
+/// A set of conditions an [`Event`](Event) waits for, or which caused
+/// its callback to fire.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Events(c_int);
+
+// the event flags are all small, so none of these casts can wrap
+#[allow(clippy::cast_possible_wrap)]
+impl Events {
+    /// The event's timeout expired.
+    pub const TIMEOUT : Self = Self(sys::SUDO_PLUGIN_EV_TIMEOUT as _);
+
+    /// The file descriptor is readable.
+    pub const READ    : Self = Self(sys::SUDO_PLUGIN_EV_READ    as _);
+
+    /// The file descriptor is writable.
+    pub const WRITE   : Self = Self(sys::SUDO_PLUGIN_EV_WRITE   as _);
+
+    /// The event remains in the event loop after it fires, rather than
+    /// having to be re-added.
+    pub const PERSIST : Self = Self(sys::SUDO_PLUGIN_EV_PERSIST as _);
+
+    /// Returns `true` if all of the conditions in `other` are set.
+    #[must_use]
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Events {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Events {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// An event registered with `sudo`'s event loop.
+///
+/// Events are configured with [`set`](Event::set), then scheduled with
+/// [`add`](Event::add). Dropping an `Event` cancels it.
+pub struct Event {
+    pev:      *mut sys::sudo_plugin_event,
+    callback: Option<Box<Callback>>,
+}
+
+impl Event {
+    /// Allocates a new, unconfigured event using the allocator `sudo`
+    /// provided.
+    pub(crate) fn new(alloc: EventAlloc) -> io::Result<Self> {
+        let alloc = alloc.ok_or_else(|| io::Error::new(
+            io::ErrorKind::Other,
+            "sudo does not support plugin events",
+        ))?;
+
+        let pev = unsafe { (alloc)() };
+
+        if pev.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "sudo could not allocate an event",
+            ));
+        }
+
+        Ok(Self { pev, callback: None })
+    }
+
+    /// Configures the event to invoke `callback` when any of `events`
+    /// occur on `fd`. Pure timers should pass `-1` for `fd` and
+    /// [`Events::TIMEOUT`](Events::TIMEOUT). The callback is provided
+    /// with the file descriptor and the conditions which caused it to
+    /// fire.
+    ///
+    /// The event must be [`add`](Event::add)ed before it will fire.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sudo` fails to configure the event.
+    pub fn set<F>(&mut self, fd: RawFd, events: Events, callback: F) -> io::Result<()>
+    where
+        F: FnMut(RawFd, Events) + 'static,
+    {
+        let mut callback : Box<Callback> = Box::new(Box::new(callback));
+        let closure      : *mut Callback = &mut *callback;
+
+        let ret = unsafe {
+            (self.vtable().set.ok_or_else(unsupported)?)(
+                self.pev,
+                fd,
+                events.0,
+                Some(trampoline),
+                closure.cast(),
+            )
+        };
+
+        check(ret)?;
+
+        // the previous callback (if any) can only be dropped once
+        // `sudo` no longer holds a pointer to it
+        self.callback = Some(callback);
+
+        Ok(())
+    }
+
+    /// Adds the event to the event loop. If a `timeout` is provided,
+    /// the event will fire with [`Events::TIMEOUT`](Events::TIMEOUT)
+    /// once it elapses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sudo` fails to add the event.
+    pub fn add(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        // `sys::timespec` is opaque, but is the system's `timespec`
+        let mut ts = timeout.map(timespec);
+        let     tp = match ts.as_mut() {
+            Some(ts) => { let tp : *mut libc::timespec = ts; tp.cast() },
+            None     => ptr::null_mut(),
+        };
+
+        let ret = unsafe {
+            (self.vtable().add.ok_or_else(unsupported)?)(self.pev, tp)
+        };
+
+        check(ret)
+    }
+
+    /// Removes the event from the event loop. It may be re-added later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sudo` fails to remove the event.
+    pub fn del(&mut self) -> io::Result<()> {
+        let ret = unsafe {
+            (self.vtable().del.ok_or_else(unsupported)?)(self.pev)
+        };
+
+        check(ret)
+    }
+
+    /// Returns `true` if the event is waiting on any of `events`.
+    #[must_use]
+    pub fn pending(&self, events: Events) -> bool {
+        let Some(pending) = self.vtable().pending else {
+            return false;
+        };
+
+        unsafe { (pending)(self.pev, events.0, ptr::null_mut()) != 0 }
+    }
+
+    /// Causes `sudo`'s event loop to exit after the current iteration.
+    pub fn loopbreak(&self) {
+        if let Some(loopbreak) = self.vtable().loopbreak {
+            unsafe { (loopbreak)(self.pev) };
+        }
+    }
+
+    fn vtable(&self) -> &sys::sudo_plugin_event {
+        unsafe { &*self.pev }
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("pev",          &self.pev)
+            .field("has_callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        // SAFETY: the event must be removed from the loop and freed
+        // *before* the callback is dropped, since `sudo` may otherwise
+        // call into a dangling closure
+        let _ = self.del();
+
+        if let Some(free) = self.vtable().free {
+            unsafe { (free)(self.pev) };
+        }
+
+        self.callback = None;
+    }
+}
+
+unsafe extern "C" fn trampoline(fd: c_int, what: c_int, closure: *mut c_void) {
+    let Some(callback) = closure.cast::<Callback>().as_mut() else {
+        return;
+    };
+
+    // panics can't cross into C, and there's nobody to report them to
+    drop(catch_unwind(AssertUnwindSafe(|| callback(fd, Events(what)))));
+}
+
+fn timespec(duration: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec:  libc::time_t::try_from(duration.as_secs()).unwrap_or(libc::time_t::MAX),
+        // nanoseconds are always less than 10^9, so can't wrap, but
+        // the width of `tv_nsec` varies by platform
+        #[allow(clippy::cast_possible_wrap, clippy::cast_lossless)]
+        tv_nsec: duration.subsec_nanos() as _,
+    }
+}
+
+fn check(ret: c_int) -> io::Result<()> {
+    if ret == -1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "sudo event operation failed",
+        ));
+    }
+
+    Ok(())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "sudo does not support this event operation",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    thread_local! {
+        static CALLBACK: Cell<(sys::sudo_plugin_ev_callback_t, *mut c_void)> = const { Cell::new((None, ptr::null_mut())) };
+        static LOG:      RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn log(entry: &'static str) {
+        LOG.with(|l| l.borrow_mut().push(entry));
+    }
+
+    fn fire(what: Events) {
+        let (callback, closure) = CALLBACK.with(Cell::get);
+
+        unsafe { (callback.unwrap())(-1, what.0, closure) };
+    }
+
+    unsafe extern "C" fn set(
+        _pev:     *mut sys::sudo_plugin_event,
+        _fd:      c_int,
+        _events:  c_int,
+        callback: sys::sudo_plugin_ev_callback_t,
+        closure:  *mut c_void,
+    ) -> c_int {
+        CALLBACK.with(|c| c.set((callback, closure)));
+        log("set");
+        0
+    }
+
+    unsafe extern "C" fn add(_pev: *mut sys::sudo_plugin_event, timeout: *mut sys::timespec) -> c_int {
+        log(if timeout.is_null() { "add" } else { "add_timeout" });
+        0
+    }
+
+    unsafe extern "C" fn del(_pev: *mut sys::sudo_plugin_event) -> c_int {
+        log("del");
+        0
+    }
+
+    unsafe extern "C" fn free(pev: *mut sys::sudo_plugin_event) {
+        drop(Box::from_raw(pev));
+        log("free");
+    }
+
+    unsafe extern "C" fn alloc() -> *mut sys::sudo_plugin_event {
+        Box::into_raw(Box::new(sys::sudo_plugin_event {
+            set:       Some(set),
+            add:       Some(add),
+            del:       Some(del),
+            pending:   None,
+            fd:        None,
+            setbase:   None,
+            loopbreak: None,
+            free:      Some(free),
+        }))
+    }
+
+    #[test]
+    fn event_invokes_callback_and_cleans_up() {
+        let fired = Rc::new(Cell::new(Events::default()));
+        let seen  = fired.clone();
+
+        let mut event = Event::new(Some(alloc)).unwrap();
+
+        event.set(-1, Events::TIMEOUT, move |_, what| seen.set(what)).unwrap();
+        event.add(Some(Duration::from_secs(1))).unwrap();
+
+        fire(Events::TIMEOUT);
+
+        assert!(fired.get().contains(Events::TIMEOUT));
+        assert!(!fired.get().contains(Events::READ));
+
+        drop(event);
+
+        // the callback (and its captured state) is dropped with the event
+        assert_eq!(1, Rc::strong_count(&fired));
+        assert_eq!(
+            vec!["set", "add_timeout", "del", "free"],
+            LOG.with(|l| l.borrow().clone()),
+        );
+    }
+
+    #[test]
+    fn event_requires_an_allocator() {
+        assert!(Event::new(None).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Typed wrappers for the environment hooks `sudo` allows plugins to
+//! register, which are invoked whenever `sudo` (or a module it loads,
+//! such as PAM) calls `setenv(3)`, `unsetenv(3)`, `putenv(3)`, or
+//! `getenv(3)`.
+//!
+//! Plugins register closures on a [`Hooks`](Hooks) from within
+//! [`IoPlugin::register_hooks`](crate::plugin::IoPlugin::register_hooks).
+
+use crate::sys;
+
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+type SetenvFn   = Box<dyn FnMut(&CStr, Option<&CStr>, bool) -> HookResult + Send>;
+type UnsetenvFn = Box<dyn FnMut(&CStr) -> HookResult + Send>;
+type PutenvFn   = Box<dyn FnMut(&CStr) -> HookResult + Send>;
+type GetenvFn   = Box<dyn FnMut(&CStr) -> HookResult + Send>;
+
+/// The return values understood by `sudo` from an environment hook.
+// the return values are all small, so none of these casts can wrap
+#[allow(clippy::cast_possible_wrap)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum HookResult {
+    /// Continue on to the next hook (and eventually the underlying
+    /// function).
+    Next = sys::SUDO_HOOK_RET_NEXT as _,
+
+    /// Stop processing, without calling any remaining hooks or the
+    /// underlying function. For `getenv`, the variable will appear to
+    /// be unset.
+    Stop = sys::SUDO_HOOK_RET_STOP as _,
+
+    /// The hook encountered an error.
+    Error = sys::SUDO_HOOK_RET_ERROR,
+}
+
+/// The set of environment hooks a plugin wishes to register.
+#[derive(Default)]
+pub struct Hooks {
+    setenv:   Option<Box<SetenvFn>>,
+    unsetenv: Option<Box<UnsetenvFn>>,
+    putenv:   Option<Box<PutenvFn>>,
+    getenv:   Option<Box<GetenvFn>>,
+
+    raw: Vec<sys::sudo_hook>,
+}
+
+impl Hooks {
+    /// Registers a hook called with the name, value, and `overwrite`
+    /// flag of every call to `setenv(3)`.
+    pub fn on_setenv<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&CStr, Option<&CStr>, bool) -> HookResult + Send + 'static,
+    {
+        self.setenv = Some(Box::new(Box::new(f)));
+        self
+    }
+
+    /// Registers a hook called with the name of every variable passed
+    /// to `unsetenv(3)`.
+    pub fn on_unsetenv<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&CStr) -> HookResult + Send + 'static,
+    {
+        self.unsetenv = Some(Box::new(Box::new(f)));
+        self
+    }
+
+    /// Registers a hook called with the `NAME=value` string passed to
+    /// every call to `putenv(3)`.
+    pub fn on_putenv<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&CStr) -> HookResult + Send + 'static,
+    {
+        self.putenv = Some(Box::new(Box::new(f)));
+        self
+    }
+
+    /// Registers a hook called with the name of every variable looked
+    /// up by `getenv(3)`.
+    pub fn on_getenv<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&CStr) -> HookResult + Send + 'static,
+    {
+        self.getenv = Some(Box::new(Box::new(f)));
+        self
+    }
+
+    /// Builds the raw `sudo_hook` structs for every registered hook.
+    /// The returned structs point into `self`, so they're stored
+    /// alongside the closures to guarantee they live as long.
+    pub(crate) fn raw_hooks(&mut self) -> &mut [sys::sudo_hook] {
+        let setenv   = self.setenv  .as_mut().map(|f| hook(sys::SUDO_HOOK_SETENV,   erase_setenv(setenv),     closure(f)));
+        let unsetenv = self.unsetenv.as_mut().map(|f| hook(sys::SUDO_HOOK_UNSETENV, erase_unsetenv(unsetenv), closure(f)));
+        let putenv   = self.putenv  .as_mut().map(|f| hook(sys::SUDO_HOOK_PUTENV,   erase_putenv(putenv),     closure(f)));
+        let getenv   = self.getenv  .as_mut().map(|f| hook(sys::SUDO_HOOK_GETENV,   erase_getenv(getenv),     closure(f)));
+
+        self.raw = setenv.into_iter()
+            .chain(unsetenv)
+            .chain(putenv)
+            .chain(getenv)
+            .collect();
+
+        &mut self.raw
+    }
+
+    /// Hands each of the raw `sudo_hook` structs most recently built by
+    /// [`raw_hooks`](Hooks::raw_hooks) to `f` to be deregistered, after
+    /// which they're forgotten.
+    pub(crate) fn deregister<F: FnMut(&mut sys::sudo_hook)>(&mut self, f: F) {
+        self.raw.iter_mut().for_each(f);
+        self.raw.clear();
+    }
+
+    /// Releases the hooks once the plugin that registered them has
+    /// been closed.
+    ///
+    /// `sudo` only deregisters hooks when it unloads the plugin, and it
+    /// can keep calling any that haven't been deregistered for the rest
+    /// of the process. So unless every hook has been deregistered, the
+    /// closures are deliberately leaked rather than dropped out from
+    /// under it.
+    pub(crate) fn release(self) {
+        if !self.raw.is_empty() {
+            mem::forget(self);
+        }
+    }
+}
+
+// SAFETY: the only thing keeping `Hooks` from being automatically
+// `Send` are the `closure` pointers inside of the raw `sudo_hook`
+// structs. Each of those points into the heap allocation of one of the
+// boxed closures owned by this struct, so moving the struct (to another
+// thread or otherwise) leaves them pointing at the same, still-owned
+// closures. The closures themselves are required to be `Send`, so it's
+// sound for them to be invoked from whichever thread `sudo` calls the
+// hooks on. Nothing is ever read from or written to through the raw
+// pointers by this struct; they only exist to be handed to `sudo`.
+unsafe impl Send for Hooks {}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("setenv",   &self.setenv  .is_some())
+            .field("unsetenv", &self.unsetenv.is_some())
+            .field("putenv",   &self.putenv  .is_some())
+            .field("getenv",   &self.getenv  .is_some())
+            .field("raw",      &self.raw)
+            .finish()
+    }
+}
+
+type HookFn = unsafe extern "C" fn() -> c_int;
+
+// `sudo_hook` stores every hook as a generic function pointer, and
+// casts it back to the appropriate type based on its `hook_type`; these
+// only erase the argument types of each function
+
+fn erase_setenv(f: unsafe extern "C" fn(*const c_char, *const c_char, c_int, *mut c_void) -> c_int) -> HookFn {
+    unsafe { mem::transmute(f) }
+}
+
+fn erase_unsetenv(f: unsafe extern "C" fn(*const c_char, *mut c_void) -> c_int) -> HookFn {
+    unsafe { mem::transmute(f) }
+}
+
+fn erase_putenv(f: unsafe extern "C" fn(*mut c_char, *mut c_void) -> c_int) -> HookFn {
+    unsafe { mem::transmute(f) }
+}
+
+fn erase_getenv(f: unsafe extern "C" fn(*const c_char, *mut *mut c_char, *mut c_void) -> c_int) -> HookFn {
+    unsafe { mem::transmute(f) }
+}
+
+fn hook(hook_type: u32, hook_fn: HookFn, closure: *mut c_void) -> sys::sudo_hook {
+    sys::sudo_hook {
+        hook_version: sys::SUDO_HOOK_VERSION_MAJOR << 16 | sys::SUDO_HOOK_VERSION_MINOR,
+        hook_type,
+        hook_fn:      Some(hook_fn),
+        closure,
+    }
+}
+
+fn closure<T>(f: &mut Box<T>) -> *mut c_void {
+    let ptr : *mut T = &mut **f;
+    ptr.cast()
+}
+
+unsafe fn call<T: ?Sized, F: FnOnce(&mut Box<T>) -> HookResult>(closure: *mut c_void, f: F) -> c_int {
+    let Some(callback) = closure.cast::<Box<T>>().as_mut() else {
+        return HookResult::Next as _;
+    };
+
+    // panics can't cross into C, so treat them as hook errors
+    catch_unwind(AssertUnwindSafe(|| f(callback)))
+        .unwrap_or(HookResult::Error) as _
+}
+
+unsafe fn cstr<'a>(ptr: *const c_char) -> Option<&'a CStr> {
+    if ptr.is_null() { None } else { Some(CStr::from_ptr(ptr)) }
+}
+
+unsafe extern "C" fn setenv(
+    name:      *const c_char,
+    value:     *const c_char,
+    overwrite: c_int,
+    closure:   *mut c_void,
+) -> c_int {
+    let Some(name) = cstr(name) else {
+        return HookResult::Next as _;
+    };
+
+    call(closure, |f: &mut SetenvFn| f(name, cstr(value), overwrite != 0))
+}
+
+unsafe extern "C" fn unsetenv(name: *const c_char, closure: *mut c_void) -> c_int {
+    let Some(name) = cstr(name) else {
+        return HookResult::Next as _;
+    };
+
+    call(closure, |f: &mut UnsetenvFn| f(name))
+}
+
+unsafe extern "C" fn putenv(string: *mut c_char, closure: *mut c_void) -> c_int {
+    let Some(string) = cstr(string) else {
+        return HookResult::Next as _;
+    };
+
+    call(closure, |f: &mut PutenvFn| f(string))
+}
+
+unsafe extern "C" fn getenv(name: *const c_char, value: *mut *mut c_char, closure: *mut c_void) -> c_int {
+    let Some(name) = cstr(name) else {
+        return HookResult::Next as _;
+    };
+
+    let ret = call(closure, |f: &mut GetenvFn| f(name));
+
+    // a stopped `getenv` returns whatever is in `value`, so make sure
+    // the variable appears unset
+    if ret == HookResult::Stop as c_int && !value.is_null() {
+        *value = ptr::null_mut();
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CString;
+    use std::sync::{Arc, Mutex};
+
+    type RawSetenv = unsafe extern "C" fn(*const c_char, *const c_char, c_int, *mut c_void) -> c_int;
+    type RawGetenv = unsafe extern "C" fn(*const c_char, *mut *mut c_char, *mut c_void) -> c_int;
+
+    #[test]
+    fn raw_hooks_only_include_registered_hooks() {
+        let mut hooks = Hooks::default();
+
+        assert!(hooks.raw_hooks().is_empty());
+
+        let _ = hooks
+            .on_unsetenv(|_| HookResult::Next)
+            .on_getenv(|_| HookResult::Next);
+
+        let types : Vec<_> = hooks.raw_hooks().iter().map(|h| h.hook_type).collect();
+
+        assert_eq!(vec![sys::SUDO_HOOK_UNSETENV, sys::SUDO_HOOK_GETENV], types);
+    }
+
+    #[test]
+    fn only_deregistered_hooks_are_dropped_on_release() {
+        let witness = Arc::new(());
+
+        let mut registered = Hooks::default();
+        let     w          = witness.clone();
+
+        let _ = registered.on_getenv(move |_| { let _ = &w; HookResult::Next });
+        let _ = registered.raw_hooks();
+
+        // `sudo` may still call this hook, so it must be kept alive
+        registered.release();
+        assert_eq!(2, Arc::strong_count(&witness));
+
+        let mut deregistered = Hooks::default();
+        let mut count        = 0;
+        let     w            = witness.clone();
+
+        let _ = deregistered.on_getenv(move |_| { let _ = &w; HookResult::Next });
+        let _ = deregistered.raw_hooks();
+
+        deregistered.deregister(|_| count += 1);
+        deregistered.release();
+
+        assert_eq!(1, count);
+        assert_eq!(2, Arc::strong_count(&witness));
+    }
+
+    #[test]
+    fn setenv_hook_can_veto_variables() {
+        let seen  = Arc::new(Mutex::new(vec![]));
+        let log   = seen.clone();
+        let mut hooks = Hooks::default();
+
+        let _ = hooks.on_setenv(move |name, value, overwrite| {
+            log.lock().unwrap().push((
+                name.to_owned(),
+                value.map(CStr::to_owned),
+                overwrite,
+            ));
+
+            if name.to_bytes() == b"LD_PRELOAD" { HookResult::Stop } else { HookResult::Next }
+        });
+
+        let hook    = hooks.raw_hooks()[0];
+        let hook_fn = unsafe { mem::transmute::<HookFn, RawSetenv>(hook.hook_fn.unwrap()) };
+
+        let preload = CString::new("LD_PRELOAD").unwrap();
+        let path    = CString::new("PATH").unwrap();
+        let value   = CString::new("/evil.so").unwrap();
+
+        let ret1 = unsafe { hook_fn(preload.as_ptr(), value.as_ptr(), 1, hook.closure) };
+        let ret2 = unsafe { hook_fn(path.as_ptr(), ptr::null(), 0, hook.closure) };
+
+        assert_eq!(HookResult::Stop as c_int, ret1);
+        assert_eq!(HookResult::Next as c_int, ret2);
+
+        assert_eq!(vec![
+            (preload, Some(value), true),
+            (path,    None,        false),
+        ], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn stopped_getenv_hook_clears_the_value() {
+        let mut hooks = Hooks::default();
+
+        let _ = hooks.on_getenv(|_| HookResult::Stop);
+
+        let hook    = hooks.raw_hooks()[0];
+        let hook_fn = unsafe { mem::transmute::<HookFn, RawGetenv>(hook.hook_fn.unwrap()) };
+
+        let name      = CString::new("LD_PRELOAD").unwrap();
+        let mut value = name.as_ptr().cast_mut();
+
+        let ret = unsafe { hook_fn(name.as_ptr(), &mut value, hook.closure) };
+
+        assert_eq!(HookResult::Stop as c_int, ret);
+        assert!(value.is_null());
+    }
+}
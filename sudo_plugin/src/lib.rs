@@ -62,13 +62,19 @@
 
 pub mod core;
 pub mod errors;
+pub mod event;
+pub mod hooks;
+#[macro_use]
 pub mod options;
+pub mod output;
 pub mod plugin;
 pub mod prelude;
+pub mod shell;
+pub mod version;
 
-pub mod macros;
+#[cfg(feature = "testing")]
+pub mod testing;
 
-mod output;
-mod version;
+pub mod macros;
 
 pub use sudo_plugin_sys as sys;
@@ -23,11 +23,12 @@
 /// # mod necessary_for_super_type_lookup_to_work {
 /// use sudo_plugin::prelude::*;
 /// use std::io::Write;
+/// use std::sync::Arc;
 ///
 /// sudo_io_plugin! { example : Example }
 ///
 /// struct Example {
-///     env: &'static IoEnv
+///     env: Arc<IoEnv>
 /// }
 ///
 /// impl IoPlugin for Example {
@@ -35,7 +36,7 @@
 ///
 ///     const NAME: &'static str = "example";
 ///
-///     fn open(env: &'static IoEnv) -> Result<Self, Self::Error> {
+///     fn open(env: Arc<IoEnv>) -> Result<Self, Self::Error> {
 ///         writeln!(env.stdout(), "example sudo plugin initialized");
 ///
 ///         Ok(Example { env })
@@ -62,26 +63,34 @@
 /// ```ignore
 /// Plugin example example.so
 /// ```
+///
+/// Every optional callback is exported, regardless of the version of
+/// `sudo` the plugin will be loaded by. Callbacks that require a newer
+/// version of the plugin API than `sudo` advertises are ignored at
+/// runtime (see [`Capability`](crate::version::Capability)), so the
+/// same plugin can be used with older and newer versions of `sudo`
+/// alike.
 #[macro_export]
 macro_rules! sudo_io_plugin {
     ( $name:ident : $ty:ty ) => {
         mod $name {
             use super::*;
 
-            // TODO: end use of static mut
-
-            static mut SUDO_IO_ENV:    Option<$crate::plugin::IoEnv> = None;
-            static mut SUDO_IO_PLUGIN: Option<$ty>                   = None;
+            static SUDO_IO_STORAGE: $crate::plugin::IoStorage<$ty> = $crate::plugin::IoStorage::new();
 
             pub struct State;
 
-            unsafe impl $crate::plugin::IoState<$ty> for State {
-                unsafe fn io_env_mut() -> &'static mut Option<$crate::plugin::IoEnv> {
-                    &mut SUDO_IO_ENV
+            impl $crate::plugin::PluginState<$crate::plugin::IoEnv, $ty, Option<$crate::hooks::Hooks>> for State {
+                fn storage() -> &'static $crate::plugin::IoStorage<$ty> {
+                    &SUDO_IO_STORAGE
                 }
+            }
 
-                unsafe fn io_plugin_mut() -> &'static mut Option<$ty> {
-                    &mut SUDO_IO_PLUGIN
+            impl $crate::plugin::IoState<$ty> for State {
+                fn event_alloc() -> Option<unsafe extern "C" fn() -> *mut $crate::sys::sudo_plugin_event> {
+                    // SAFETY: sudo only writes to this field before
+                    // calling `open`, and never from another thread
+                    unsafe { super::$name.event_alloc }
                 }
             }
         }
@@ -103,8 +112,289 @@ macro_rules! sudo_io_plugin {
             log_stderr: Some($crate::core::log_stderr::<$ty, $name::State>),
 
             change_winsize: Some($crate::core::change_winsize::<$ty, $name::State>),
+            log_suspend:    Some($crate::core::log_suspend   ::<$ty, $name::State>),
+
+            register_hooks:   Some($crate::core::register_hooks  ::<$ty, $name::State>),
+            deregister_hooks: Some($crate::core::deregister_hooks::<$ty, $name::State>),
 
             .. $crate::sys::io_plugin::empty()
         };
     }
 }
+
+/// Emits the boilerplate stanza for creating and initializing a custom
+/// sudo approval plugin.
+///
+/// # Example
+///
+/// ```rust
+/// # mod necessary_for_super_type_lookup_to_work {
+/// use sudo_plugin::prelude::*;
+/// use sudo_plugin::options::CommandInfo;
+/// use std::ffi::CString;
+/// use std::io::Write;
+/// use std::sync::Arc;
+///
+/// sudo_approval_plugin! { example : Example }
+///
+/// struct Example {
+///     env: Arc<ApprovalEnv>
+/// }
+///
+/// impl ApprovalPlugin for Example {
+///     type Error = Error;
+///
+///     const NAME: &'static str = "example";
+///
+///     fn open(env: Arc<ApprovalEnv>) -> Result<Self, Self::Error> {
+///         Ok(Example { env })
+///     }
+///
+///     fn check(
+///         &self,
+///         command_info: &CommandInfo,
+///         _:            &[CString],
+///         _:            &OptionMap,
+///     ) -> Result<(), Self::Error> {
+///         if command_info.command.starts_with("/usr/bin") {
+///             return Ok(());
+///         }
+///
+///         writeln!(self.env.stderr(), "only commands in /usr/bin are allowed");
+///
+///         Err(Error::Other("command rejected".into()))
+///     }
+/// }
+/// # }
+/// ```
+///
+/// The generated plugin will have the entry point `example`, so to
+/// enable it, you'd copy the library to `example.so` in sudo's plugin
+/// directory (on macOS, `/usr/local/libexec/sudo`) and add the following
+/// to `/etc/sudo.conf`:
+///
+/// ```ignore
+/// Plugin example example.so
+/// ```
+#[macro_export]
+macro_rules! sudo_approval_plugin {
+    ( $name:ident : $ty:ty ) => {
+        mod $name {
+            use super::*;
+
+            static SUDO_APPROVAL_STORAGE: $crate::plugin::ApprovalStorage<$ty> = $crate::plugin::ApprovalStorage::new();
+
+            pub struct State;
+
+            impl $crate::plugin::PluginState<$crate::plugin::ApprovalEnv, $ty> for State {
+                fn storage() -> &'static $crate::plugin::ApprovalStorage<$ty> {
+                    &SUDO_APPROVAL_STORAGE
+                }
+            }
+        }
+
+        // This must be `static mut` as the sudo plugin API may write
+        // into the plugin struct.
+        #[allow(non_upper_case_globals)]
+        #[allow(missing_docs)]
+        #[no_mangle]
+        pub static mut $name: $crate::sys::approval_plugin = $crate::sys::approval_plugin {
+            open:         Some($crate::core::approval_open        ::<$ty, $name::State>),
+            close:        Some($crate::core::approval_close       ::<$ty, $name::State>),
+            check:        Some($crate::core::approval_check       ::<$ty, $name::State>),
+            show_version: Some($crate::core::approval_show_version::<$ty, $name::State>),
+
+            .. $crate::sys::approval_plugin::empty()
+        };
+    }
+}
+
+/// Emits the boilerplate stanza for creating and initializing a custom
+/// sudo audit plugin.
+///
+/// # Example
+///
+/// ```rust
+/// # mod necessary_for_super_type_lookup_to_work {
+/// use sudo_plugin::prelude::*;
+/// use sudo_plugin::options::CommandInfo;
+/// use sudo_plugin::plugin::PluginType;
+/// use std::ffi::CString;
+/// use std::io::Write;
+/// use std::sync::Arc;
+///
+/// sudo_audit_plugin! { example : Example }
+///
+/// struct Example {
+///     env: Arc<AuditEnv>
+/// }
+///
+/// impl AuditPlugin for Example {
+///     type Error = Error;
+///
+///     const NAME: &'static str = "example";
+///
+///     fn open(env: Arc<AuditEnv>) -> Result<Self, Self::Error> {
+///         Ok(Example { env })
+///     }
+///
+///     fn accept(
+///         &self,
+///         plugin_name:  &str,
+///         _:            PluginType,
+///         command_info: &CommandInfo,
+///         _:            &[CString],
+///         _:            &OptionMap,
+///     ) -> Result<(), Self::Error> {
+///         writeln!(
+///             self.env.stderr(),
+///             "{} accepted {}",
+///             plugin_name,
+///             command_info.command.display(),
+///         );
+///
+///         Ok(())
+///     }
+/// }
+/// # }
+/// ```
+///
+/// The generated plugin will have the entry point `example`, so to
+/// enable it, you'd copy the library to `example.so` in sudo's plugin
+/// directory (on macOS, `/usr/local/libexec/sudo`) and add the following
+/// to `/etc/sudo.conf`:
+///
+/// ```ignore
+/// Plugin example example.so
+/// ```
+#[macro_export]
+macro_rules! sudo_audit_plugin {
+    ( $name:ident : $ty:ty ) => {
+        mod $name {
+            use super::*;
+
+            static SUDO_AUDIT_STORAGE: $crate::plugin::AuditStorage<$ty> = $crate::plugin::AuditStorage::new();
+
+            pub struct State;
+
+            impl $crate::plugin::PluginState<$crate::plugin::AuditEnv, $ty> for State {
+                fn storage() -> &'static $crate::plugin::AuditStorage<$ty> {
+                    &SUDO_AUDIT_STORAGE
+                }
+            }
+        }
+
+        // This must be `static mut` as the sudo plugin API may write
+        // into the plugin struct.
+        #[allow(non_upper_case_globals)]
+        #[allow(missing_docs)]
+        #[no_mangle]
+        pub static mut $name: $crate::sys::audit_plugin = $crate::sys::audit_plugin {
+            open:         Some($crate::core::audit_open        ::<$ty, $name::State>),
+            close:        Some($crate::core::audit_close       ::<$ty, $name::State>),
+            accept:       Some($crate::core::audit_accept      ::<$ty, $name::State>),
+            reject:       Some($crate::core::audit_reject      ::<$ty, $name::State>),
+            error:        Some($crate::core::audit_error       ::<$ty, $name::State>),
+            show_version: Some($crate::core::audit_show_version::<$ty, $name::State>),
+
+            .. $crate::sys::audit_plugin::empty()
+        };
+    }
+}
+
+/// Emits the boilerplate stanza for creating and initializing a custom
+/// sudo policy plugin.
+///
+/// # Example
+///
+/// ```rust
+/// # mod necessary_for_super_type_lookup_to_work {
+/// use sudo_plugin::prelude::*;
+/// use sudo_plugin::options::CommandInfo;
+/// use std::ffi::CString;
+/// use std::sync::Arc;
+///
+/// sudo_policy_plugin! { example : Example }
+///
+/// struct Example {
+///     env: Arc<PolicyEnv>
+/// }
+///
+/// impl PolicyPlugin for Example {
+///     type Error = Error;
+///
+///     const NAME: &'static str = "example";
+///
+///     fn open(env: Arc<PolicyEnv>) -> Result<Self, Self::Error> {
+///         Ok(Example { env })
+///     }
+///
+///     fn check_policy(
+///         &self,
+///         argv: &[CString],
+///         _:    &OptionMap,
+///     ) -> Result<Accepted, Self::Error> {
+///         let command = argv.first()
+///             .and_then(|c| c.to_str().ok())
+///             .filter(|c| c.starts_with("/usr/bin/"))
+///             .ok_or_else(|| Error::Other("command rejected".into()))?;
+///
+///         Ok(Accepted {
+///             command_info: CommandInfo::builder()
+///                 .command(command)
+///                 .runas_uid(0)
+///                 .runas_gid(0)
+///                 .cwd(&self.env.user_info.cwd)
+///                 .build(),
+///             argv:     argv.to_vec(),
+///             user_env: self.env.user_env.clone(),
+///         })
+///     }
+/// }
+/// # }
+/// ```
+///
+/// The generated plugin will have the entry point `example`, so to
+/// enable it, you'd copy the library to `example.so` in sudo's plugin
+/// directory (on macOS, `/usr/local/libexec/sudo`) and add the following
+/// to `/etc/sudo.conf`:
+///
+/// ```ignore
+/// Plugin example example.so
+/// ```
+#[macro_export]
+macro_rules! sudo_policy_plugin {
+    ( $name:ident : $ty:ty ) => {
+        mod $name {
+            use super::*;
+
+            static SUDO_POLICY_STORAGE: $crate::plugin::PolicyStorage<$ty> = $crate::plugin::PolicyStorage::new();
+
+            pub struct State;
+
+            impl $crate::plugin::PluginState<$crate::plugin::PolicyEnv, $ty, Option<$crate::plugin::PolicyOutput>> for State {
+                fn storage() -> &'static $crate::plugin::PolicyStorage<$ty> {
+                    &SUDO_POLICY_STORAGE
+                }
+            }
+        }
+
+        // This must be `static mut` as the sudo plugin API may write
+        // into the plugin struct.
+        #[allow(non_upper_case_globals)]
+        #[allow(missing_docs)]
+        #[no_mangle]
+        pub static mut $name: $crate::sys::policy_plugin = $crate::sys::policy_plugin {
+            open:         Some($crate::core::policy_open        ::<$ty, $name::State>),
+            close:        Some($crate::core::policy_close       ::<$ty, $name::State>),
+            show_version: Some($crate::core::policy_show_version::<$ty, $name::State>),
+            check_policy: Some($crate::core::policy_check_policy::<$ty, $name::State>),
+            list:         Some($crate::core::policy_list        ::<$ty, $name::State>),
+            validate:     Some($crate::core::policy_validate    ::<$ty, $name::State>),
+            invalidate:   Some($crate::core::policy_invalidate  ::<$ty, $name::State>),
+            init_session: Some($crate::core::policy_init_session::<$ty, $name::State>),
+
+            .. $crate::sys::policy_plugin::empty()
+        };
+    }
+}
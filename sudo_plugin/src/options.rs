@@ -25,14 +25,80 @@
 // The layouts of the structs below this module aren't under my control.
 #![allow(clippy::struct_excessive_bools)]
 
+/// Generates a chainable setter on a builder for each of the listed
+/// fields, where builders are tuple structs wrapping the value being
+/// built. Setters for `into` fields accept anything convertible into
+/// the field's type (e.g., a `&str` for a `PathBuf`), while those for
+/// `value` fields take the type itself so that integer literals can be
+/// inferred.
+macro_rules! setters {
+    ( $( $(#[$attr:meta])* $field:ident : $kind:ident $ty:ty ),* $(,)* ) => {
+        $( setters!(@$kind $(#[$attr])* $field : $ty); )*
+    };
+
+    ( @value $(#[$attr:meta])* $field:ident : $ty:ty ) => {
+        $(#[$attr])*
+        #[must_use]
+        pub fn $field(mut self, $field: $ty) -> Self {
+            self.0.$field = $field;
+            self
+        }
+    };
+
+    ( @into $(#[$attr:meta])* $field:ident : $ty:ty ) => {
+        $(#[$attr])*
+        #[must_use]
+        pub fn $field<T: Into<$ty>>(mut self, $field: T) -> Self {
+            self.0.$field = $field.into();
+            self
+        }
+    };
+}
+
+/// Like `setters!`, but for optional fields. The setters take the inner
+/// value.
+macro_rules! optional_setters {
+    ( $( $(#[$attr:meta])* $field:ident : $kind:ident $ty:ty ),* $(,)* ) => {
+        $( optional_setters!(@$kind $(#[$attr])* $field : $ty); )*
+    };
+
+    ( @value $(#[$attr:meta])* $field:ident : $ty:ty ) => {
+        $(#[$attr])*
+        #[must_use]
+        pub fn $field(mut self, $field: $ty) -> Self {
+            self.0.$field = Some($field);
+            self
+        }
+    };
+
+    ( @into $(#[$attr:meta])* $field:ident : $ty:ty ) => {
+        $(#[$attr])*
+        #[must_use]
+        pub fn $field<T: Into<$ty>>(mut self, $field: T) -> Self {
+            self.0.$field = Some($field.into());
+            self
+        }
+    };
+}
+
+#[doc(hidden)] pub mod c_string_array;
 #[doc(hidden)] pub mod command_info;
 #[doc(hidden)] pub mod option_map;
+#[doc(hidden)] pub mod rlimits;
 #[doc(hidden)] pub mod settings;
+#[doc(hidden)] pub mod sudo_options;
 #[doc(hidden)] pub mod user_info;
 
-mod traits;
+pub(crate) mod traits;
 
-pub use command_info::CommandInfo;
+pub use c_string_array::CStringArray;
+pub use command_info::{CommandInfo, CommandInfoBuilder};
 pub use option_map::OptionMap;
-pub use settings::Settings;
-pub use user_info::UserInfo;
+pub use rlimits::{ResourceLimit, ResourceLimits};
+pub use settings::{Settings, SettingsBuilder};
+pub use sudo_options::{SudoOptions, is_nonzero};
+pub use traits::{FromSudoOption, FromSudoOptionList};
+pub use user_info::{UserInfo, UserInfoBuilder};
+
+#[cfg(feature = "derive")]
+pub use sudo_plugin_derive::SudoOptions;
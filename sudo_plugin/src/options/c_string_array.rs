@@ -0,0 +1,77 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::errors::Result;
+use crate::options::OptionMap;
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::ptr;
+
+use libc::c_char;
+
+/// An owned, NULL-terminated array of NUL-terminated strings, which is
+/// the format `sudo` expects policy plugins to return lists in (e.g.,
+/// `command_info`, `argv_out`, and `user_env_out`).
+///
+/// The pointer returned by [`as_mut_ptr`](CStringArray::as_mut_ptr)
+/// remains valid for as long as the `CStringArray` itself is alive,
+/// so callers must keep it around until `sudo` is done with it.
+#[derive(Debug)]
+pub struct CStringArray {
+    strings: Vec<CString>,
+    ptrs:    Vec<*mut c_char>,
+}
+
+impl CStringArray {
+    /// Builds a `CStringArray` from a list of strings.
+    #[must_use]
+    pub fn new(strings: Vec<CString>) -> Self {
+        let mut ptrs : Vec<_> = strings.iter()
+            .map(|s| s.as_ptr().cast_mut())
+            .collect();
+
+        ptrs.push(ptr::null_mut());
+
+        Self { strings, ptrs }
+    }
+
+    /// Returns the strings contained in the array.
+    #[must_use]
+    pub fn strings(&self) -> &[CString] {
+        &self.strings
+    }
+
+    /// Returns a pointer to the NULL-terminated array of strings. `sudo`
+    /// never writes through this pointer, but its API requires it to
+    /// be mutable.
+    pub fn as_mut_ptr(&mut self) -> *mut *mut c_char {
+        self.ptrs.as_mut_ptr()
+    }
+}
+
+// SAFETY: every pointer in `ptrs` points into the heap allocation of
+// one of the `strings`, which this struct owns and never mutates or
+// frees before it is dropped. Moving the struct to another thread moves
+// those allocations along with it, so the pointers are exactly as valid
+// there as they were here.
+unsafe impl Send for CStringArray {}
+
+impl TryFrom<&OptionMap> for CStringArray {
+    type Error = crate::errors::Error;
+
+    fn try_from(options: &OptionMap) -> Result<Self> {
+        options.to_cstrings().map(Self::new)
+    }
+}
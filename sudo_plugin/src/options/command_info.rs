@@ -13,7 +13,9 @@
 // permissions and limitations under the License.
 
 use crate::errors::{Result, Error};
-use crate::options::OptionMap;
+use crate::options::{OptionMap, ResourceLimits};
+use crate::options::sudo_options::parse_list;
+use crate::options::traits::ToSudoOption;
 
 use std::convert::TryFrom;
 use std::os::unix::io::RawFd;
@@ -24,8 +26,11 @@ use libc::{self, gid_t, mode_t, uid_t};
 /// Information about the command being run. These values are used by
 /// sudo to set the execution environment when running a command and set
 /// by the policy plugin.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CommandInfo {
+    /// The AppArmor profile to transition to when executing the command.
+    pub apparmor_profile: Option<String>,
+
     /// The root directory to use when running the command.
     pub chroot: Option<String>,
 
@@ -78,6 +83,17 @@ pub struct CommandInfo {
     /// open file descriptor.
     pub exec_fd: Option<u64>,
 
+    /// If set, sudo will run the command in intercept mode, where any
+    /// commands it in turn attempts to execute are passed back to the
+    /// policy plugin to be accepted or rejected.
+    pub intercept: bool,
+
+    /// If set, sudo will verify that a command run in intercept mode
+    /// actually executes the arguments and environment the policy plugin
+    /// accepted, rather than ones changed out from under it after the
+    /// check.
+    pub intercept_verify: bool,
+
     /// Set to true if the I/O logging plugins, if any, should compress the log
     /// data. This is a hint to the I/O logging plugin which may choose to
     /// ignore it.
@@ -129,6 +145,15 @@ pub struct CommandInfo {
     /// This is a hint to the I/O logging plugin which may choose to ignore it.
     pub iolog_user: Option<String>,
 
+    /// A list of one or more remote log servers, each in the form
+    /// “host:port”, to send audit and I/O logs to.
+    pub log_servers: Vec<String>,
+
+    /// If set, sudo will log any commands executed by the command being
+    /// run (e.g., those run by a shell), using the same mechanism as
+    /// intercept mode.
+    pub log_subcmds: bool,
+
     /// BSD login class to use when setting resource limits and nice value
     /// (optional). This option is only set on systems that support login
     /// classes.
@@ -151,6 +176,10 @@ pub struct CommandInfo {
     /// initializing the group vector based on runas_user.
     pub preserve_groups: bool,
 
+    /// Resource limits to apply when executing the command, from the
+    /// `rlimit_*` options.
+    pub rlimits: ResourceLimits,
+
     /// Effective group-ID to run the command as. If not specified, the value
     /// of runas_gid is used.
     pub runas_egid: gid_t,
@@ -181,6 +210,10 @@ pub struct CommandInfo {
     /// (if any), with the tty, time, type and pid fields updated.
     pub set_utmp: bool,
 
+    /// The source of the policy decision (e.g., the file and line of a
+    /// matching sudoers rule), if the policy plugin reports one.
+    pub source: Option<String>,
+
     /// Set to true when in sudoedit mode. The plugin may enable sudoedit mode
     /// even if sudo was not invoked as sudoedit. This allows the plugin to
     /// perform command substitution and transparently enable sudoedit when the
@@ -235,17 +268,24 @@ pub struct CommandInfo {
     pub raw: OptionMap,
 }
 
-impl TryFrom<OptionMap> for CommandInfo {
-    type Error = Error;
+impl CommandInfo {
+    /// Returns a [`CommandInfoBuilder`](CommandInfoBuilder) for
+    /// constructing a `CommandInfo` to be returned to `sudo` from a
+    /// policy plugin. Any values left unset take on the same defaults
+    /// they would if `sudo` had omitted them.
+    #[must_use]
+    pub fn builder() -> CommandInfoBuilder {
+        CommandInfoBuilder(Self::parse(OptionMap::new()))
+    }
 
-    fn try_from(value: OptionMap) -> Result<Self> {
+    fn parse(value: OptionMap) -> Self {
         let runas_gid = value.get("runas_gid")
             .unwrap_or_else(|_| unsafe { libc::getegid() });
 
         let runas_uid = value.get("runas_uid")
             .unwrap_or_else(|_| unsafe { libc::geteuid() });
 
-        Ok(Self {
+        Self {
             // in the event that the `-V` flag is passed to `sudo`,
             // there's no command
             command:       value.get("command").unwrap_or_default(),
@@ -253,13 +293,23 @@ impl TryFrom<OptionMap> for CommandInfo {
             runas_uid,
             runas_egid:    value.get("runas_egid").unwrap_or(runas_gid),
             runas_euid:    value.get("runas_euid").unwrap_or(runas_uid),
-            umask:         value.get("umask").unwrap_or(0o7777),
+            umask:         value.get_str("umask")
+                .and_then(|umask| mode_t::from_str_radix(umask, 8).ok())
+                .unwrap_or(0o7777),
+
+            log_servers:   value.get_str("log_servers")
+                .and_then(|servers| parse_list(servers, ','))
+                .unwrap_or_default(),
+            rlimits:       ResourceLimits::parse(&value),
 
+            apparmor_profile:  value.get("apparmor_profile")  .ok(),
             chroot:            value.get("chroot")            .ok(),
             close_from:        value.get("closefrom")         .ok(),
             cwd:               value.get("cwd")               .ok(),
             exec_background:   value.get("exec_background")   .unwrap_or(false),
             exec_fd:           value.get("execfd")            .ok(),
+            intercept:         value.get("intercept")         .unwrap_or(false),
+            intercept_verify:  value.get("intercept_verify")  .unwrap_or(false),
             iolog_compress:    value.get("iolog_compress")    .unwrap_or(false),
             iolog_group:       value.get("iolog_group")       .ok(),
             iolog_mode:        value.get("iolog_mode")        .ok(),
@@ -270,6 +320,7 @@ impl TryFrom<OptionMap> for CommandInfo {
             iolog_ttyin:       value.get("iolog_ttyin")       .unwrap_or(false),
             iolog_ttyout:      value.get("iolog_ttyout")      .unwrap_or(false),
             iolog_user:        value.get("iolog_user")        .ok(),
+            log_subcmds:       value.get("log_subcmds")       .unwrap_or(false),
             login_class:       value.get("login_class")       .ok(),
             nice:              value.get("nice")              .ok(),
             noexec:            value.get("noexec")            .unwrap_or(false),
@@ -279,6 +330,7 @@ impl TryFrom<OptionMap> for CommandInfo {
             selinux_role:      value.get("selinux_role")      .ok(),
             selinux_type:      value.get("selinux_type")      .ok(),
             set_utmp:          value.get("set_utmp")          .unwrap_or(false),
+            source:            value.get("source")            .ok(),
             sudoedit:          value.get("sudoedit")          .unwrap_or(false),
             sudoedit_checkdir: value.get("sudoedit_checkdir") .unwrap_or(true),
             sudoedit_follow:   value.get("sudoedit_follow")   .unwrap_or(false),
@@ -288,6 +340,247 @@ impl TryFrom<OptionMap> for CommandInfo {
             utmp_user:         value.get("utmp_user")         .ok(),
 
             raw: value,
-        })
+        }
+    }
+}
+
+impl TryFrom<OptionMap> for CommandInfo {
+    type Error = Error;
+
+    fn try_from(value: OptionMap) -> Result<Self> {
+        Ok(Self::parse(value))
+    }
+}
+
+impl From<&CommandInfo> for OptionMap {
+    fn from(info: &CommandInfo) -> Self {
+        fn insert_opt<T: ToSudoOption>(map: &mut OptionMap, k: &str, v: Option<T>) {
+            match v {
+                Some(v) => map.insert(k, v),
+                None    => drop(map.remove(k)),
+            }
+        }
+
+        // start from the raw options so that any keys not known to
+        // this library are passed through untouched
+        let mut map = info.raw.clone();
+
+        map.insert("command",    &info.command);
+        map.insert("runas_gid",  info.runas_gid);
+        map.insert("runas_uid",  info.runas_uid);
+        map.insert("runas_egid", info.runas_egid);
+        map.insert("runas_euid", info.runas_euid);
+
+        // sudo reads the umask as an octal value
+        if info.umask == 0o7777 {
+            drop(map.remove("umask"));
+        } else {
+            map.insert("umask", format!("0{:o}", info.umask));
+        }
+
+        insert_opt(&mut map, "apparmor_profile",  info.apparmor_profile.as_ref());
+        insert_opt(&mut map, "chroot",            info.chroot.as_ref());
+        insert_opt(&mut map, "closefrom",         info.close_from);
+        insert_opt(&mut map, "cwd",               info.cwd.as_ref());
+        map.insert(          "exec_background",   info.exec_background);
+        insert_opt(&mut map, "execfd",            info.exec_fd);
+        map.insert(          "intercept",         info.intercept);
+        map.insert(          "intercept_verify",  info.intercept_verify);
+        map.insert(          "iolog_compress",    info.iolog_compress);
+        insert_opt(&mut map, "iolog_group",       info.iolog_group.as_ref());
+        insert_opt(&mut map, "iolog_mode",        info.iolog_mode.as_ref());
+        insert_opt(&mut map, "iolog_path",        info.iolog_path.as_ref());
+        map.insert(          "iolog_stdin",       info.iolog_stdin);
+        map.insert(          "iolog_stdout",      info.iolog_stdout);
+        map.insert(          "iolog_stderr",      info.iolog_stderr);
+        map.insert(          "iolog_ttyin",       info.iolog_ttyin);
+        map.insert(          "iolog_ttyout",      info.iolog_ttyout);
+        insert_opt(&mut map, "iolog_user",        info.iolog_user.as_ref());
+        map.insert(          "log_subcmds",       info.log_subcmds);
+        insert_opt(&mut map, "login_class",       info.login_class.as_ref());
+        insert_opt(&mut map, "nice",              info.nice);
+        map.insert(          "noexec",            info.noexec);
+        map.insert(          "preserve_groups",   info.preserve_groups);
+        insert_opt(&mut map, "runas_groups",      info.runas_groups.as_ref());
+        insert_opt(&mut map, "selinux_role",      info.selinux_role.as_ref());
+        insert_opt(&mut map, "selinux_type",      info.selinux_type.as_ref());
+        map.insert(          "set_utmp",          info.set_utmp);
+        insert_opt(&mut map, "source",            info.source.as_ref());
+        map.insert(          "sudoedit",          info.sudoedit);
+        map.insert(          "sudoedit_checkdir", info.sudoedit_checkdir);
+        map.insert(          "sudoedit_follow",   info.sudoedit_follow);
+        insert_opt(&mut map, "timeout",           info.timeout);
+        insert_opt(&mut map, "umask_override",    info.umask_override);
+        map.insert(          "use_pty",           info.use_pty);
+        insert_opt(&mut map, "utmp_user",         info.utmp_user.as_ref());
+
+        if info.preserve_fds.is_empty() {
+            drop(map.remove("preserve_fds"));
+        } else {
+            map.insert("preserve_fds", &info.preserve_fds);
+        }
+
+        if info.log_servers.is_empty() {
+            drop(map.remove("log_servers"));
+        } else {
+            map.insert("log_servers", info.log_servers.join(","));
+        }
+
+        info.rlimits.insert_into(&mut map);
+
+        map
+    }
+}
+
+/// A builder for [`CommandInfo`](CommandInfo), used by policy plugins
+/// to describe to `sudo` how a command should be run. See the
+/// documentation on each field of `CommandInfo` for its meaning.
+///
+/// ```
+/// use sudo_plugin::options::{CommandInfo, OptionMap};
+///
+/// let info = CommandInfo::builder()
+///     .command("/usr/bin/id")
+///     .runas_uid(0_u32)
+///     .runas_gid(0_u32)
+///     .cwd("/")
+///     .use_pty(true)
+///     .build();
+///
+/// let options = OptionMap::from(&info);
+///
+/// assert_eq!(Some("/usr/bin/id"), options.get_str("command"));
+/// assert_eq!(Some("true"),        options.get_str("use_pty"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct CommandInfoBuilder(CommandInfo);
+
+impl CommandInfoBuilder {
+    setters! {
+        /// Sets `command`.
+        command : into PathBuf,
+        /// Sets `exec_background`.
+        exec_background : value bool,
+        /// Sets `intercept`.
+        intercept : value bool,
+        /// Sets `intercept_verify`.
+        intercept_verify : value bool,
+        /// Sets `iolog_compress`.
+        iolog_compress : value bool,
+        /// Sets `iolog_stdin`.
+        iolog_stdin : value bool,
+        /// Sets `iolog_stdout`.
+        iolog_stdout : value bool,
+        /// Sets `iolog_stderr`.
+        iolog_stderr : value bool,
+        /// Sets `iolog_ttyin`.
+        iolog_ttyin : value bool,
+        /// Sets `iolog_ttyout`.
+        iolog_ttyout : value bool,
+        /// Sets `log_servers`.
+        log_servers : into Vec<String>,
+        /// Sets `log_subcmds`.
+        log_subcmds : value bool,
+        /// Sets `noexec`.
+        noexec : value bool,
+        /// Sets `preserve_fds`.
+        preserve_fds : into Vec<RawFd>,
+        /// Sets `preserve_groups`.
+        preserve_groups : value bool,
+        /// Sets `rlimits`.
+        rlimits : value ResourceLimits,
+        /// Sets `runas_egid`.
+        runas_egid : value gid_t,
+        /// Sets `runas_euid`.
+        runas_euid : value uid_t,
+        /// Sets `set_utmp`.
+        set_utmp : value bool,
+        /// Sets `sudoedit`.
+        sudoedit : value bool,
+        /// Sets `sudoedit_checkdir`.
+        sudoedit_checkdir : value bool,
+        /// Sets `sudoedit_follow`.
+        sudoedit_follow : value bool,
+        /// Sets `umask`.
+        umask : value mode_t,
+        /// Sets `use_pty`.
+        use_pty : value bool,
+    }
+
+    optional_setters! {
+        /// Sets `apparmor_profile`.
+        apparmor_profile : into String,
+        /// Sets `chroot`.
+        chroot : into String,
+        /// Sets `close_from` (`closefrom`).
+        close_from : value u64,
+        /// Sets `cwd`.
+        cwd : into PathBuf,
+        /// Sets `exec_fd` (`execfd`).
+        exec_fd : value u64,
+        /// Sets `iolog_group`.
+        iolog_group : into String,
+        /// Sets `iolog_mode`.
+        iolog_mode : into String,
+        /// Sets `iolog_path`.
+        iolog_path : into String,
+        /// Sets `iolog_user`.
+        iolog_user : into String,
+        /// Sets `login_class`.
+        login_class : into String,
+        /// Sets `nice`.
+        nice : value u64,
+        /// Sets `runas_groups`.
+        runas_groups : into Vec<gid_t>,
+        /// Sets `selinux_role`.
+        selinux_role : into String,
+        /// Sets `selinux_type`.
+        selinux_type : into String,
+        /// Sets `source`.
+        source : into String,
+        /// Sets `timeout`.
+        timeout : value u64,
+        /// Sets `umask_override`.
+        umask_override : value bool,
+        /// Sets `utmp_user`.
+        utmp_user : into String,
+    }
+
+    /// Sets `runas_uid`. Unless `runas_euid` has been set explicitly,
+    /// it is changed to match.
+    #[must_use]
+    pub fn runas_uid(mut self, runas_uid: uid_t) -> Self {
+        if self.0.runas_euid == self.0.runas_uid {
+            self.0.runas_euid = runas_uid;
+        }
+
+        self.0.runas_uid = runas_uid;
+        self
+    }
+
+    /// Sets `runas_gid`. Unless `runas_egid` has been set explicitly,
+    /// it is changed to match.
+    #[must_use]
+    pub fn runas_gid(mut self, runas_gid: gid_t) -> Self {
+        if self.0.runas_egid == self.0.runas_gid {
+            self.0.runas_egid = runas_gid;
+        }
+
+        self.0.runas_gid = runas_gid;
+        self
+    }
+
+    /// Sets an additional option by name that isn't otherwise known to
+    /// this library.
+    #[must_use]
+    pub fn raw<T: ToSudoOption>(mut self, k: &str, v: T) -> Self {
+        self.0.raw.insert(k, v);
+        self
+    }
+
+    /// Returns the completed `CommandInfo`.
+    #[must_use]
+    pub fn build(self) -> CommandInfo {
+        self.0
     }
 }
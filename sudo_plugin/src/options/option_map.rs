@@ -13,10 +13,10 @@
 // permissions and limitations under the License.
 
 use crate::errors::{Result, Error};
-use crate::options::traits::FromSudoOption;
+use crate::options::traits::{FromSudoOption, ToSudoOption};
 
 use std::collections::HashMap;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::str;
 
 use libc::c_char;
@@ -29,16 +29,20 @@ const OPTIONS_SEPARATOR: u8 = b'=';
 /// Allows for automatic parsing of values into any type which implements
 /// the `FromSudoOption` trait as well as values into a `Vec` of any type
 /// which implements the `FromSudoOptionList` trait.
-#[derive(Clone, Debug)]
+///
+/// Options can also be written to, which is how policy plugins return
+/// values (e.g., `command_info`) back to `sudo`.
+#[derive(Clone, Debug, Default)]
 pub struct OptionMap(HashMap<Vec<u8>, Vec<u8>>);
 
-// TOOD: in policy plugins, some of these values can be written back to
-// by the plugin in order to change the execution of sudo itself (e.g.,
-// `command_info.chroot`, `command_info.cwd`, and others). We should
-// support that use-case, but I don't think the current design of the
-// API can be plausibly made to support it. This will probably require
-// a redesign of this entire thing, which I'm not really excited about.
 impl OptionMap {
+    /// Creates an empty `OptionMap`, suitable for building up a list
+    /// of options to be returned to `sudo`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     /// Initializes the `OptionMap` from a pointer to the options
     /// provided when `sudo` invokes the plugin's entry function. The
     /// format of these is a NUL-terminated array of NUL-terminated
@@ -126,6 +130,53 @@ impl OptionMap {
     pub fn get_bytes(&self, k: &[u8]) -> Option<&[u8]> {
         self.0.get(k).map(Vec::as_slice)
     }
+
+    /// Sets the value of a key to any type that implements the
+    /// `ToSudoOption` trait, replacing any existing value.
+    pub fn insert<T: ToSudoOption>(&mut self, k: &str, v: T) {
+        self.insert_bytes(k.as_bytes(), &v.to_sudo_option());
+    }
+
+    /// Sets a raw byte value using bytes as the key, replacing any
+    /// existing value. This is provided to allow plugins to write
+    /// values for keys when the value and/or key are not guaranteed to
+    /// be UTF-8 strings.
+    pub fn insert_bytes(&mut self, k: &[u8], v: &[u8]) {
+        drop(self.0.insert(k.to_owned(), v.to_owned()));
+    }
+
+    /// Removes a key from the map, returning its raw value if it was
+    /// present.
+    pub fn remove(&mut self, k: &str) -> Option<Vec<u8>> {
+        self.0.remove(k.as_bytes())
+    }
+
+    /// Serializes the map into a list of "key=value" strings in the
+    /// format `sudo` expects to receive them. Entries are sorted by key
+    /// so the output is deterministic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any key or value contains an interior NUL
+    /// byte, since such an entry can't be represented as a C string.
+    pub fn to_cstrings(&self) -> Result<Vec<CString>> {
+        let mut entries : Vec<_> = self.0.iter().collect();
+
+        entries.sort();
+
+        entries.into_iter().map(|(k, v)| {
+            let mut entry = Vec::with_capacity(k.len() + v.len() + 1);
+
+            entry.extend_from_slice(k);
+            entry.push(OPTIONS_SEPARATOR);
+            entry.extend_from_slice(v);
+
+            CString::new(entry).map_err(|_e| Error::OptionInvalid {
+                key:   String::from_utf8_lossy(k).into_owned(),
+                value: String::from_utf8_lossy(v).into_owned(),
+            })
+        }).collect()
+    }
 }
 
 #[cfg(test)]
@@ -276,4 +327,174 @@ mod tests {
         assert!(set.contains("3"));
         assert!(!set.contains("4"));
     }
+
+    #[test]
+    fn insert_serializes_common_types() {
+        let mut map = OptionMap::new();
+
+        map.insert("str",  "value");
+        map.insert("bool", true);
+        map.insert("i32",  -42_i32);
+        map.insert("path", PathBuf::from("/foo/bar"));
+        map.insert("ints", vec![1_u32, 2, 3]);
+
+        assert_eq!("value",    map.get_str("str") .unwrap());
+        assert_eq!("true",     map.get_str("bool").unwrap());
+        assert_eq!("-42",      map.get_str("i32") .unwrap());
+        assert_eq!("/foo/bar", map.get_str("path").unwrap());
+        assert_eq!("1,2,3",    map.get_str("ints").unwrap());
+
+        assert_eq!(vec![1, 2, 3], map.get::<Vec<u32>>("ints").unwrap());
+    }
+
+    #[test]
+    fn to_cstrings_round_trips() {
+        let mut map = OptionMap::new();
+
+        map.insert("b", "value=value");
+        map.insert("a", "value");
+        map.insert_bytes(b"c", b"\x80");
+
+        let strings = map.to_cstrings().unwrap();
+
+        assert_eq!(vec![
+            CString::new("a=value")      .unwrap(),
+            CString::new("b=value=value").unwrap(),
+            CString::new(&b"c=\x80"[..]) .unwrap(),
+        ], strings);
+
+        let mut ptrs : Vec<_> = strings.iter().map(|s| s.as_ptr()).collect();
+        ptrs.push(ptr::null());
+
+        let parsed = unsafe { OptionMap::from_raw(ptrs.as_ptr()) };
+
+        assert_eq!(map.0, parsed.0);
+    }
+
+    #[test]
+    fn to_cstrings_rejects_interior_nuls() {
+        let mut map = OptionMap::new();
+
+        map.insert("key", "val\0ue");
+
+        assert!(map.to_cstrings().is_err());
+    }
+
+    #[test]
+    fn command_info_round_trips() {
+        use crate::options::{CommandInfo, ResourceLimit, ResourceLimits};
+        use std::convert::TryFrom;
+
+        let info = CommandInfo::builder()
+            .command("/usr/bin/id")
+            .runas_uid(1000)
+            .runas_gid(1000)
+            .cwd("/tmp")
+            .umask(0o022)
+            .preserve_fds(vec![3, 4])
+            .use_pty(true)
+            .intercept(true)
+            .log_servers(vec!["logs1:30344".to_owned(), "logs2:30344".to_owned()])
+            .rlimits(ResourceLimits {
+                core:   Some(ResourceLimit { soft: Some(0), hard: None }),
+                nofile: Some(ResourceLimit { soft: Some(1024), hard: Some(4096) }),
+                ..ResourceLimits::default()
+            })
+            .raw("unknown_key", "value")
+            .build();
+
+        let map    = OptionMap::from(&info);
+        let parsed = CommandInfo::try_from(map.clone()).unwrap();
+
+        assert_eq!("022",                     map.get_str("umask").unwrap());
+        assert_eq!("logs1:30344,logs2:30344", map.get_str("log_servers").unwrap());
+        assert_eq!("0,infinity",              map.get_str("rlimit_core").unwrap());
+        assert_eq!(None,                      map.get_str("rlimit_stack"));
+
+        assert_eq!(PathBuf::from("/usr/bin/id"), parsed.command);
+        assert_eq!(1000,                         parsed.runas_uid);
+        assert_eq!(1000,                         parsed.runas_euid);
+        assert_eq!(1000,                         parsed.runas_gid);
+        assert_eq!(1000,                         parsed.runas_egid);
+        assert_eq!(Some(PathBuf::from("/tmp")),  parsed.cwd);
+        assert_eq!(0o022,                        parsed.umask);
+        assert_eq!(vec![3, 4],                   parsed.preserve_fds);
+        assert_eq!(true,                         parsed.use_pty);
+        assert_eq!(None,                         parsed.chroot);
+        assert_eq!(true,                         parsed.intercept);
+        assert_eq!(false,                        parsed.log_subcmds);
+        assert_eq!(info.log_servers,             parsed.log_servers);
+        assert_eq!(info.rlimits,                 parsed.rlimits);
+        assert_eq!("value", parsed.raw.get_str("unknown_key").unwrap());
+    }
+
+    #[test]
+    fn user_info_round_trips() {
+        use crate::options::UserInfo;
+        use std::convert::TryFrom;
+
+        let info = UserInfo::builder()
+            .user("alice")
+            .uid(1000)
+            .gid(1000)
+            .groups(vec![1000, 27])
+            .host("example.com")
+            .tty("/dev/pts/3")
+            .ttydev(34819)
+            .build();
+
+        let map    = OptionMap::from(&info);
+        let parsed = UserInfo::try_from(map.clone()).unwrap();
+
+        assert_eq!("1000,27", map.get_str("groups").unwrap());
+        assert_eq!(None,      map.get_str("umask"));
+
+        assert_eq!("alice",                           parsed.user);
+        assert_eq!(1000,                              parsed.uid);
+        assert_eq!(1000,                              parsed.euid);
+        assert_eq!(1000,                              parsed.gid);
+        assert_eq!(1000,                              parsed.egid);
+        assert_eq!(vec![1000, 27],                    parsed.groups);
+        assert_eq!("example.com",                     parsed.host);
+        assert_eq!(Some(PathBuf::from("/dev/pts/3")), parsed.tty);
+        assert_eq!(Some(34819),                       parsed.ttydev);
+        assert_eq!(80,                                parsed.cols);
+        assert_eq!(24,                                parsed.lines);
+    }
+
+    #[test]
+    fn settings_round_trips() {
+        use crate::options::Settings;
+        use crate::options::settings::NetAddr;
+        use std::convert::TryFrom;
+
+        let settings = Settings::builder()
+            .plugin_dir("/usr/libexec/sudo")
+            .runas_user("root")
+            .close_from(5)
+            .login_shell(true)
+            .run_cwd("/srv")
+            .update_ticket(false)
+            .network_addrs(vec![
+                NetAddr::from_sudo_option("192.168.1.2/255.255.255.0").unwrap(),
+                NetAddr::from_sudo_option("fe80::1/ffff:ffff:ffff:ffff::").unwrap(),
+            ])
+            .build();
+
+        let map    = OptionMap::from(&settings);
+        let parsed = Settings::try_from(map.clone()).unwrap();
+
+        assert_eq!("5",    map.get_str("closefrom").unwrap());
+        assert_eq!("sudo", map.get_str("progname").unwrap());
+
+        assert_eq!("/usr/libexec/sudo",     parsed.plugin_dir);
+        assert_eq!(Some("root".to_owned()), parsed.runas_user);
+        assert_eq!(None,                    parsed.runas_group);
+        assert_eq!(Some(5),                 parsed.close_from);
+        assert_eq!(true,                    parsed.login_shell);
+        assert_eq!(Some("/srv".into()),     parsed.run_cwd);
+        assert_eq!(None,                    parsed.run_chroot);
+        assert_eq!(false,                   parsed.update_ticket);
+        assert_eq!(settings.network_addrs,  parsed.network_addrs);
+    }
 }
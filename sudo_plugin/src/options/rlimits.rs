@@ -0,0 +1,150 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::options::OptionMap;
+use crate::options::traits::{FromSudoOption, ToSudoOption};
+
+use std::num::ParseIntError;
+
+/// A soft and hard limit on a single resource, as given in one of the
+/// `rlimit_*` options. A limit of `None` is unlimited (`infinity`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ResourceLimit {
+    /// The soft limit, which is the value the kernel enforces.
+    pub soft: Option<u64>,
+
+    /// The hard limit, which acts as a ceiling for the soft limit.
+    pub hard: Option<u64>,
+}
+
+impl FromSudoOption for ResourceLimit {
+    type Err = ParseIntError;
+
+    // limits are given as `soft,hard`, though a single value sets both
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        fn limit(s: &str) -> ::std::result::Result<Option<u64>, ParseIntError> {
+            match s {
+                "infinity" => Ok(None),
+                _          => s.parse().map(Some),
+            }
+        }
+
+        let mut split = s.splitn(2, ',');
+
+        let soft = limit(split.next().unwrap_or_default())?;
+        let hard = match split.next() {
+            Some(hard) => limit(hard)?,
+            None       => soft,
+        };
+
+        Ok(Self {
+            soft,
+            hard,
+        })
+    }
+}
+
+impl ToSudoOption for ResourceLimit {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        fn limit(limit: Option<u64>) -> String {
+            limit.map_or_else(|| "infinity".into(), |limit| limit.to_string())
+        }
+
+        format!("{},{}", limit(self.soft), limit(self.hard)).into_bytes()
+    }
+}
+
+macro_rules! resource_limits {
+    ( $( $(#[$attr:meta])* $field:ident : $key:literal ),* $(,)* ) => {
+        /// The resource limits given in the `rlimit_*` options, one for
+        /// each resource. Resources without a corresponding option are
+        /// `None`, in which case `sudo` leaves that limit unchanged.
+        #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+        pub struct ResourceLimits {
+            $( $(#[$attr])* pub $field: Option<ResourceLimit>, )*
+        }
+
+        impl ResourceLimits {
+            pub(crate) fn parse(value: &OptionMap) -> Self {
+                Self {
+                    $( $field: value.get($key).ok(), )*
+                }
+            }
+
+            pub(crate) fn insert_into(&self, map: &mut OptionMap) {
+                $(
+                    match self.$field {
+                        Some(limit) => map.insert($key, limit),
+                        None        => drop(map.remove($key)),
+                    }
+                )*
+            }
+        }
+    };
+}
+
+resource_limits! {
+    /// The maximum size of the process's virtual memory (`rlimit_as`).
+    address_space : "rlimit_as",
+
+    /// The maximum size of a core file (`rlimit_core`).
+    core : "rlimit_core",
+
+    /// The maximum amount of CPU time, in seconds (`rlimit_cpu`).
+    cpu : "rlimit_cpu",
+
+    /// The maximum size of the process's data segment (`rlimit_data`).
+    data : "rlimit_data",
+
+    /// The maximum size of a file the process may create
+    /// (`rlimit_fsize`).
+    file_size : "rlimit_fsize",
+
+    /// The maximum number of file locks (`rlimit_locks`).
+    locks : "rlimit_locks",
+
+    /// The maximum number of bytes of memory that may be locked into
+    /// RAM (`rlimit_memlock`).
+    memlock : "rlimit_memlock",
+
+    /// One more than the maximum file descriptor number that may be
+    /// opened (`rlimit_nofile`).
+    nofile : "rlimit_nofile",
+
+    /// The maximum number of processes for the user (`rlimit_nproc`).
+    nproc : "rlimit_nproc",
+
+    /// The maximum resident set size (`rlimit_rss`).
+    rss : "rlimit_rss",
+
+    /// The maximum size of the process's stack (`rlimit_stack`).
+    stack : "rlimit_stack",
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_limits_parse_soft_and_hard_values() {
+        let limit = |s| ResourceLimit::from_sudo_option(s).ok();
+
+        assert_eq!(Some(ResourceLimit { soft: Some(1024), hard: Some(4096) }), limit("1024,4096"));
+        assert_eq!(Some(ResourceLimit { soft: Some(0),    hard: None       }), limit("0,infinity"));
+        assert_eq!(Some(ResourceLimit { soft: None,       hard: None       }), limit("infinity"));
+        assert_eq!(Some(ResourceLimit { soft: Some(8),    hard: Some(8)    }), limit("8"));
+        assert_eq!(None,                                                       limit("user"));
+        assert_eq!(None,                                                       limit("1,2,3"));
+    }
+}
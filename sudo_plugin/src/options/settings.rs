@@ -14,17 +14,19 @@
 
 use crate::errors::{Result, Error};
 use crate::options::OptionMap;
-use crate::options::traits::{FromSudoOption, FromSudoOptionList};
+use crate::options::traits::{FromSudoOption, FromSudoOptionList, ToSudoOption};
 
 use std::convert::TryFrom;
+use std::ffi::OsString;
 use std::net::{AddrParseError, IpAddr};
+use std::path::PathBuf;
 use std::str;
 
 /// A vector of user-supplied sudo settings. These settings correspond
 /// to options the user specified when running sudo. As such, they will
 /// only be present when the corresponding option has been specified on
 /// the command line.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Settings {
     /// Authentication type, if specified by the -a option, to use on systems
     /// where BSD authentication is supported.
@@ -122,6 +124,14 @@ pub struct Settings {
     /// 1.4.
     pub remote_host: Option<String>,
 
+    /// The root directory to change to before running the command, if
+    /// specified via the -R option.
+    pub run_chroot: Option<PathBuf>,
+
+    /// The directory to change to before running the command, if
+    /// specified via the -D option.
+    pub run_cwd: Option<PathBuf>,
+
     /// Set to true if the user specified the -s option, indicating that the
     /// user wishes to run a shell.
     pub run_shell: bool,
@@ -158,6 +168,10 @@ pub struct Settings {
     /// plugin-specific.
     pub timeout: Option<String>,
 
+    /// Set to false if the user specified the -N option, indicating that
+    /// sudo should not update the user's cached credentials on success.
+    pub update_ticket: bool,
+
     /// The raw underlying [`OptionMap`](OptionMap) to retrieve additional
     /// values that may not have been known at the time of the authorship of
     /// this file.
@@ -165,119 +179,141 @@ pub struct Settings {
 }
 
 impl Settings {
-    // TODO: surely this can be made more cleanly; also, it would be
-    // great if we could actually get the full original `sudo`
-    // invocation without having to reconstruct it by hand
+    /// Reconstructs the command-line flags `sudo` was invoked with,
+    /// from the settings it reports. Each element is a single word of
+    /// `argv`, so flags that take a value (e.g., `--user root`) are
+    /// followed by a separate element holding that value.
+    ///
+    /// Flags are always given in their long form, and in a fixed order
+    /// rather than the order they were typed in.
     //
-    // TODO: maybe if /proc/$$/cmd exists I can prefer to use it
+    // TODO: it would be great if we could actually get the full
+    // original `sudo` invocation without having to reconstruct it by
+    // hand; maybe if /proc/$$/cmd exists I can prefer to use it
     #[must_use]
-    pub fn flags(&self) -> Vec<Vec<u8>> {
-        let mut flags: Vec<Vec<u8>> = vec![];
+    pub fn flags(&self) -> Vec<OsString> {
+        let mut flags: Vec<OsString> = vec![];
+
+        let mut flag = |name: &str, value: Option<&str>| {
+            flags.push(name.into());
+            flags.extend(value.map(OsString::from));
+        };
 
         // `sudoedit` is set if the flag was provided *or* if sudo
         // was invoked as `sudoedit` directly; try our best to intrepret
         // this case, although we'll technically get it wrong in the
         // case of `sudoedit -e ...`
         if self.sudoedit && self.progname != "sudoedit" {
-            flags.push(b"--edit".to_vec());
+            flag("--edit", None);
         }
 
         if let Some(ref runas_user) = self.runas_user {
-            let mut flag = b"--user ".to_vec();
-            flag.extend_from_slice(runas_user.as_bytes());
-
-            flags.push(flag);
+            flag("--user", Some(runas_user));
         }
 
         if let Some(ref runas_group) = self.runas_group {
-            let mut flag = b"--group ".to_vec();
-            flag.extend_from_slice(runas_group.as_bytes());
-
-            flags.push(flag);
+            flag("--group", Some(runas_group));
         }
 
         if let Some(ref prompt) = self.prompt {
-            let mut flag = b"--prompt ".to_vec();
-            flag.extend_from_slice(prompt.as_bytes());
-
-            flags.push(flag);
+            flag("--prompt", Some(prompt));
         }
 
         if self.login_shell {
-            flags.push(b"--login".to_vec());
+            flag("--login", None);
         }
 
         if self.run_shell {
-            flags.push(b"--shell".to_vec());
+            flag("--shell", None);
         }
 
         if self.set_home {
-            flags.push(b"--set-home".to_vec());
+            flag("--set-home", None);
         }
 
         if self.preserve_environment {
-            flags.push(b"--preserve-env".to_vec());
+            flag("--preserve-env", None);
         }
 
         if self.preserve_groups {
-            flags.push(b"--preserve-groups".to_vec());
+            flag("--preserve-groups", None);
         }
 
         if self.ignore_ticket {
-            flags.push(b"--reset-timestamp".to_vec());
+            flag("--reset-timestamp", None);
+        }
+
+        if !self.update_ticket {
+            flag("--no-update", None);
         }
 
         if self.noninteractive {
-            flags.push(b"--non-interactive".to_vec());
+            flag("--non-interactive", None);
         }
 
         if let Some(ref login_class) = self.login_class {
-            let mut flag = b"--login-class ".to_vec();
-            flag.extend_from_slice(login_class.as_bytes());
-
-            flags.push(flag);
+            flag("--login-class", Some(login_class));
         }
 
         if let Some(ref selinux_role) = self.selinux_role {
-            let mut flag = b"--role ".to_vec();
-            flag.extend_from_slice(selinux_role.as_bytes());
-
-            flags.push(flag);
+            flag("--role", Some(selinux_role));
         }
 
         if let Some(ref selinux_type) = self.selinux_type {
-            let mut flag = b"--type ".to_vec();
-            flag.extend_from_slice(selinux_type.as_bytes());
-
-            flags.push(flag);
+            flag("--type", Some(selinux_type));
         }
 
         if let Some(ref bsd_auth_type) = self.bsd_auth_type {
-            let mut flag = b"--auth-type ".to_vec();
-            flag.extend_from_slice(bsd_auth_type.as_bytes());
-
-            flags.push(flag);
+            flag("--auth-type", Some(bsd_auth_type));
         }
 
         if let Some(close_from) = self.close_from {
-            let mut flag = b"--close-from ".to_vec();
-            flag.extend_from_slice(close_from.to_string().as_bytes());
+            flag("--close-from", Some(&close_from.to_string()));
+        }
+
+        if let Some(ref timeout) = self.timeout {
+            flag("--command-timeout", Some(timeout));
+        }
 
-            flags.push(flag);
+        if let Some(ref remote_host) = self.remote_host {
+            flag("--host", Some(remote_host));
+        }
+
+        // paths aren't necessarily UTF-8, so these can't go through
+        // `flag` above
+        if let Some(ref run_chroot) = self.run_chroot {
+            flags.push("--chroot".into());
+            flags.push(run_chroot.into());
+        }
+
+        if let Some(ref run_cwd) = self.run_cwd {
+            flags.push("--chdir".into());
+            flags.push(run_cwd.into());
         }
 
         flags
     }
 }
 
-impl TryFrom<OptionMap> for Settings {
-    type Error = Error;
+impl Settings {
+    /// Returns a [`SettingsBuilder`](SettingsBuilder) for constructing
+    /// `Settings` by hand (e.g., in tests). Any values left unset take
+    /// on the same defaults they would if `sudo` had omitted them, and
+    /// `progname` defaults to `sudo`.
+    #[must_use]
+    pub fn builder() -> SettingsBuilder {
+        let mut settings = Self::parse(OptionMap::new());
 
-    fn try_from(value: OptionMap) -> Result<Self> {
-        Ok(Self {
-            plugin_dir:  value.get("plugin_dir")?,
-            plugin_path: value.get("plugin_path")?,
-            progname:    value.get("progname")?,
+        settings.progname = "sudo".into();
+
+        SettingsBuilder(settings)
+    }
+
+    fn parse(value: OptionMap) -> Self {
+        Self {
+            plugin_dir:  value.get("plugin_dir") .unwrap_or_default(),
+            plugin_path: value.get("plugin_path").unwrap_or_default(),
+            progname:    value.get("progname")   .unwrap_or_default(),
 
             bsd_auth_type:        value.get("bsd_auth_type")       .ok(),
             close_from:           value.get("closefrom")           .ok(),
@@ -294,6 +330,8 @@ impl TryFrom<OptionMap> for Settings {
             preserve_groups:      value.get("preserve_groups")     .unwrap_or(false),
             prompt:               value.get("prompt")              .ok(),
             remote_host:          value.get("remote_host")         .ok(),
+            run_chroot:           value.get("runchroot")           .ok(),
+            run_cwd:              value.get("runcwd")              .ok(),
             run_shell:            value.get("run_shell")           .unwrap_or(false),
             runas_group:          value.get("runas_group")         .ok(),
             runas_user:           value.get("runas_user")          .ok(),
@@ -302,12 +340,181 @@ impl TryFrom<OptionMap> for Settings {
             set_home:             value.get("set_home")            .unwrap_or(false),
             sudoedit:             value.get("sudoedit")            .unwrap_or(false),
             timeout:              value.get("timeout")             .ok(),
+            update_ticket:        value.get("update_ticket")       .unwrap_or(true),
 
             raw: value,
+        }
+    }
+}
+
+impl TryFrom<OptionMap> for Settings {
+    type Error = Error;
+
+    fn try_from(value: OptionMap) -> Result<Self> {
+        // these are always provided by sudo, so their absence is an
+        // error rather than something to paper over with a default
+        Ok(Self {
+            plugin_dir:  value.get("plugin_dir")?,
+            plugin_path: value.get("plugin_path")?,
+            progname:    value.get("progname")?,
+
+            ..Self::parse(value)
         })
     }
 }
 
+impl From<&Settings> for OptionMap {
+    fn from(settings: &Settings) -> Self {
+        fn insert_opt<T: ToSudoOption>(map: &mut OptionMap, k: &str, v: Option<T>) {
+            match v {
+                Some(v) => map.insert(k, v),
+                None    => drop(map.remove(k)),
+            }
+        }
+
+        // start from the raw options so that any keys not known to
+        // this library are passed through untouched
+        let mut map = settings.raw.clone();
+
+        map.insert("plugin_dir",  &settings.plugin_dir);
+        map.insert("plugin_path", &settings.plugin_path);
+        map.insert("progname",    &settings.progname);
+
+        insert_opt(&mut map, "bsd_auth_type",        settings.bsd_auth_type.as_ref());
+        insert_opt(&mut map, "closefrom",            settings.close_from);
+        insert_opt(&mut map, "debug_flags",          settings.debug_flags.as_ref());
+        insert_opt(&mut map, "debug_level",          settings.debug_level);
+        map.insert(          "ignore_ticket",        settings.ignore_ticket);
+        map.insert(          "implied_shell",        settings.implied_shell);
+        insert_opt(&mut map, "login_class",          settings.login_class.as_ref());
+        map.insert(          "login_shell",          settings.login_shell);
+        insert_opt(&mut map, "max_groups",           settings.max_groups);
+        map.insert(          "noninteractive",       settings.noninteractive);
+        map.insert(          "preserve_environment", settings.preserve_environment);
+        map.insert(          "preserve_groups",      settings.preserve_groups);
+        insert_opt(&mut map, "prompt",               settings.prompt.as_ref());
+        insert_opt(&mut map, "remote_host",          settings.remote_host.as_ref());
+        insert_opt(&mut map, "runchroot",            settings.run_chroot.as_ref());
+        insert_opt(&mut map, "runcwd",               settings.run_cwd.as_ref());
+        map.insert(          "run_shell",            settings.run_shell);
+        insert_opt(&mut map, "runas_group",          settings.runas_group.as_ref());
+        insert_opt(&mut map, "runas_user",           settings.runas_user.as_ref());
+        insert_opt(&mut map, "selinux_role",         settings.selinux_role.as_ref());
+        insert_opt(&mut map, "selinux_type",         settings.selinux_type.as_ref());
+        map.insert(          "set_home",             settings.set_home);
+        map.insert(          "sudoedit",             settings.sudoedit);
+        insert_opt(&mut map, "timeout",              settings.timeout.as_ref());
+        map.insert(          "update_ticket",        settings.update_ticket);
+
+        if settings.network_addrs.is_empty() {
+            drop(map.remove("network_addrs"));
+        } else {
+            map.insert("network_addrs", &settings.network_addrs);
+        }
+
+        map
+    }
+}
+
+/// A builder for [`Settings`](Settings). See the documentation on each
+/// field of `Settings` for its meaning.
+///
+/// ```
+/// use sudo_plugin::options::{OptionMap, Settings};
+///
+/// let settings = Settings::builder()
+///     .runas_user("root")
+///     .login_shell(true)
+///     .build();
+///
+/// let options = OptionMap::from(&settings);
+///
+/// assert_eq!(Some("root"), options.get_str("runas_user"));
+/// assert_eq!(Some("true"), options.get_str("login_shell"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct SettingsBuilder(Settings);
+
+impl SettingsBuilder {
+    setters! {
+        /// Sets `ignore_ticket`.
+        ignore_ticket : value bool,
+        /// Sets `implied_shell`.
+        implied_shell : value bool,
+        /// Sets `login_shell`.
+        login_shell : value bool,
+        /// Sets `network_addrs`.
+        network_addrs : into Vec<NetAddr>,
+        /// Sets `noninteractive`.
+        noninteractive : value bool,
+        /// Sets `plugin_dir`.
+        plugin_dir : into String,
+        /// Sets `plugin_path`.
+        plugin_path : into String,
+        /// Sets `preserve_environment`.
+        preserve_environment : value bool,
+        /// Sets `preserve_groups`.
+        preserve_groups : value bool,
+        /// Sets `progname`.
+        progname : into String,
+        /// Sets `run_shell`.
+        run_shell : value bool,
+        /// Sets `set_home`.
+        set_home : value bool,
+        /// Sets `sudoedit`.
+        sudoedit : value bool,
+        /// Sets `update_ticket`.
+        update_ticket : value bool,
+    }
+
+    optional_setters! {
+        /// Sets `bsd_auth_type`.
+        bsd_auth_type : into String,
+        /// Sets `close_from` (`closefrom`).
+        close_from : value u64,
+        /// Sets `debug_flags`.
+        debug_flags : into String,
+        /// Sets `debug_level`.
+        debug_level : value u64,
+        /// Sets `login_class`.
+        login_class : into String,
+        /// Sets `max_groups`.
+        max_groups : value u64,
+        /// Sets `prompt`.
+        prompt : into String,
+        /// Sets `remote_host`.
+        remote_host : into String,
+        /// Sets `run_chroot` (`runchroot`).
+        run_chroot : into PathBuf,
+        /// Sets `run_cwd` (`runcwd`).
+        run_cwd : into PathBuf,
+        /// Sets `runas_group`.
+        runas_group : into String,
+        /// Sets `runas_user`.
+        runas_user : into String,
+        /// Sets `selinux_role`.
+        selinux_role : into String,
+        /// Sets `selinux_type`.
+        selinux_type : into String,
+        /// Sets `timeout`.
+        timeout : into String,
+    }
+
+    /// Sets an additional option by name that isn't otherwise known to
+    /// this library.
+    #[must_use]
+    pub fn raw<T: ToSudoOption>(mut self, k: &str, v: T) -> Self {
+        self.0.raw.insert(k, v);
+        self
+    }
+
+    /// Returns the completed `Settings`.
+    #[must_use]
+    pub fn build(self) -> Settings {
+        self.0
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct NetAddr {
     pub addr: IpAddr,
@@ -340,3 +547,9 @@ impl FromSudoOption for NetAddr {
 impl FromSudoOptionList for NetAddr {
     const SEPARATOR: char = ' ';
 }
+
+impl ToSudoOption for NetAddr {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        format!("{}/{}", self.addr, self.mask).into_bytes()
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Support for parsing a plugin's options into a struct of typed
+//! values, usually through `#[derive(SudoOptions)]`.
+
+use super::OptionMap;
+use super::traits::FromSudoOption;
+use crate::errors::Error;
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::str;
+
+/// Types that can be parsed from a plugin's options.
+///
+/// With the `derive` feature enabled, this can be derived for structs
+/// with named fields, each of which is parsed with
+/// [`FromSudoOption`](super::FromSudoOption):
+///
+/// ```ignore
+/// use sudo_plugin::options::{OptionMap, SudoOptions};
+///
+/// #[derive(SudoOptions)]
+/// struct Options {
+///     #[sudo_option(required)]
+///     socket_dir: PathBuf,
+///
+///     #[sudo_option(default = "30", validate = "is_positive")]
+///     timeout: u64,
+///
+///     #[sudo_option(key = "groups", separator = ':')]
+///     gids: Vec<u32>,
+///
+///     log_path: Option<PathBuf>,
+/// }
+///
+/// fn is_positive(timeout: &u64) -> bool {
+///     *timeout > 0
+/// }
+///
+/// let options = Options::from_option_map(&plugin_options)?;
+/// ```
+pub trait SudoOptions: Sized {
+    /// Parses options out of `map`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error::OptionsInvalid`](Error::OptionsInvalid)
+    /// listing every option that was required but missing, or that
+    /// couldn't be parsed or validated.
+    fn from_option_map(map: &OptionMap) -> Result<Self, Error>;
+}
+
+/// Collections that can be parsed from an option whose elements are
+/// split on an arbitrary separator.
+#[doc(hidden)]
+pub trait SudoOptionCollection {
+    type Item: FromSudoOption;
+}
+
+impl<T: FromSudoOption> SudoOptionCollection for Vec<T> {
+    type Item = T;
+}
+
+impl<T, S> SudoOptionCollection for HashSet<T, S>
+where
+    T: FromSudoOption + Eq + Hash,
+    S: BuildHasher + Default,
+{
+    type Item = T;
+}
+
+/// A validator for `#[sudo_option(validate = "...")]` that rejects
+/// zero (or any other type's default value), for options where it
+/// would be meaningless.
+///
+/// ```ignore
+/// #[sudo_option(default = "4096", validate = "sudo_plugin::options::is_nonzero")]
+/// buffer_size: usize,
+/// ```
+#[must_use]
+pub fn is_nonzero<T: Default + PartialEq>(value: &T) -> bool {
+    *value != T::default()
+}
+
+/// Parses the option `key` out of `map` with `parse`, recording an
+/// error if it's missing (and `required`) or if it can't be parsed.
+#[doc(hidden)]
+pub fn parse<T, F>(
+    map:      &OptionMap,
+    key:      &str,
+    required: bool,
+    errors:   &mut Vec<Error>,
+    parse:    F,
+) -> Option<T>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    let bytes = map.get_bytes(key.as_bytes());
+
+    if bytes.is_none() && required {
+        errors.push(Error::OptionMissing { key: key.into() });
+    }
+
+    let bytes = bytes?;
+
+    let value = str::from_utf8(bytes).ok().and_then(parse);
+
+    if value.is_none() {
+        errors.push(Error::OptionInvalid {
+            key:   key.into(),
+            value: String::from_utf8_lossy(bytes).into_owned(),
+        });
+    }
+
+    value
+}
+
+/// Parses a collection from `value`, whose elements are separated by
+/// `separator`.
+#[doc(hidden)]
+#[must_use]
+pub fn parse_list<C>(value: &str, separator: char) -> Option<C>
+where
+    C: SudoOptionCollection + FromIterator<C::Item>,
+{
+    value.split(separator)
+        .map(|element| C::Item::from_sudo_option(element).ok())
+        .collect()
+}
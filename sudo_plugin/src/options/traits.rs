@@ -13,16 +13,26 @@
 // permissions and limitations under the License.
 
 use std::collections::HashSet;
-use std::hash::Hash;
-use std::path::PathBuf;
+use std::hash::{BuildHasher, Hash};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Clone, Copy, Debug)]
 pub struct ParseListError<T>(T);
 
+/// Types that can be parsed from the value of an option provided by
+/// `sudo`.
 pub trait FromSudoOption: Sized {
+    /// The error returned when a value can't be parsed.
     type Err;
 
+    /// Parses a value of this type from the value of an option.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value can't be parsed.
     fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err>;
 }
 
@@ -99,6 +109,23 @@ impl FromSudoOption for u64 {
     }
 }
 
+impl FromSudoOption for usize {
+    type Err = ::std::num::ParseIntError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        FromStr::from_str(s)
+    }
+}
+
+/// Durations are given as a whole number of seconds.
+impl FromSudoOption for Duration {
+    type Err = ::std::num::ParseIntError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        FromStr::from_str(s).map(Self::from_secs)
+    }
+}
+
 impl FromSudoOption for String {
     type Err = ::std::string::ParseError;
 
@@ -136,9 +163,10 @@ where
     }
 }
 
-impl<T> FromSudoOption for HashSet<T>
+impl<T, S> FromSudoOption for HashSet<T, S>
 where
     T: Eq + Hash + FromSudoOption + FromSudoOptionList,
+    S: BuildHasher + Default,
 {
     type Err = ParseListError<T::Err>;
 
@@ -147,9 +175,14 @@ where
     }
 }
 
+/// Types that can be parsed from a list of values in a single option
+/// (e.g., as a `Vec` of them).
 pub trait FromSudoOptionList: Sized {
+    /// The character that separates values in the list.
     const SEPARATOR: char = ',';
 
+    /// Splits the value of an option into its individual elements.
+    #[must_use]
     fn from_sudo_option_list(s: &str) -> Vec<&str> {
         s.split(|b| b == Self::SEPARATOR).collect()
     }
@@ -163,4 +196,117 @@ impl FromSudoOptionList for i32 {}
 impl FromSudoOptionList for u32 {}
 impl FromSudoOptionList for i64 {}
 impl FromSudoOptionList for u64 {}
+impl FromSudoOptionList for usize {}
 impl FromSudoOptionList for PathBuf {}
+
+pub trait ToSudoOption {
+    fn to_sudo_option(&self) -> Vec<u8>;
+}
+
+impl<T: ToSudoOption + ?Sized> ToSudoOption for &T {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        (**self).to_sudo_option()
+    }
+}
+
+impl ToSudoOption for bool {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for i8 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for u8 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for i16 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for u16 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for i32 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for u32 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for i64 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for u64 {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl ToSudoOption for str {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl ToSudoOption for String {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl ToSudoOption for Path {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.as_os_str().as_bytes().to_vec()
+    }
+}
+
+impl ToSudoOption for PathBuf {
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.as_path().to_sudo_option()
+    }
+}
+
+impl<T> ToSudoOption for [T]
+where
+    T: ToSudoOption + FromSudoOptionList,
+{
+    fn to_sudo_option(&self) -> Vec<u8> {
+        let mut separator = [0; 4];
+        let separator     = T::SEPARATOR.encode_utf8(&mut separator).as_bytes();
+
+        self.iter()
+            .map(ToSudoOption::to_sudo_option)
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+impl<T> ToSudoOption for Vec<T>
+where
+    T: ToSudoOption + FromSudoOptionList,
+{
+    fn to_sudo_option(&self) -> Vec<u8> {
+        self.as_slice().to_sudo_option()
+    }
+}
@@ -13,15 +13,16 @@
 // permissions and limitations under the License.
 
 use crate::errors::{Result, Error};
-use crate::options::OptionMap;
+use crate::options::{OptionMap, ResourceLimits};
+use crate::options::traits::ToSudoOption;
 
 use std::convert::TryFrom;
 use std::path::PathBuf;
 
-use libc::{gid_t, pid_t, uid_t};
+use libc::{self, dev_t, gid_t, pid_t, uid_t};
 
 /// A vector of information about the user running the command.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct UserInfo {
     /// The number of columns the user's terminal supports. If there is no
     /// terminal device available, a default value of 80 is used.
@@ -63,6 +64,11 @@ pub struct UserInfo {
     /// starting with API version 1.2.
     pub ppid: pid_t,
 
+    /// The resource limits of the sudo process, from the `rlimit_*`
+    /// options. Unlike those in `command_info`, these are the limits in
+    /// effect when the user invoked sudo.
+    pub rlimits: ResourceLimits,
+
     /// The session ID of the running sudo process or 0 if sudo is not part of
     /// a POSIX job control session. Only available starting with API version
     /// 1.2.
@@ -78,6 +84,10 @@ pub struct UserInfo {
     /// “tty=”.
     pub tty: Option<PathBuf>,
 
+    /// The device number of the user's terminal, if there is one. This
+    /// identifies the terminal even when its path can't be determined.
+    pub ttydev: Option<dev_t>,
+
     /// The real user-ID of the user invoking sudo.
     pub uid: uid_t,
 
@@ -94,10 +104,54 @@ pub struct UserInfo {
     pub raw: OptionMap,
 }
 
+impl UserInfo {
+    /// Returns a [`UserInfoBuilder`](UserInfoBuilder) for constructing
+    /// a `UserInfo` by hand (e.g., in tests). Any values left unset
+    /// take on those of the current process where they can be
+    /// determined, and are otherwise empty.
+    #[must_use]
+    pub fn builder() -> UserInfoBuilder {
+        UserInfoBuilder(Self::parse(OptionMap::new()))
+    }
+
+    fn parse(value: OptionMap) -> Self {
+        let gid = value.get("gid")
+            .unwrap_or_else(|_| unsafe { libc::getgid() });
+
+        Self {
+            cwd:    value.get("cwd")   .unwrap_or_else(|_| "/".into()),
+            egid:   value.get("egid")  .unwrap_or_else(|_| unsafe { libc::getegid() }),
+            euid:   value.get("euid")  .unwrap_or_else(|_| unsafe { libc::geteuid() }),
+            gid,
+            groups: value.get("groups").unwrap_or_else(|_| vec![gid]),
+            host:   value.get("host")  .unwrap_or_default(),
+            pgid:   value.get("pgid")  .unwrap_or_else(|_| unsafe { libc::getpgrp() }),
+            pid:    value.get("pid")   .unwrap_or_else(|_| unsafe { libc::getpid() }),
+            ppid:   value.get("ppid")  .unwrap_or_else(|_| unsafe { libc::getppid() }),
+            uid:    value.get("uid")   .unwrap_or_else(|_| unsafe { libc::getuid() }),
+            user:   value.get("user")  .unwrap_or_default(),
+
+            rlimits: ResourceLimits::parse(&value),
+
+            umask:  value.get("umask") .ok(),
+            cols:   value.get("cols")  .unwrap_or(80),
+            lines:  value.get("lines") .unwrap_or(24),
+            sid:    value.get("sid")   .unwrap_or(0),
+            tcpgid: value.get("tcpgid").unwrap_or(-1),
+            tty:    value.get("tty")   .ok(),
+            ttydev: value.get("ttydev").ok(),
+
+            raw: value,
+        }
+    }
+}
+
 impl TryFrom<OptionMap> for UserInfo {
     type Error = Error;
 
     fn try_from(value: OptionMap) -> Result<Self> {
+        // these are always provided by sudo, so their absence is an
+        // error rather than something to paper over with a default
         Ok(Self {
             cwd:    value.get("cwd")?,
             egid:   value.get("egid")?,
@@ -111,14 +165,151 @@ impl TryFrom<OptionMap> for UserInfo {
             uid:    value.get("uid")?,
             user:   value.get("user")?,
 
-            umask:  value.get("umask") .ok(),
-            cols:   value.get("cols")  .unwrap_or(80),
-            lines:  value.get("lines") .unwrap_or(24),
-            sid:    value.get("sid")   .unwrap_or(0),
-            tcpgid: value.get("tcpgid").unwrap_or(-1),
-            tty:    value.get("tty")   .ok(),
-
-            raw: value,
+            ..Self::parse(value)
         })
     }
 }
+
+impl From<&UserInfo> for OptionMap {
+    fn from(info: &UserInfo) -> Self {
+        // start from the raw options so that any keys not known to
+        // this library are passed through untouched
+        let mut map = info.raw.clone();
+
+        map.insert("cols",   info.cols);
+        map.insert("cwd",    &info.cwd);
+        map.insert("egid",   info.egid);
+        map.insert("euid",   info.euid);
+        map.insert("gid",    info.gid);
+        map.insert("groups", &info.groups);
+        map.insert("host",   &info.host);
+        map.insert("lines",  info.lines);
+        map.insert("pgid",   info.pgid);
+        map.insert("pid",    info.pid);
+        map.insert("ppid",   info.ppid);
+        map.insert("sid",    info.sid);
+        map.insert("tcpgid", info.tcpgid);
+        map.insert("uid",    info.uid);
+        map.insert("user",   &info.user);
+
+        match info.tty {
+            Some(ref tty) => map.insert("tty", tty),
+            None          => drop(map.remove("tty")),
+        }
+
+        match info.ttydev {
+            Some(ttydev) => map.insert("ttydev", ttydev),
+            None         => drop(map.remove("ttydev")),
+        }
+
+        match info.umask {
+            Some(ref umask) => map.insert("umask", umask),
+            None            => drop(map.remove("umask")),
+        }
+
+        info.rlimits.insert_into(&mut map);
+
+        map
+    }
+}
+
+/// A builder for [`UserInfo`](UserInfo). See the documentation on each
+/// field of `UserInfo` for its meaning.
+///
+/// ```
+/// use sudo_plugin::options::{OptionMap, UserInfo};
+///
+/// let info = UserInfo::builder()
+///     .user("alice")
+///     .uid(1000)
+///     .gid(1000)
+///     .groups(vec![1000, 27])
+///     .build();
+///
+/// let options = OptionMap::from(&info);
+///
+/// assert_eq!(Some("alice"),   options.get_str("user"));
+/// assert_eq!(Some("1000,27"), options.get_str("groups"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct UserInfoBuilder(UserInfo);
+
+impl UserInfoBuilder {
+    setters! {
+        /// Sets `cols`.
+        cols : value u64,
+        /// Sets `cwd`.
+        cwd : into PathBuf,
+        /// Sets `egid`.
+        egid : value gid_t,
+        /// Sets `euid`.
+        euid : value uid_t,
+        /// Sets `groups`.
+        groups : into Vec<gid_t>,
+        /// Sets `host`.
+        host : into String,
+        /// Sets `lines`.
+        lines : value u64,
+        /// Sets `pgid`.
+        pgid : value pid_t,
+        /// Sets `pid`.
+        pid : value pid_t,
+        /// Sets `ppid`.
+        ppid : value pid_t,
+        /// Sets `rlimits`.
+        rlimits : value ResourceLimits,
+        /// Sets `sid`.
+        sid : value pid_t,
+        /// Sets `tcpgid`.
+        tcpgid : value pid_t,
+        /// Sets `user`.
+        user : into String,
+    }
+
+    optional_setters! {
+        /// Sets `tty`.
+        tty : into PathBuf,
+        /// Sets `ttydev`.
+        ttydev : value dev_t,
+        /// Sets `umask`.
+        umask : into String,
+    }
+
+    /// Sets `uid`. Unless `euid` has been set explicitly, it is changed
+    /// to match.
+    #[must_use]
+    pub fn uid(mut self, uid: uid_t) -> Self {
+        if self.0.euid == self.0.uid {
+            self.0.euid = uid;
+        }
+
+        self.0.uid = uid;
+        self
+    }
+
+    /// Sets `gid`. Unless `egid` has been set explicitly, it is changed
+    /// to match.
+    #[must_use]
+    pub fn gid(mut self, gid: gid_t) -> Self {
+        if self.0.egid == self.0.gid {
+            self.0.egid = gid;
+        }
+
+        self.0.gid = gid;
+        self
+    }
+
+    /// Sets an additional option by name that isn't otherwise known to
+    /// this library.
+    #[must_use]
+    pub fn raw<T: ToSudoOption>(mut self, k: &str, v: T) -> Self {
+        self.0.raw.insert(k, v);
+        self
+    }
+
+    /// Returns the completed `UserInfo`.
+    #[must_use]
+    pub fn build(self) -> UserInfo {
+        self.0
+    }
+}
@@ -12,14 +12,57 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
-pub(crate) mod print_facility;
-pub(crate) mod tty;
+//! Facilities for communicating with the user invoking `sudo`.
 
-pub(crate) use print_facility::PrintFacility;
-pub(crate) use tty::Tty;
+mod conversation;
+mod print_facility;
+mod tty;
+
+pub use conversation::{Conversation, ConversationCallback, Message, MessageKind, Reply};
+pub use print_facility::PrintFacility;
+pub use tty::Tty;
 
 use crate::sys;
 
+/// The facilities `sudo` gives every type of plugin for communicating
+/// with the user who invoked it.
+pub(crate) struct UserIo {
+    /// `sudo`'s `printf_facility`, configured to write to the user's
+    /// stdout.
+    pub(crate) stdout: PrintFacility,
+
+    /// `sudo`'s `printf_facility`, configured to write to the user's
+    /// stderr.
+    pub(crate) stderr: PrintFacility,
+
+    /// `sudo`'s conversation function, which allows two-way
+    /// communication with the user.
+    pub(crate) conversation: Conversation,
+}
+
+impl UserIo {
+    /// Wraps the `printf` and conversation functions `sudo` provides to
+    /// a plugin's `open` callback. Output is tagged with `plugin_name`,
+    /// if given.
+    ///
+    /// # Safety
+    ///
+    /// Both functions, if provided, must be the ones `sudo` provided.
+    pub(crate) unsafe fn new(
+        plugin_name:  Option<&str>,
+        printf:       sys::sudo_printf_t,
+        conversation: sys::sudo_conv_t,
+    ) -> Self {
+        let (stdout, stderr) = PrintFacility::new(plugin_name, printf);
+
+        Self {
+            stdout,
+            stderr,
+            conversation: Conversation::new(conversation),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 enum Level {
@@ -0,0 +1,454 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::sys;
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+/// The kinds of message that may be sent through `sudo`'s conversation
+/// function.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum MessageKind {
+    /// Prompt the user for input without echoing what they type (e.g.,
+    /// for a password).
+    PromptEchoOff = sys::SUDO_CONV_PROMPT_ECHO_OFF,
+
+    /// Prompt the user for input, echoing what they type.
+    PromptEchoOn = sys::SUDO_CONV_PROMPT_ECHO_ON,
+
+    /// Prompt the user for input, echoing a mask character in place of
+    /// what they type.
+    PromptMask = sys::SUDO_CONV_PROMPT_MASK,
+
+    /// Display an informational message to the user.
+    Info = sys::SUDO_CONV_INFO_MSG,
+
+    /// Display an error message to the user.
+    Error = sys::SUDO_CONV_ERROR_MSG,
+}
+
+impl MessageKind {
+    fn is_prompt(self) -> bool {
+        matches!(self, Self::PromptEchoOff | Self::PromptEchoOn | Self::PromptMask)
+    }
+}
+
+/// A single message to send through `sudo`'s conversation function.
+#[derive(Clone, Debug)]
+pub struct Message {
+    kind:       MessageKind,
+    text:       String,
+    timeout:    Option<Duration>,
+    echo_ok:    bool,
+    prefer_tty: bool,
+}
+
+impl Message {
+    /// Creates a message of the given kind.
+    #[must_use]
+    pub fn new(kind: MessageKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text:       text.into(),
+            timeout:    None,
+            echo_ok:    false,
+            prefer_tty: false,
+        }
+    }
+
+    /// Creates a prompt whose reply is not echoed back to the user.
+    #[must_use]
+    pub fn prompt_echo_off(text: impl Into<String>) -> Self {
+        Self::new(MessageKind::PromptEchoOff, text)
+    }
+
+    /// Creates a prompt whose reply is echoed back to the user.
+    #[must_use]
+    pub fn prompt_echo_on(text: impl Into<String>) -> Self {
+        Self::new(MessageKind::PromptEchoOn, text)
+    }
+
+    /// Creates an informational message.
+    #[must_use]
+    pub fn info(text: impl Into<String>) -> Self {
+        Self::new(MessageKind::Info, text)
+    }
+
+    /// Creates an error message.
+    #[must_use]
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::new(MessageKind::Error, text)
+    }
+
+    /// Sets how long to wait for the user to reply to a prompt before
+    /// giving up. Timeouts are rounded down to the second.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Allows an `echo_off` prompt to proceed even if echoing can't be
+    /// disabled (e.g., because there's no terminal).
+    #[must_use]
+    pub fn echo_ok(mut self) -> Self {
+        self.echo_ok = true;
+        self
+    }
+
+    /// Asks `sudo` to write the message to the user's terminal rather
+    /// than to STDOUT or STDERR, when possible.
+    #[must_use]
+    pub fn prefer_tty(mut self) -> Self {
+        self.prefer_tty = true;
+        self
+    }
+
+    fn msg_type(&self) -> c_int {
+        let mut msg_type = self.kind as u32;
+
+        if self.echo_ok {
+            msg_type |= sys::SUDO_CONV_PROMPT_ECHO_OK;
+        }
+
+        if self.prefer_tty {
+            msg_type |= sys::SUDO_CONV_PREFER_TTY;
+        }
+
+        #[allow(clippy::cast_possible_wrap)]
+        let msg_type = msg_type as c_int;
+
+        msg_type
+    }
+
+    fn timeout_secs(&self) -> c_int {
+        self.timeout
+            .map_or(0, |t| c_int::try_from(t.as_secs()).unwrap_or(c_int::MAX))
+    }
+}
+
+/// A user's reply to a prompt. The contents are zeroed when the reply
+/// is dropped, since replies frequently contain secrets.
+pub struct Reply(Vec<u8>);
+
+impl Reply {
+    /// Returns the raw bytes of the reply.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the reply as a string, if it's valid UTF-8.
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// Returns `true` if the user replied with nothing (or the message
+    /// wasn't a prompt).
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Reply(<redacted>)")
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        zero(self.0.as_mut_ptr(), self.0.len());
+    }
+}
+
+/// Hooks invoked by `sudo` while a conversation is in progress, when
+/// the process is suspended (e.g., by the user pressing `^Z`) and when
+/// it is later resumed.
+pub trait ConversationCallback {
+    /// Called before `sudo` suspends itself with `signal`.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error tells `sudo` the hook failed.
+    fn on_suspend(&mut self, _signal: c_int) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after `sudo` has resumed from being suspended by
+    /// `signal`.
+    ///
+    /// # Errors
+    ///
+    /// Returning an error tells `sudo` the hook failed.
+    fn on_resume(&mut self, _signal: c_int) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A handle to `sudo`'s conversation function, which allows two-way
+/// communication with the user invoking `sudo` (e.g., prompting them
+/// for input).
+#[derive(Clone, Copy, Debug)]
+pub struct Conversation(sys::sudo_conv_t);
+
+impl Conversation {
+    /// Wraps the conversation function provided to a plugin's `open`
+    /// callback.
+    ///
+    /// # Safety
+    ///
+    /// This function *must* be provided with either a `None` or a real
+    /// pointer to `sudo`'s conversation function.
+    #[must_use]
+    pub unsafe fn new(conversation: sys::sudo_conv_t) -> Self {
+        Self(conversation)
+    }
+
+    /// Sends a batch of messages to the user, returning one reply for
+    /// each message in the same order. Non-prompt messages always have
+    /// an empty reply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sudo` didn't provide a conversation
+    /// function, if any message contains a NUL byte, or if the
+    /// conversation fails (e.g., because a prompt timed out).
+    pub fn converse(&self, messages: &[Message]) -> io::Result<Vec<Reply>> {
+        self.converse_inner(messages, None)
+    }
+
+    /// Like [`converse`](Conversation::converse), but invokes the
+    /// provided callback if `sudo` is suspended and resumed while
+    /// waiting for the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error under the same conditions as
+    /// [`converse`](Conversation::converse).
+    pub fn converse_with_callback(
+        &self,
+        messages: &[Message],
+        callback: &mut dyn ConversationCallback,
+    ) -> io::Result<Vec<Reply>> {
+        self.converse_inner(messages, Some(callback))
+    }
+
+    fn converse_inner(
+        &self,
+        messages:     &[Message],
+        mut callback: Option<&mut dyn ConversationCallback>,
+    ) -> io::Result<Vec<Reply>> {
+        let conv = self.0.ok_or_else(|| io::Error::new(
+            io::ErrorKind::Other,
+            "sudo did not provide a conversation function",
+        ))?;
+
+        let num_msgs = c_int::try_from(messages.len()).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many conversation messages",
+        ))?;
+
+        let texts = messages.iter()
+            .map(|m| CString::new(m.text.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let msgs : Vec<_> = messages.iter().zip(&texts).map(|(m, text)| {
+            sys::sudo_conv_message {
+                msg_type: m.msg_type(),
+                timeout:  m.timeout_secs(),
+                msg:      text.as_ptr(),
+            }
+        }).collect();
+
+        let mut replies : Vec<_> = messages.iter()
+            .map(|_| sys::sudo_conv_reply { reply: ptr::null_mut() })
+            .collect();
+
+        // the closure is a thin pointer to the (fat) trait object
+        // reference, which the trampolines cast back
+        let has_callback = callback.is_some();
+        let closure : *mut Option<&mut dyn ConversationCallback> = &mut callback;
+
+        let mut hooks = sys::sudo_conv_callback {
+            version:    sys::SUDO_CONV_CALLBACK_VERSION_MAJOR << 16 | sys::SUDO_CONV_CALLBACK_VERSION_MINOR,
+            closure:    closure.cast(),
+            on_suspend: Some(on_suspend),
+            on_resume:  Some(on_resume),
+        };
+
+        let hooks_ptr : *mut sys::sudo_conv_callback = if has_callback {
+            &mut hooks
+        } else {
+            ptr::null_mut()
+        };
+
+        let ret = unsafe {
+            (conv)(num_msgs, msgs.as_ptr(), replies.as_mut_ptr(), hooks_ptr)
+        };
+
+        // take ownership of (and scrub) every reply `sudo` allocated,
+        // even if the conversation failed partway through
+        let replies : Vec<_> = messages.iter().zip(&mut replies).map(|(m, r)| {
+            let reply = unsafe { take_reply(r.reply) };
+            r.reply   = ptr::null_mut();
+
+            if m.kind.is_prompt() { reply } else { Reply(vec![]) }
+        }).collect();
+
+        if ret != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "conversation with the user failed",
+            ));
+        }
+
+        Ok(replies)
+    }
+}
+
+/// Copies a reply allocated by `sudo` into a `Reply`, then zeroes and
+/// frees the original.
+unsafe fn take_reply(reply: *mut c_char) -> Reply {
+    if reply.is_null() {
+        return Reply(vec![]);
+    }
+
+    let bytes = CStr::from_ptr(reply).to_bytes();
+    let owned = Reply(bytes.to_vec());
+
+    zero(reply.cast(), bytes.len());
+    libc::free(reply.cast());
+
+    owned
+}
+
+/// Overwrites a buffer with zeroes in a way the compiler won't optimize
+/// away.
+fn zero(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        unsafe { ptr::write_volatile(ptr.add(i), 0) };
+    }
+}
+
+unsafe extern "C" fn on_suspend(signo: c_int, closure: *mut c_void) -> c_int {
+    trampoline(closure, |cb| cb.on_suspend(signo))
+}
+
+unsafe extern "C" fn on_resume(signo: c_int, closure: *mut c_void) -> c_int {
+    trampoline(closure, |cb| cb.on_resume(signo))
+}
+
+unsafe fn trampoline<F>(closure: *mut c_void, f: F) -> c_int
+where
+    F: FnOnce(&mut dyn ConversationCallback) -> io::Result<()>,
+{
+    let closure = closure.cast::<Option<&mut dyn ConversationCallback>>();
+
+    let callback = match closure.as_mut().and_then(Option::as_mut) {
+        Some(c) => &mut **c,
+        None    => return 0,
+    };
+
+    match catch_unwind(AssertUnwindSafe(|| f(callback))) {
+        Ok(Ok(())) => 0,
+        _          => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn echo(
+        num_msgs: c_int,
+        msgs:     *const sys::sudo_conv_message,
+        replies:  *mut sys::sudo_conv_reply,
+        callback: *mut sys::sudo_conv_callback,
+    ) -> c_int {
+        assert!(callback.is_null());
+
+        #[allow(clippy::cast_sign_loss)]
+        for i in 0..num_msgs as usize {
+            let msg = &*msgs.add(i);
+
+            (*replies.add(i)).reply = libc::strdup(msg.msg);
+        }
+
+        0
+    }
+
+    unsafe extern "C" fn fail(
+        _num_msgs: c_int,
+        _msgs:     *const sys::sudo_conv_message,
+        _replies:  *mut sys::sudo_conv_reply,
+        _callback: *mut sys::sudo_conv_callback,
+    ) -> c_int {
+        -1
+    }
+
+    #[test]
+    fn converse_returns_replies_to_prompts() {
+        let conversation = unsafe { Conversation::new(Some(echo)) };
+
+        let replies = conversation.converse(&[
+            Message::prompt_echo_off("secret").prefer_tty(),
+            Message::info("hello"),
+        ]).unwrap();
+
+        assert_eq!(2,              replies.len());
+        assert_eq!(Some("secret"), replies[0].as_str());
+        assert!(replies[1].is_empty());
+        assert_eq!("Reply(<redacted>)", format!("{:?}", replies[0]));
+    }
+
+    #[test]
+    fn converse_reports_failures() {
+        let conversation = unsafe { Conversation::new(Some(fail)) };
+
+        assert!(conversation.converse(&[Message::info("hello")]).is_err());
+    }
+
+    #[test]
+    fn converse_requires_a_conversation_function() {
+        let conversation = unsafe { Conversation::new(None) };
+
+        assert!(conversation.converse(&[Message::info("hello")]).is_err());
+    }
+
+    #[test]
+    fn message_flags_are_combined() {
+        let msg = Message::prompt_echo_off("").echo_ok().prefer_tty();
+
+        #[allow(clippy::cast_possible_wrap)]
+        let expected = (
+            sys::SUDO_CONV_PROMPT_ECHO_OFF |
+            sys::SUDO_CONV_PROMPT_ECHO_OK  |
+            sys::SUDO_CONV_PREFER_TTY
+        ) as c_int;
+
+        assert_eq!(expected, msg.msg_type());
+    }
+}
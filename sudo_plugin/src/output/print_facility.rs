@@ -68,6 +68,10 @@ impl PrintFacility {
     }
 
     /// Pretty-prints a line, prefixed by the name of the plugin.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying `printf` facility fails.
     pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
         let tag = self.tag.clone();
 
@@ -79,6 +83,10 @@ impl PrintFacility {
     }
 
     /// Pretty-prints nested errors to the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying `printf` facility fails.
     pub fn write_error(&mut self, error: &dyn Error) -> io::Result<()> {
         // errors are prefixed with a newline for clarity, since they
         // might be emitted while an existing line has output on it
@@ -88,8 +96,12 @@ impl PrintFacility {
         // stabilized
         self.write_line(format!("{}", error).as_bytes())?;
 
-        while let Some(error) = error.source() {
+        let mut source = error.source();
+
+        while let Some(error) = source {
             self.write_line(format!("{}", error).as_bytes())?;
+
+            source = error.source();
         }
 
         Ok(())
@@ -16,10 +16,23 @@
 
 #![allow(clippy::module_name_repetitions)]
 
+mod approval_plugin;
+mod audit_plugin;
+mod command_env;
 mod io_env;
 mod io_plugin;
-mod io_state;
+mod policy_env;
+mod policy_plugin;
+mod state;
+mod submit_env;
 
-pub use io_env::IoEnv;
+pub use approval_plugin::ApprovalPlugin;
+pub use audit_plugin::{AuditPlugin, CloseStatus, PluginType};
+pub use command_env::CommandEnv;
+pub use io_env::{IoEnv, IoEnvBuilder};
 pub use io_plugin::IoPlugin;
-pub use io_state::IoState;
+pub use policy_env::PolicyEnv;
+pub use policy_plugin::{Accepted, PolicyPlugin};
+pub use state::{PluginState, PolicyOutput, Storage};
+pub use state::{ApprovalStorage, AuditStorage, IoState, IoStorage, PolicyStorage};
+pub use submit_env::{ApprovalEnv, AuditEnv, SubmitEnv};
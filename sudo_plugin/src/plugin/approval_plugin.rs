@@ -0,0 +1,104 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::ApprovalEnv;
+use crate::core::CheckStatus;
+use crate::errors::SudoError;
+use crate::options::{CommandInfo, OptionMap};
+
+use std::ffi::CString;
+use std::sync::Arc;
+
+#[warn(clippy::missing_inline_in_public_items)]
+/// The trait that defines the implementation of a sudo approval plugin.
+///
+/// Approval plugins are consulted after the policy plugin has accepted
+/// a command, and may veto it before it is run. They require a version
+/// of sudo that supports version 1.15 or later of the plugin API.
+pub trait ApprovalPlugin: 'static + Sized + Send {
+    /// The type for errors returned by this `ApprovalPlugin`. Errors
+    /// must implement the [`SudoError`](crate::errors::SudoError)
+    /// trait, as well as describe how they should be converted to a
+    /// [`CheckStatus`](crate::core::CheckStatus) when returned from
+    /// [`check`](ApprovalPlugin::check).
+    type Error: SudoError + Into<CheckStatus>;
+
+    /// The name of the plugin. Used when printing the version of the
+    /// plugin and error messages.
+    const NAME: &'static str;
+
+    /// The version of the plugin. Defaults to the the value of the
+    /// `CARGO_PKG_VERSION` environment variable during build.
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    /// Prints the name and version of the plugin. A default
+    /// implementation of this function is provided, but may be
+    /// overridden if desired.
+    #[inline]
+    fn show_version(env: &ApprovalEnv, _verbose: bool) {
+        use std::io::Write;
+
+        let _ = writeln!(
+            env.stdout(),
+            "{} approval plugin version {}",
+            Self::NAME,
+            Self::VERSION,
+        );
+    }
+
+    /// The `open` function is run before `check` is called. Unlike I/O
+    /// plugins, approval plugins are opened before the policy plugin
+    /// has decided whether or not the command may be run.
+    ///
+    /// The plugin may keep the provided handle to its environment (or
+    /// clones of it) for as long as it needs; the environment is
+    /// dropped once the plugin and every other handle are gone.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`.
+    fn open(env: Arc<ApprovalEnv>) -> Result<Self, Self::Error>;
+
+    /// The `close` method is called when `sudo` is finished with the
+    /// plugin, which happens before the command is run. A default
+    /// no-op implementation is provided, but be overriden if desired.
+    ///
+    /// As suggested by its signature, once this method exits, the
+    /// plugin will be dropped.
+    #[inline]
+    fn close(self) {}
+
+    /// The `check` method is called once the policy plugin has accepted
+    /// the command, and is provided with the final `command_info`,
+    /// arguments, and environment it will be run with. Returning `Ok`
+    /// approves the command.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`CheckStatus`](crate::core::CheckStatus) before being
+    /// returned to `sudo`. Plugins reject commands by returning an
+    /// error that converts to
+    /// [`CheckStatus::Reject`](crate::core::CheckStatus::Reject).
+    fn check(
+        &self,
+        command_info: &CommandInfo,
+        run_argv:     &[CString],
+        run_env:      &OptionMap,
+    ) -> Result<(), Self::Error>;
+}
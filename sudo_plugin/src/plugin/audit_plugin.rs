@@ -0,0 +1,220 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::AuditEnv;
+use crate::errors::SudoError;
+use crate::options::{CommandInfo, OptionMap};
+use crate::sys;
+
+use std::ffi::CString;
+use std::sync::Arc;
+use std::os::raw::{c_int, c_uint};
+
+/// The kinds of plugin (or `sudo` itself) that audit events may come
+/// from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PluginType {
+    /// The `sudo` front-end itself.
+    FrontEnd,
+
+    /// A policy plugin.
+    Policy,
+
+    /// An I/O plugin.
+    Io,
+
+    /// An audit plugin.
+    Audit,
+
+    /// An approval plugin.
+    Approval,
+
+    /// A type of plugin unknown to this library.
+    Unknown(c_uint),
+}
+
+impl From<c_uint> for PluginType {
+    fn from(plugin_type: c_uint) -> Self {
+        match plugin_type {
+            sys::SUDO_FRONT_END       => PluginType::FrontEnd,
+            sys::SUDO_POLICY_PLUGIN   => PluginType::Policy,
+            sys::SUDO_IO_PLUGIN       => PluginType::Io,
+            sys::SUDO_AUDIT_PLUGIN    => PluginType::Audit,
+            sys::SUDO_APPROVAL_PLUGIN => PluginType::Approval,
+            other                     => PluginType::Unknown(other),
+        }
+    }
+}
+
+/// How the command (or `sudo` itself) finished, as reported to an
+/// audit plugin when it's closed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CloseStatus {
+    /// No status is available (e.g., the command was never run).
+    None,
+
+    /// The command exited, with the given wait status (as returned by
+    /// `waitpid(2)`).
+    Wait(c_int),
+
+    /// The command couldn't be executed, with the given `errno`.
+    ExecError(c_int),
+
+    /// `sudo` itself failed, with the given `errno`.
+    SudoError(c_int),
+}
+
+impl CloseStatus {
+    pub(crate) fn from_ffi(status_type: c_int, status: c_int) -> Self {
+        // unrecognized types can't be interpreted, so they're treated as
+        // though no status was given
+        #[allow(clippy::cast_sign_loss)]
+        match status_type as c_uint {
+            sys::SUDO_PLUGIN_WAIT_STATUS => CloseStatus::Wait(status),
+            sys::SUDO_PLUGIN_EXEC_ERROR  => CloseStatus::ExecError(status),
+            sys::SUDO_PLUGIN_SUDO_ERROR  => CloseStatus::SudoError(status),
+            _                            => CloseStatus::None,
+        }
+    }
+}
+
+#[warn(clippy::missing_inline_in_public_items)]
+/// The trait that defines the implementation of a sudo audit plugin.
+///
+/// Audit plugins are told about every command that is accepted or
+/// rejected by the other plugins, and about any errors that prevented a
+/// decision from being made. They require a version of sudo that
+/// supports version 1.15 or later of the plugin API.
+pub trait AuditPlugin: 'static + Sized + Send {
+    /// The type for errors returned by this `AuditPlugin`. Errors must
+    /// implement the [`SudoError`](crate::errors::SudoError) trait
+    /// which describes how to convert them to the return codes expected
+    /// by the `sudo_plugin(8)` facility.
+    type Error: SudoError;
+
+    /// The name of the plugin. Used when printing the version of the
+    /// plugin and error messages.
+    const NAME: &'static str;
+
+    /// The version of the plugin. Defaults to the the value of the
+    /// `CARGO_PKG_VERSION` environment variable during build.
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    /// Prints the name and version of the plugin. A default
+    /// implementation of this function is provided, but may be
+    /// overridden if desired.
+    #[inline]
+    fn show_version(env: &AuditEnv, _verbose: bool) {
+        use std::io::Write;
+
+        let _ = writeln!(
+            env.stdout(),
+            "{} audit plugin version {}",
+            Self::NAME,
+            Self::VERSION,
+        );
+    }
+
+    /// The `open` function is run before any other callbacks, as soon
+    /// as `sudo` has parsed its command line.
+    ///
+    /// The plugin may keep the provided handle to its environment (or
+    /// clones of it) for as long as it needs; the environment is
+    /// dropped once the plugin and every other handle are gone.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`.
+    fn open(env: Arc<AuditEnv>) -> Result<Self, Self::Error>;
+
+    /// The `close` method is called when the command being run by
+    /// sudo finishes, or when `sudo` exits without running it. A
+    /// default no-op implementation is provided, but be overriden if
+    /// desired.
+    ///
+    /// As suggested by its signature, once this method exits, the
+    /// plugin will be dropped.
+    #[inline]
+    fn close(self, _status: CloseStatus) {}
+
+    /// The `accept` method is called when a plugin has accepted the
+    /// command, along with the final `command_info`, arguments, and
+    /// environment the command will be run with.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`. The command will not be run if an error is
+    /// returned.
+    #[inline]
+    fn accept(
+        &self,
+        _plugin_name:  &str,
+        _plugin_type:  PluginType,
+        _command_info: &CommandInfo,
+        _run_argv:     &[CString],
+        _run_env:      &OptionMap,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The `reject` method is called when a plugin has rejected the
+    /// command, along with the reason it gave. Since the command may be
+    /// rejected before it's fully resolved, `command_info` may be
+    /// incomplete or empty.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn reject(
+        &self,
+        _plugin_name:  &str,
+        _plugin_type:  PluginType,
+        _message:      &str,
+        _command_info: &OptionMap,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The `error` method is called when a plugin (or `sudo` itself)
+    /// encountered an error that prevented the command from being run.
+    /// Like with [`reject`](AuditPlugin::reject), `command_info` may be
+    /// incomplete or empty.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn error(
+        &self,
+        _plugin_name:  &str,
+        _plugin_type:  PluginType,
+        _message:      &str,
+        _command_info: &OptionMap,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
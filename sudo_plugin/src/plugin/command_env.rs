@@ -0,0 +1,128 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::options::{CommandInfo, Settings, UserInfo};
+use crate::shell;
+
+use std::collections::HashSet;
+use std::ffi::{CString, OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use libc::gid_t;
+
+/// Everything `sudo` has decided about a command that's about to be
+/// run, from which details about how it will be run can be derived.
+///
+/// This is implemented by [`IoEnv`](super::IoEnv). Approval plugins
+/// only learn the final `command_info` and command line when `check` is
+/// called, so they may implement it for whatever type they keep those
+/// in alongside their [`ApprovalEnv`](super::ApprovalEnv).
+pub trait CommandEnv {
+    /// The settings `sudo` was invoked with.
+    fn settings(&self) -> &Settings;
+
+    /// Information about the user running the command.
+    fn user_info(&self) -> &UserInfo;
+
+    /// Information about the command being run.
+    fn command_info(&self) -> &CommandInfo;
+
+    /// The command being executed under `sudo`, in the same form as
+    /// would be passed to the `execve(2)` system call.
+    fn cmdline(&self) -> &[CString];
+
+    ///
+    /// As best as can be reconstructed, what was actually typed at the
+    /// shell in order to launch this invocation of sudo. Each argument
+    /// is quoted as necessary (see [`shell::quote`](crate::shell::quote)),
+    /// so the result is an unambiguous rendering of the command being
+    /// run.
+    ///
+    // TODO: I don't really like this name
+    #[must_use]
+    fn invocation(&self) -> Vec<u8> {
+        shell::join(self.invocation_args())
+    }
+
+    ///
+    /// The unquoted arguments of [`invocation`](CommandEnv::invocation):
+    /// the name `sudo` was run as, the flags it was given (see
+    /// [`Settings::flags`](Settings::flags)), and the command line
+    /// being run.
+    ///
+    #[must_use]
+    fn invocation_args(&self) -> Vec<OsString> {
+        let mut args = vec![OsString::from(&self.settings().progname)];
+
+        args.extend(self.settings().flags());
+        args.extend(self.cmdline().iter().map(|arg|
+            OsStr::from_bytes(arg.as_bytes()).to_owned()
+        ));
+
+        args
+    }
+
+    ///
+    /// The `cwd` to be used for the command being run. This is
+    /// typically set on the `user_info` component, but may be
+    /// overridden by the policy plugin setting its value on
+    /// `command_info`.
+    ///
+    #[must_use]
+    fn cwd(&self) -> &PathBuf {
+        self.command_info().cwd.as_ref().unwrap_or(
+            &self.user_info().cwd
+        )
+    }
+
+    ///
+    /// The complete set of groups the invoked command will have
+    /// privileges for. If the `-P` (`--preserve-groups`) flag was
+    /// passed to `sudo`, the underlying `command_info` will not have
+    /// this set and this method will return the list of original groups
+    /// from the running the command.
+    ///
+    /// This set will always contain `runas_egid`.
+    ///
+    #[must_use]
+    fn runas_gids(&self) -> HashSet<gid_t> {
+        let command_info = self.command_info();
+
+        // sanity-check that if preserve_groups is unset we have
+        // `runas_groups`, and if it is set that we don't
+        if command_info.preserve_groups {
+            debug_assert!(command_info.runas_groups.is_none());
+        } else {
+            debug_assert!(command_info.runas_groups.is_some());
+        }
+
+        // even though the above sanity-check might go wrong, it still
+        // seems like a safe bet that if `runas_groups` isn't set that
+        // the command will be invoked with the original user's groups
+        // (it will probably require reading the `sudo` source code to
+        // verify this)
+        let mut set : HashSet<_> = command_info.runas_groups.as_ref().unwrap_or(
+            &self.user_info().groups
+        ).iter().copied().collect();
+
+        // `command_info.runas_egid` won't necessarily be in the list of
+        // `command_info.runas_groups` if `-P` was passed; however, the
+        // user will have this in the list of groups that they will gain
+        // permissions for so it seems sane to include it in this list
+        let _ = set.insert(command_info.runas_egid);
+
+        set
+    }
+}
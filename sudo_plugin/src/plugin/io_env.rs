@@ -12,18 +12,21 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
+use super::CommandEnv;
 use crate::errors::Result;
+use crate::event::{Event, EventAlloc, Events};
 use crate::version::Version;
 use crate::options::{OptionMap, CommandInfo, Settings, UserInfo};
-use crate::output::{PrintFacility, Tty};
+use crate::output::{ConversationCallback, Message, PrintFacility, Reply, Tty, UserIo};
 
 use std::convert::{TryFrom, TryInto};
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::os::unix::io::RawFd;
 use std::ffi::{CString, CStr};
+use std::io;
 use std::slice;
+use std::time::Duration;
 
-use libc::{c_char, c_int, c_uint, gid_t};
+use libc::{c_char, c_int, c_uint};
 
 /// An implementation of the sudo [`io_plugin`](crate::sys::io_plugin) environment, initialized
 /// and parsed from the values passed to the underlying `open` callback.
@@ -67,17 +70,12 @@ pub struct IoEnv {
     /// `plugin_options["disabled"] => "disabled"`).
     pub plugin_options: OptionMap,
 
-    /// A handle to the plugin's printf_facility, configured to write to
-    /// the user's stdout.
-    stdout: PrintFacility,
+    /// The facilities for communicating with the user.
+    user_io: UserIo,
 
-    /// A handle to the plugin's printf_facility, configured to write to
-    /// the user's stdin.
-    stderr: PrintFacility,
-
-    /// A (currently-unused) handle to the sudo_plugin conversation
-    /// facility, which allows two-way communication with the user.
-    _conversation: crate::sys::sudo_conv_t,
+    /// The event allocator `sudo` writes into the plugin struct before
+    /// calling `open`, if it supports plugin events.
+    pub(crate) event_alloc: EventAlloc,
 }
 
 // I don't get to control how many arguments these methods accept, since
@@ -116,11 +114,6 @@ impl IoEnv {
     ) -> Result<Self> {
         let version = Version::from(version).check()?;
 
-        let (stdout, stderr) = PrintFacility::new(
-            Some(plugin_name),
-            plugin_printf
-        );
-
         // god help us all if `argc` is negative
         #[allow(clippy::cast_sign_loss)]
         let mut argv = slice::from_raw_parts(
@@ -147,9 +140,8 @@ impl IoEnv {
             user_env:       OptionMap::from_raw(user_env.cast()),
             plugin_options: OptionMap::from_raw(plugin_options.cast()),
 
-            stdout,
-            stderr,
-            _conversation: conversation,
+            user_io:     UserIo::new(Some(plugin_name), plugin_printf, conversation),
+            event_alloc: None,
         };
 
         Ok(plugin)
@@ -161,7 +153,7 @@ impl IoEnv {
     ///
     #[must_use]
     pub fn stdout(&self) -> PrintFacility {
-        self.stdout.clone()
+        self.user_io.stdout.clone()
     }
 
     ///
@@ -170,7 +162,114 @@ impl IoEnv {
     ///
     #[must_use]
     pub fn stderr(&self) -> PrintFacility {
-        self.stderr.clone()
+        self.user_io.stderr.clone()
+    }
+
+    ///
+    /// Sends a batch of messages to the invoking user through `sudo`'s
+    /// conversation function, returning their replies to any prompts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse(&self, messages: &[Message]) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse(messages)
+    }
+
+    ///
+    /// Like [`converse`](IoEnv::converse), but invokes `callback` if
+    /// `sudo` is suspended and resumed while waiting for the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse_with_callback(
+        &self,
+        messages: &[Message],
+        callback: &mut dyn ConversationCallback,
+    ) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse_with_callback(messages, callback)
+    }
+
+    ///
+    /// Allocates a new, unconfigured [`Event`](Event) in `sudo`'s event
+    /// loop. Requires a version of sudo that supports version 1.15 or
+    /// later of the plugin API.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sudo` doesn't support plugin events or
+    /// fails to allocate one.
+    ///
+    pub fn event(&self) -> io::Result<Event> {
+        Event::new(self.event_alloc)
+    }
+
+    ///
+    /// Invokes `callback` every time `fd` becomes readable, until the
+    /// returned [`Event`](Event) is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event can't be allocated or scheduled.
+    ///
+    pub fn on_readable<F: FnMut(RawFd) + 'static>(&self, fd: RawFd, mut callback: F) -> io::Result<Event> {
+        self.watch(fd, Events::READ | Events::PERSIST, None, move |fd, _| callback(fd))
+    }
+
+    ///
+    /// Invokes `callback` every time `fd` becomes writable, until the
+    /// returned [`Event`](Event) is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event can't be allocated or scheduled.
+    ///
+    pub fn on_writable<F: FnMut(RawFd) + 'static>(&self, fd: RawFd, mut callback: F) -> io::Result<Event> {
+        self.watch(fd, Events::WRITE | Events::PERSIST, None, move |fd, _| callback(fd))
+    }
+
+    ///
+    /// Invokes `callback` once, after `timeout` has elapsed. Dropping
+    /// the returned [`Event`](Event) cancels the timer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event can't be allocated or scheduled.
+    ///
+    pub fn after<F: FnMut() + 'static>(&self, timeout: Duration, mut callback: F) -> io::Result<Event> {
+        self.watch(-1, Events::TIMEOUT, Some(timeout), move |_, _| callback())
+    }
+
+    ///
+    /// Invokes `callback` every `interval`, until the returned
+    /// [`Event`](Event) is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event can't be allocated or scheduled.
+    ///
+    pub fn every<F: FnMut() + 'static>(&self, interval: Duration, mut callback: F) -> io::Result<Event> {
+        self.watch(-1, Events::TIMEOUT | Events::PERSIST, Some(interval), move |_, _| callback())
+    }
+
+    fn watch<F: FnMut(RawFd, Events) + 'static>(
+        &self,
+        fd:       RawFd,
+        events:   Events,
+        timeout:  Option<Duration>,
+        callback: F,
+    ) -> io::Result<Event> {
+        let mut event = self.event()?;
+
+        event.set(fd, events, callback)?;
+        event.add(timeout)?;
+
+        Ok(event)
     }
 
     ///
@@ -183,77 +282,94 @@ impl IoEnv {
             Tty::try_from(path.as_path()).ok()
         )
     }
+}
 
-    ///
-    /// As best as can be reconstructed, what was actually typed at the
-    /// shell in order to launch this invocation of sudo.
-    ///
-    // TODO: I don't really like this name
-    #[must_use]
-    pub fn invocation(&self) -> Vec<u8> {
-        let mut sudo    = self.settings.progname.as_bytes().to_vec();
-        let     flags   = self.settings.flags();
+impl CommandEnv for IoEnv {
+    fn settings(&self) -> &Settings {
+        &self.settings
+    }
 
-        if !flags.is_empty() {
-            sudo.push(b' ');
-            sudo.extend_from_slice(&flags.join(&b' ')[..]);
-        }
+    fn user_info(&self) -> &UserInfo {
+        &self.user_info
+    }
 
-        for entry in &self.cmdline {
-            sudo.push(b' ');
-            sudo.extend_from_slice(entry.as_bytes());
-        }
+    fn command_info(&self) -> &CommandInfo {
+        &self.command_info
+    }
 
-        sudo
+    fn cmdline(&self) -> &[CString] {
+        &self.cmdline
     }
+}
 
-    ///
-    /// The `cwd` to be used for the command being run. This is
-    /// typically set on the `user_info` component, but may be
-    /// overridden by the policy plugin setting its value on
-    /// `command_info`.
-    ///
+/// A builder for [`IoEnv`](IoEnv), for constructing an environment by
+/// hand (e.g., to unit test plugin logic) rather than from the arguments
+/// `sudo` passes to `open`.
+///
+/// An `IoEnv` built this way has no way to communicate with the user:
+/// writes to its [`stdout`](IoEnv::stdout) and
+/// [`stderr`](IoEnv::stderr) fail, as does
+/// [`converse`](IoEnv::converse), and it has no event loop.
+///
+/// ```
+/// use sudo_plugin::options::{CommandInfo, UserInfo};
+/// use sudo_plugin::plugin::IoEnv;
+///
+/// let env = IoEnv::builder()
+///     .user_info(UserInfo::builder().uid(1000).build())
+///     .command_info(CommandInfo::builder().command("/usr/bin/id").build())
+///     .build();
+///
+/// assert_eq!(1000, env.user_info.uid);
+/// ```
+#[allow(missing_debug_implementations)]
+pub struct IoEnvBuilder(IoEnv);
+
+impl IoEnv {
+    /// Returns an [`IoEnvBuilder`](IoEnvBuilder). Any values left unset
+    /// take on the defaults of their own builders.
     #[must_use]
-    pub fn cwd(&self) -> &PathBuf {
-        self.command_info.cwd.as_ref().unwrap_or(
-            &self.user_info.cwd
-        )
+    pub fn builder() -> IoEnvBuilder {
+        IoEnvBuilder(Self {
+            plugin_name:    "",
+            plugin_version: "",
+            api_version:    Version::from(crate::sys::SUDO_API_VERSION),
+            cmdline:        vec![],
+            settings:       Settings::builder().build(),
+            user_info:      UserInfo::builder().build(),
+            command_info:   CommandInfo::builder().build(),
+            user_env:       OptionMap::new(),
+            plugin_options: OptionMap::new(),
+
+            user_io:     unsafe { UserIo::new(None, None, None) },
+            event_alloc: None,
+        })
     }
+}
 
-    ///
-    /// The complete set of groups the invoked command will have
-    /// privileges for. If the `-P` (`--preserve-groups`) flag was
-    /// passed to `sudo`, the underlying `command_info` will not have
-    /// this set and this method will return the list of original groups
-    /// from the running the command.
-    ///
-    /// This set will always contain `runas_egid`.
-    ///
+impl IoEnvBuilder {
+    setters! {
+        /// Sets `plugin_name`.
+        plugin_name : value &'static str,
+        /// Sets `plugin_version`.
+        plugin_version : value &'static str,
+        /// Sets `cmdline`.
+        cmdline : into Vec<CString>,
+        /// Sets `settings`.
+        settings : value Settings,
+        /// Sets `user_info`.
+        user_info : value UserInfo,
+        /// Sets `command_info`.
+        command_info : value CommandInfo,
+        /// Sets `user_env`.
+        user_env : value OptionMap,
+        /// Sets `plugin_options`.
+        plugin_options : value OptionMap,
+    }
+
+    /// Returns the completed `IoEnv`.
     #[must_use]
-    pub fn runas_gids(&self) -> HashSet<gid_t> {
-        // sanity-check that if preserve_groups is unset we have
-        // `runas_groups`, and if it is set that we don't
-        if self.command_info.preserve_groups {
-            debug_assert!(self.command_info.runas_groups.is_none());
-        } else {
-            debug_assert!(self.command_info.runas_groups.is_some());
-        }
-
-        // even though the above sanity-check might go wrong, it still
-        // seems like a safe bet that if `runas_groups` isn't set that
-        // the command will be invoked with the original user's groups
-        // (it will probably require reading the `sudo` source code to
-        // verify this)
-        let mut set : HashSet<_> = self.command_info.runas_groups.as_ref().unwrap_or(
-            &self.user_info.groups
-        ).iter().copied().collect();
-
-        // `command_info.runas_egid` won't necessarily be in the list of
-        // `command_info.runas_groups` if `-P` was passed; however, the
-        // user will have this in the list of groups that they will gain
-        // permissions for so it seems sane to include it in this list
-        let _ = set.insert(self.command_info.runas_egid);
-
-        set
+    pub fn build(self) -> IoEnv {
+        self.0
     }
 }
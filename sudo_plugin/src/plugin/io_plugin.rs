@@ -19,11 +19,17 @@
 
 use super::IoEnv;
 use crate::errors::SudoError;
+use crate::hooks::Hooks;
 
-#[warn(clippy::missing_inline_in_public_items)]
+use std::sync::Arc;
 
+#[warn(clippy::missing_inline_in_public_items)]
 /// The trait that defines the implementation of a sudo I/O plugin.
-pub trait IoPlugin: 'static + Sized {
+///
+/// Plugins must be `Send`, since they're kept in thread-safe storage
+/// between `open` and `close`. This allows plugins to spawn worker
+/// threads that share their state and their [`IoEnv`].
+pub trait IoPlugin: 'static + Sized + Send {
     /// The type for errors returned by this `IoPlugin`. Errors must
     /// implement the [`SudoError`](crate::errors::SudoError) trait
     /// which describes how to convert them to the return codes expected
@@ -68,13 +74,17 @@ pub trait IoPlugin: 'static + Sized {
     /// policy plugin's `check_policy` function has returned
     /// successfully.
     ///
+    /// The plugin may keep the provided handle to its environment (or
+    /// clones of it) for as long as it needs; the environment is
+    /// dropped once the plugin and every other handle are gone.
+    ///
     /// # Errors
     ///
     /// Any errors will be recursively printed (up their
     /// [`source`](std::error::Error::source) chain) then converted to
     /// an [`OpenStatus`](crate::errors::OpenStatus) before being
     /// returned to `sudo`.
-    fn open(env: &'static IoEnv) -> Result<Self, Self::Error>;
+    fn open(env: Arc<IoEnv>) -> Result<Self, Self::Error>;
 
     /// The `close` method is called when the command being run by
     /// sudo finishes. A default no-op implementation is provided, but
@@ -171,6 +181,9 @@ pub trait IoPlugin: 'static + Sized {
     /// provided with a count of the number of horizontal lines and vertical
     /// columns that the terminal can now display.
     ///
+    /// This callback is only invoked by versions of `sudo` that support
+    /// [`Capability::ChangeWinsize`](crate::version::Capability::ChangeWinsize).
+    ///
     /// # Errors
     ///
     /// Any errors will be recursively printed (up their
@@ -182,5 +195,37 @@ pub trait IoPlugin: 'static + Sized {
         Ok(())
     }
 
-    // TODO: support for `log_suspend`
+    /// The `log_suspend` callback is invoked whenever the command is
+    /// suspended or resumed. It is provided with the signal that caused
+    /// the command to be suspended (e.g., `SIGTSTP`), or `SIGCONT` if
+    /// the command was resumed.
+    ///
+    /// This callback is only invoked by versions of `sudo` that support
+    /// [`Capability::LogSuspend`](crate::version::Capability::LogSuspend).
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`LogStatus`](crate::errors::LogStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn log_suspend(&self, _signal: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The `register_hooks` method is called after the plugin has been
+    /// opened, and allows the plugin to register closures on `hooks`
+    /// that are invoked whenever the environment is manipulated (e.g.,
+    /// to watch for or veto changes to `LD_PRELOAD`). See
+    /// [`Hooks`](crate::hooks::Hooks) for details. A default no-op
+    /// implementation is provided, but may be overridden if desired.
+    ///
+    /// The hooks remain registered until `sudo` deregisters them, which
+    /// may not happen until it exits. Hooks that are still registered
+    /// when the plugin is closed are kept alive (and may continue to be
+    /// called) for the rest of the process, so they shouldn't capture
+    /// anything that expects to be dropped with the plugin.
+    #[inline]
+    fn register_hooks(&self, _hooks: &mut Hooks) {}
 }
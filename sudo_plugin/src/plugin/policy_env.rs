@@ -0,0 +1,161 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::errors::Result;
+use crate::version::Version;
+use crate::options::{OptionMap, Settings, UserInfo};
+use crate::output::{ConversationCallback, Message, PrintFacility, Reply, UserIo};
+
+use std::convert::TryInto;
+use std::io;
+
+use libc::{c_char, c_uint};
+
+/// An implementation of the sudo [`policy_plugin`](crate::sys::policy_plugin)
+/// environment, initialized and parsed from the values passed to the
+/// underlying `open` callback.
+#[allow(missing_debug_implementations)]
+pub struct PolicyEnv {
+    /// The name of the plugin. This will be the generally be the same
+    /// as the name of the exported C struct.
+    pub plugin_name: &'static str,
+
+    /// The version of the plugin.
+    pub plugin_version: &'static str,
+
+    /// The plugin API version supported by the invoked `sudo` command.
+    pub api_version: Version,
+
+    /// A map of user-supplied sudo settings. These settings correspond
+    /// to flags the user specified when running sudo. As such, they
+    /// will only be present when the corresponding flag has been specified
+    /// on the command line.
+    pub settings: Settings,
+
+    /// A map of information about the user running the command.
+    pub user_info: UserInfo,
+
+    /// A map of the environment variables `sudo` was invoked with.
+    pub user_env: OptionMap,
+
+    /// A map of options provided to the plugin after the its path in
+    /// sudo.conf.
+    ///
+    /// Settings that aren't of the form `key=value` will have a key
+    /// in the map whose value is the same as the key, similar to how
+    /// HTML handles valueless attributes (e.g., `disabled` will become
+    /// `plugin_options["disabled"] => "disabled"`).
+    pub plugin_options: OptionMap,
+
+    /// The facilities for communicating with the user.
+    user_io: UserIo,
+}
+
+// I don't get to control how many arguments these methods accept, since
+// it's dictated by the C plugin.
+#[allow(clippy::too_many_arguments)]
+impl PolicyEnv {
+    /// Initializes a `PolicyEnv` from the arguments provided to the
+    /// underlying C `open` callback function.
+    ///
+    /// Verifies that the API version advertised by the underlying
+    /// `sudo` is supported, parses all provided options, and wires up
+    /// communication facilities.
+    //
+    /// # Errors
+    ///
+    /// Returns an error if there was a problem initializing the plugin.
+    ///
+    /// # Safety
+    ///
+    /// This function is inherently unsafe since it's provided with
+    /// raw pointers. As long as sudo obeys its contracts for how these
+    /// are interpreted (see [`OptionMap`](OptionMap) for details).
+    pub unsafe fn new(
+        plugin_name:    &'static str,
+        plugin_version: &'static str,
+        version:        c_uint,
+        plugin_printf:  crate::sys::sudo_printf_t,
+        conversation:   crate::sys::sudo_conv_t,
+        settings:       *const *mut c_char,
+        user_info:      *const *mut c_char,
+        user_env:       *const *mut c_char,
+        plugin_options: *const *mut c_char,
+    ) -> Result<Self> {
+        let version = Version::from(version).check()?;
+
+        let plugin = Self {
+            plugin_name,
+            plugin_version,
+
+            api_version: version,
+
+            settings:       OptionMap::from_raw(settings.cast()).try_into()?,
+            user_info:      OptionMap::from_raw(user_info.cast()).try_into()?,
+            user_env:       OptionMap::from_raw(user_env.cast()),
+            plugin_options: OptionMap::from_raw(plugin_options.cast()),
+
+            user_io: UserIo::new(Some(plugin_name), plugin_printf, conversation),
+        };
+
+        Ok(plugin)
+    }
+
+    ///
+    /// Returns a facility implementing `std::io::Write` that emits to
+    /// the invoking user's STDOUT.
+    ///
+    #[must_use]
+    pub fn stdout(&self) -> PrintFacility {
+        self.user_io.stdout.clone()
+    }
+
+    ///
+    /// Returns a facility implementing `std::io::Write` that emits to
+    /// the invoking user's STDERR.
+    ///
+    #[must_use]
+    pub fn stderr(&self) -> PrintFacility {
+        self.user_io.stderr.clone()
+    }
+    ///
+    /// Sends a batch of messages to the invoking user through `sudo`'s
+    /// conversation function, returning their replies to any prompts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse(&self, messages: &[Message]) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse(messages)
+    }
+
+    ///
+    /// Like [`converse`](PolicyEnv::converse), but invokes `callback`
+    /// if `sudo` is suspended and resumed while waiting for the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse_with_callback(
+        &self,
+        messages: &[Message],
+        callback: &mut dyn ConversationCallback,
+    ) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse_with_callback(messages, callback)
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::PolicyEnv;
+use crate::core::CheckStatus;
+use crate::errors::{Error, SudoError};
+use crate::options::{CommandInfo, OptionMap};
+
+use std::ffi::CString;
+use std::sync::Arc;
+
+/// The result of a policy plugin accepting a command: everything
+/// `sudo` needs to know in order to run it.
+#[derive(Clone, Debug)]
+pub struct Accepted {
+    /// How the command should be run, typically constructed with
+    /// [`CommandInfo::builder`](crate::options::CommandInfo::builder).
+    pub command_info: CommandInfo,
+
+    /// The argument vector to run the command with, starting with the
+    /// name of the command itself.
+    pub argv: Vec<CString>,
+
+    /// The environment to run the command with.
+    pub user_env: OptionMap,
+}
+
+#[warn(clippy::missing_inline_in_public_items)]
+/// The trait that defines the implementation of a sudo policy plugin.
+///
+/// A policy plugin decides whether or not a user may run a command
+/// and, if so, how it should be run. Exactly one policy plugin may be
+/// loaded by `sudo`.
+pub trait PolicyPlugin: 'static + Sized + Send {
+    /// The type for errors returned by this `PolicyPlugin`. Errors
+    /// must implement the [`SudoError`](crate::errors::SudoError)
+    /// trait, as well as describe how they should be converted to a
+    /// [`CheckStatus`](crate::core::CheckStatus) when returned from
+    /// [`check_policy`](PolicyPlugin::check_policy) and the other
+    /// methods that `sudo` expects an accept or reject response from.
+    type Error: SudoError + Into<CheckStatus>;
+
+    /// The name of the plugin. Used when printing the version of the
+    /// plugin and error messages.
+    const NAME: &'static str;
+
+    /// The version of the plugin. Defaults to the the value of the
+    /// `CARGO_PKG_VERSION` environment variable during build.
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    /// Prints the name and version of the plugin. A default
+    /// implementation of this function is provided, but may be
+    /// overridden if desired.
+    #[inline]
+    fn show_version(env: &PolicyEnv, _verbose: bool) {
+        use std::io::Write;
+
+        let _ = writeln!(
+            env.stdout(),
+            "{} policy plugin version {}",
+            Self::NAME,
+            Self::VERSION,
+        );
+    }
+
+    /// The `open` function is run when `sudo` starts, before any other
+    /// plugins are opened.
+    ///
+    /// The plugin may keep the provided handle to its environment (or
+    /// clones of it) for as long as it needs; the environment is
+    /// dropped once the plugin and every other handle are gone.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// an [`OpenStatus`](crate::core::OpenStatus) before being
+    /// returned to `sudo`.
+    fn open(env: Arc<PolicyEnv>) -> Result<Self, Self::Error>;
+
+    /// The `close` method is called when the command being run exits,
+    /// or when `sudo` is finished with the plugin if no command is
+    /// run. It's provided with the command's wait status (as returned
+    /// by `waitpid(2)`) and, if the command couldn't be executed, the
+    /// value of `errno` at the time. A default no-op implementation is
+    /// provided, but be overriden if desired.
+    ///
+    /// As suggested by its signature, once this method exits, the
+    /// plugin will be dropped.
+    #[inline]
+    fn close(self, _exit_status: i32, _error: i32) {}
+
+    /// The `check_policy` method is called with the command the user
+    /// wishes to run (in `argv`) and any environment variables they
+    /// asked to set (in `env_add`). Returning `Ok` accepts the command
+    /// and tells `sudo` how to run it.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`CheckStatus`](crate::core::CheckStatus) before being
+    /// returned to `sudo`. Plugins reject commands by returning an
+    /// error that converts to
+    /// [`CheckStatus::Reject`](crate::core::CheckStatus::Reject).
+    fn check_policy(
+        &self,
+        argv:    &[CString],
+        env_add: &OptionMap,
+    ) -> Result<Accepted, Self::Error>;
+
+    /// The `list` method is called when the user runs `sudo -l`, and
+    /// should print the commands the user (or `list_user`, if
+    /// provided) may run. If `argv` is non-empty, it should instead
+    /// only print whether or not that specific command is allowed.
+    /// The default implementation reports that listing isn't
+    /// supported.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`CheckStatus`](crate::core::CheckStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn list(
+        &self,
+        _argv:      &[CString],
+        _verbose:   bool,
+        _list_user: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        Err(Error::Other(
+            "listing privileges is not supported".into()
+        ).into())
+    }
+
+    /// The `validate` method is called when the user runs `sudo -v`,
+    /// and should update any cached credentials. The default
+    /// implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`CheckStatus`](crate::core::CheckStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn validate(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// The `invalidate` method is called when the user runs `sudo -k`
+    /// (or `sudo -K`, in which case `remove` is `true`), and should
+    /// invalidate (or remove) any cached credentials. The default
+    /// implementation does nothing.
+    #[inline]
+    fn invalidate(&self, _remove: bool) {}
+
+    /// The `init_session` method is called just before the command is
+    /// run, in the process that will become the command, and may be
+    /// used to set up a session (e.g., with PAM). The default
+    /// implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Any errors will be recursively printed (up their
+    /// [`source`](std::error::Error::source) chain) then converted to
+    /// a [`CheckStatus`](crate::core::CheckStatus) before being
+    /// returned to `sudo`.
+    #[inline]
+    fn init_session(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
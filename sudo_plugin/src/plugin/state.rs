@@ -0,0 +1,196 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::{ApprovalEnv, AuditEnv, IoEnv, IoPlugin, PolicyEnv};
+use crate::core::OpenStatus;
+use crate::event::EventAlloc;
+use crate::hooks::Hooks;
+use crate::options::CStringArray;
+
+use once_cell::sync::OnceCell;
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// This trait is implemented invisibly by the `sudo_*_plugin` macros
+/// and is not user-visible.
+///
+/// Implementors only need to point at a `static` [`Storage`], which
+/// owns the plugin and its environment between `open` and `close`.
+#[doc(hidden)]
+pub trait PluginState<E, P, X = ()> {
+    /// Returns the static storage backing this plugin.
+    fn storage() -> &'static Storage<E, P, X>;
+}
+
+/// This trait is implemented invisibly by the `sudo_io_plugin` macro
+/// and is not user-visible.
+#[doc(hidden)]
+pub trait IoState<P: IoPlugin>: PluginState<IoEnv, P, Option<Hooks>> {
+    /// Returns the event allocator `sudo` wrote into the exported
+    /// plugin struct, if any.
+    #[must_use]
+    fn event_alloc() -> EventAlloc {
+        None
+    }
+}
+
+/// Storage for an I/O plugin, alongside any hooks it has registered.
+#[doc(hidden)]
+pub type IoStorage<P> = Storage<IoEnv, P, Option<Hooks>>;
+
+/// Storage for an approval plugin.
+#[doc(hidden)]
+pub type ApprovalStorage<P> = Storage<ApprovalEnv, P>;
+
+/// Storage for an audit plugin.
+#[doc(hidden)]
+pub type AuditStorage<P> = Storage<AuditEnv, P>;
+
+/// Storage for a policy plugin, alongside the output of its most recent
+/// call to `check_policy`.
+#[doc(hidden)]
+pub type PolicyStorage<P> = Storage<PolicyEnv, P, Option<PolicyOutput>>;
+
+/// The lists handed back to `sudo` from a successful call to
+/// `check_policy`. `sudo` holds onto pointers into these for as long
+/// as the plugin is open, so they must be kept alive until `close`.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct PolicyOutput {
+    /// The serialized `command_info`.
+    pub command_info: CStringArray,
+
+    /// The argument vector for the command.
+    pub argv: CStringArray,
+
+    /// The environment for the command.
+    pub user_env: CStringArray,
+}
+
+/// Thread-safe storage for a plugin over the course of a single
+/// `open`/`close` cycle, along with any extra state `X` that must live
+/// exactly as long as the plugin does.
+///
+/// The plugin is only ever accessed while holding a lock, so plugins
+/// are free to hand clones of their environment to worker threads. The
+/// plugin is dropped at `close`; the environment is dropped once the
+/// last clone of it is released.
+#[doc(hidden)]
+#[allow(missing_debug_implementations)]
+pub struct Storage<E, P, X = ()> {
+    instance: OnceCell<Mutex<Option<Instance<E, P, X>>>>,
+}
+
+struct Instance<E, P, X> {
+    env:    Arc<E>,
+    plugin: Option<P>,
+    extra:  X,
+}
+
+impl<E, P, X> Storage<E, P, X> {
+    /// Creates empty storage, suitable for use in a `static`.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { instance: OnceCell::new() }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Instance<E, P, X>>> {
+        // a panic inside of a plugin callback is caught at the FFI
+        // boundary, so a poisoned lock doesn't mean the state is any
+        // less usable than it would otherwise have been
+        self.instance
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stores the environment passed to the plugin. Takes a callback
+    /// that is provided a handle to this environment and which returns
+    /// the plugin to store alongside it, or `None` if the plugin
+    /// shouldn't be fully instantiated.
+    pub(crate) fn init<R: Into<OpenStatus>, F: FnOnce(Arc<E>) -> Result<Option<P>, R>>(
+        &self,
+        env: E,
+        f:   F,
+    ) -> OpenStatus
+    where
+        X: Default,
+    {
+        let env = Arc::new(env);
+
+        // the plugin is opened without the lock held, so it's free to
+        // spawn threads that immediately make use of the environment
+        let (plugin, status) = match f(env.clone()) {
+            Ok(plugin) => (plugin, OpenStatus::Ok),
+            Err(e)     => (None,   e.into()),
+        };
+
+        // the environment is stored even if the plugin failed to open,
+        // since `sudo` may still call `show_version`
+        *self.lock() = Some(Instance {
+            env,
+            plugin,
+            extra: X::default(),
+        });
+
+        status
+    }
+
+    /// Removes the plugin from storage and hands it to the provided
+    /// callback to be closed. The environment is released afterward,
+    /// and any extra state is returned so the caller can decide how
+    /// it's disposed of.
+    pub(crate) fn close<F: FnOnce(P)>(&self, f: F) -> Option<X> {
+        // the lock is released before closing the plugin so that any
+        // threads it needs to join won't deadlock on it
+        let Instance { env, plugin, extra } = self.lock().take()?;
+
+        if let Some(plugin) = plugin {
+            f(plugin);
+        }
+
+        drop(env);
+
+        Some(extra)
+    }
+
+    /// Returns a handle to the plugin environment, if the plugin has
+    /// been opened.
+    pub(crate) fn env(&self) -> Option<Arc<E>> {
+        self.lock().as_ref().map(|i| i.env.clone())
+    }
+
+    /// Calls the provided function with the plugin and its environment
+    /// while holding the lock, returning `None` if the plugin hasn't
+    /// been opened.
+    pub(crate) fn with<T, F: FnOnce(&E, &P) -> T>(&self, f: F) -> Option<T> {
+        self.with_extra(|env, plugin, _| f(env, plugin))
+    }
+
+    /// Like [`with`](Storage::with), but also provides mutable access
+    /// to the extra state stored alongside the plugin.
+    pub(crate) fn with_extra<T, F: FnOnce(&E, &P, &mut X) -> T>(&self, f: F) -> Option<T> {
+        let mut guard    = self.lock();
+        let     instance = guard.as_mut()?;
+        let     plugin   = instance.plugin.as_ref()?;
+
+        Some(f(&instance.env, plugin, &mut instance.extra))
+    }
+}
+
+impl<E, P, X> Default for Storage<E, P, X> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
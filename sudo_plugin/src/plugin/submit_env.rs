@@ -0,0 +1,202 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::core::cstrings_from_raw;
+use crate::errors::Result;
+use crate::version::{Capability, Version};
+use crate::options::{OptionMap, Settings, UserInfo};
+use crate::output::{ConversationCallback, Message, PrintFacility, Reply, UserIo};
+
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::io;
+
+use libc::{c_char, c_int, c_uint};
+
+/// The environment shared by the sudo [`approval_plugin`](crate::sys::approval_plugin)
+/// and [`audit_plugin`](crate::sys::audit_plugin), both of which are
+/// given the command line and environment `sudo` was submitted with,
+/// initialized and parsed from the values passed to the underlying
+/// `open` callback.
+#[allow(missing_debug_implementations)]
+pub struct SubmitEnv {
+    /// The name of the plugin. This will be the generally be the same
+    /// as the name of the exported C struct.
+    pub plugin_name: &'static str,
+
+    /// The version of the plugin.
+    pub plugin_version: &'static str,
+
+    /// The plugin API version supported by the invoked `sudo` command.
+    pub api_version: Version,
+
+    /// The command line `sudo` itself was invoked with, including any
+    /// flags passed to `sudo`.
+    pub submit_argv: Vec<CString>,
+
+    /// The index into `submit_argv` of the first argument that isn't a
+    /// flag to `sudo` (i.e., the start of the command to be run).
+    pub submit_optind: usize,
+
+    /// A map of user-supplied sudo settings. These settings correspond
+    /// to flags the user specified when running sudo. As such, they
+    /// will only be present when the corresponding flag has been specified
+    /// on the command line.
+    pub settings: Settings,
+
+    /// A map of information about the user running the command.
+    pub user_info: UserInfo,
+
+    /// A map of the environment variables `sudo` was invoked with.
+    pub submit_env: OptionMap,
+
+    /// A map of options provided to the plugin after the its path in
+    /// sudo.conf.
+    ///
+    /// Settings that aren't of the form `key=value` will have a key
+    /// in the map whose value is the same as the key, similar to how
+    /// HTML handles valueless attributes (e.g., `disabled` will become
+    /// `plugin_options["disabled"] => "disabled"`).
+    pub plugin_options: OptionMap,
+
+    /// The facilities for communicating with the user.
+    user_io: UserIo,
+}
+
+// I don't get to control how many arguments these methods accept, since
+// it's dictated by the C plugin.
+#[allow(clippy::too_many_arguments)]
+impl SubmitEnv {
+    /// Initializes a `SubmitEnv` from the arguments provided to the
+    /// underlying C `open` callback function.
+    ///
+    /// Verifies that the API version advertised by the underlying
+    /// `sudo` provides the `required` capability (i.e., that it
+    /// supports the type of plugin being opened), parses all provided options, and wires up
+    /// communication facilities.
+    //
+    /// # Errors
+    ///
+    /// Returns an error if there was a problem initializing the plugin.
+    ///
+    /// # Safety
+    ///
+    /// This function is inherently unsafe since it's provided with
+    /// raw pointers. As long as sudo obeys its contracts for how these
+    /// are interpreted (see [`OptionMap`](OptionMap) for details).
+    pub unsafe fn new(
+        plugin_name:    &'static str,
+        plugin_version: &'static str,
+        version:        c_uint,
+        plugin_printf:  crate::sys::sudo_printf_t,
+        conversation:   crate::sys::sudo_conv_t,
+        settings:       *const *mut c_char,
+        user_info:      *const *mut c_char,
+        submit_optind:  c_int,
+        submit_argv:    *const *mut c_char,
+        submit_envp:    *const *mut c_char,
+        plugin_options: *const *mut c_char,
+        required:       Capability,
+    ) -> Result<Self> {
+        let version = Version::from(version).require(required)?;
+
+        // a negative index is nonsensical, so treat it as though every
+        // argument is a flag to `sudo`
+        let submit_argv   = cstrings_from_raw(submit_argv);
+        let submit_optind = TryInto::<usize>::try_into(submit_optind)
+            .unwrap_or(submit_argv.len());
+
+        let plugin = Self {
+            plugin_name,
+            plugin_version,
+
+            api_version: version,
+
+            submit_argv,
+            submit_optind,
+
+            settings:       OptionMap::from_raw(settings.cast()).try_into()?,
+            user_info:      OptionMap::from_raw(user_info.cast()).try_into()?,
+            submit_env:     OptionMap::from_raw(submit_envp.cast()),
+            plugin_options: OptionMap::from_raw(plugin_options.cast()),
+
+            user_io: UserIo::new(Some(plugin_name), plugin_printf, conversation),
+        };
+
+        Ok(plugin)
+    }
+
+    ///
+    /// Returns a facility implementing `std::io::Write` that emits to
+    /// the invoking user's STDOUT.
+    ///
+    #[must_use]
+    pub fn stdout(&self) -> PrintFacility {
+        self.user_io.stdout.clone()
+    }
+
+    ///
+    /// Returns a facility implementing `std::io::Write` that emits to
+    /// the invoking user's STDERR.
+    ///
+    #[must_use]
+    pub fn stderr(&self) -> PrintFacility {
+        self.user_io.stderr.clone()
+    }
+
+    ///
+    /// Sends a batch of messages to the invoking user through `sudo`'s
+    /// conversation function, returning their replies to any prompts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse(&self, messages: &[Message]) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse(messages)
+    }
+
+    ///
+    /// Like [`converse`](SubmitEnv::converse), but invokes `callback`
+    /// if `sudo` is suspended and resumed while waiting for the user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails (e.g., because a
+    /// prompt timed out).
+    ///
+    pub fn converse_with_callback(
+        &self,
+        messages: &[Message],
+        callback: &mut dyn ConversationCallback,
+    ) -> io::Result<Vec<Reply>> {
+        self.user_io.conversation.converse_with_callback(messages, callback)
+    }
+
+    ///
+    /// The command (and its arguments) the user asked `sudo` to run,
+    /// as it was typed on the command line.
+    ///
+    #[must_use]
+    pub fn submit_command(&self) -> &[CString] {
+        self.submit_argv.get(self.submit_optind..).unwrap_or(&[])
+    }
+}
+
+/// The environment of an approval plugin.
+pub type ApprovalEnv = SubmitEnv;
+
+/// The environment of an audit plugin.
+pub type AuditEnv = SubmitEnv;
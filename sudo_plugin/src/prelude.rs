@@ -15,8 +15,11 @@
 //! A prelude module that includes most of what's necessary to start
 //! using this crate.
 
-pub use crate::core::{OpenStatus, LogStatus};
+pub use crate::core::{CheckStatus, OpenStatus, LogStatus};
 pub use crate::errors::Error;
-pub use crate::options::OptionMap;
-pub use crate::plugin::{IoEnv, IoPlugin};
-pub use crate::sudo_io_plugin;
+pub use crate::hooks::{HookResult, Hooks};
+pub use crate::options::{OptionMap, SudoOptions};
+pub use crate::plugin::{ApprovalEnv, ApprovalPlugin, AuditEnv, AuditPlugin, IoEnv, IoPlugin};
+pub use crate::plugin::{Accepted, CommandEnv, PolicyEnv, PolicyPlugin};
+pub use crate::version::{Capability, Version};
+pub use crate::{sudo_approval_plugin, sudo_audit_plugin, sudo_io_plugin, sudo_policy_plugin};
@@ -0,0 +1,193 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Rendering of command lines as they would be typed at a shell.
+//!
+//! Arguments are quoted so that the rendered command line is an
+//! unambiguous, copy-and-pasteable representation of what is actually
+//! being run: word boundaries are preserved, and bytes that would
+//! otherwise be invisible or misleading on a terminal (control
+//! characters, bidirectional overrides, invalid UTF-8) are rendered as
+//! `$'...'` escapes.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::str;
+
+/// Quotes `arg`, if necessary, so that a POSIX shell would interpret
+/// it as a single word with exactly the same contents.
+///
+/// Arguments consisting only of characters that are never special to
+/// the shell are returned unchanged. Otherwise, arguments are wrapped
+/// in single quotes unless they contain non-printable characters or
+/// invalid UTF-8, in which case they are rendered with `$'...'`
+/// escapes.
+#[must_use]
+pub fn quote(arg: &[u8]) -> Cow<'_, [u8]> {
+    if !arg.is_empty() && arg.iter().copied().all(is_safe) {
+        return arg.into();
+    }
+
+    match str::from_utf8(arg) {
+        Ok(s) if !s.chars().any(is_unprintable) => {
+            let mut quoted = Vec::with_capacity(arg.len() + 2);
+
+            quoted.push(b'\'');
+
+            for &byte in arg {
+                if byte == b'\'' {
+                    quoted.extend_from_slice(br"'\''");
+                } else {
+                    quoted.push(byte);
+                }
+            }
+
+            quoted.push(b'\'');
+            quoted.into()
+        },
+
+        _ => escape(arg).into_bytes().into(),
+    }
+}
+
+/// Quotes each argument and joins them with spaces.
+#[must_use]
+pub fn join<I, S>(args: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut line = Vec::new();
+
+    for arg in args {
+        if !line.is_empty() {
+            line.push(b' ');
+        }
+
+        line.extend_from_slice(&quote(arg.as_ref().as_bytes()));
+    }
+
+    line
+}
+
+/// Returns true for ASCII characters that can appear anywhere in a
+/// word without being interpreted by the shell.
+fn is_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"%+,-./:=@_".contains(&byte)
+}
+
+/// Returns true for characters that shouldn't be printed verbatim,
+/// either because they're invisible or because they change how the
+/// surrounding text is displayed (e.g., right-to-left overrides, which
+/// can make a command appear to be something it's not).
+fn is_unprintable(c: char) -> bool {
+    c.is_control() || matches!(c,
+        '\u{200b}'..='\u{200f}' |
+        '\u{202a}'..='\u{202e}' |
+        '\u{2060}'..='\u{2069}' |
+        '\u{feff}'
+    )
+}
+
+/// Renders `arg` as an ANSI-C quoted (`$'...'`) string.
+fn escape(mut arg: &[u8]) -> String {
+    let mut escaped = String::from("$'");
+
+    while !arg.is_empty() {
+        let (valid, invalid) = match str::from_utf8(arg) {
+            Ok(s)  => (s, &[][..]),
+            Err(e) => {
+                let (valid, rest) = arg.split_at(e.valid_up_to());
+                let len           = e.error_len().unwrap_or(rest.len());
+
+                // the prefix was just verified to be valid UTF-8
+                (str::from_utf8(valid).unwrap_or_default(), &rest[..len])
+            },
+        };
+
+        for c in valid.chars() {
+            let _ = match c {
+                '\\'   => escaped.write_str(r"\\"),
+                '\''   => escaped.write_str(r"\'"),
+                '\x07' => escaped.write_str(r"\a"),
+                '\x08' => escaped.write_str(r"\b"),
+                '\x1b' => escaped.write_str(r"\e"),
+                '\x0c' => escaped.write_str(r"\f"),
+                '\n'   => escaped.write_str(r"\n"),
+                '\r'   => escaped.write_str(r"\r"),
+                '\t'   => escaped.write_str(r"\t"),
+                '\x0b' => escaped.write_str(r"\v"),
+
+                c if c.is_ascii() && is_unprintable(c) => write!(escaped, r"\x{code:02x}", code = u32::from(c)),
+                c if is_unprintable(c)                 => write!(escaped, r"\u{code:04x}", code = u32::from(c)),
+                c                                      => escaped.write_char(c),
+            };
+        }
+
+        for byte in invalid {
+            let _ = write!(escaped, r"\x{byte:02x}");
+        }
+
+        arg = &arg[valid.len() + invalid.len()..];
+    }
+
+    escaped.push('\'');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(arg: &[u8]) -> String {
+        String::from_utf8(quote(arg).into_owned()).unwrap()
+    }
+
+    #[test]
+    fn safe_arguments_are_unquoted() {
+        assert_eq!("/usr/bin/id",      quoted(b"/usr/bin/id"));
+        assert_eq!("--user=root",      quoted(b"--user=root"));
+        assert_eq!("user@host:1,2%3+", quoted(b"user@host:1,2%3+"));
+    }
+
+    #[test]
+    fn special_arguments_are_single_quoted() {
+        assert_eq!("''",               quoted(b""));
+        assert_eq!("'rm -rf /tmp/x y'", quoted(b"rm -rf /tmp/x y"));
+        assert_eq!(r"'it'\''s'",        quoted(b"it's"));
+        assert_eq!("'$HOME'",           quoted(b"$HOME"));
+        assert_eq!("'caf\u{e9}'",       quoted("caf\u{e9}".as_bytes()));
+    }
+
+    #[test]
+    fn unprintable_arguments_are_escaped() {
+        assert_eq!(r"$'a\nb'",         quoted(b"a\nb"));
+        assert_eq!(r"$'\e[2J\x7f'",    quoted(b"\x1b[2J\x7f"));
+        assert_eq!(r"$'it\'s\t\\'",    quoted(b"it's\t\\"));
+        assert_eq!(r"$'\xff\xfeok'",   quoted(b"\xff\xfeok"));
+        assert_eq!(r"$'caf\xc3'",      quoted(b"caf\xc3"));
+        assert_eq!(r"$'ab\u202ec'",    quoted("ab\u{202e}c".as_bytes()));
+        assert_eq!("$'\u{e9}\\x80'",   quoted(b"\xc3\xa9\x80"));
+    }
+
+    #[test]
+    fn arguments_are_joined_with_spaces() {
+        assert_eq!(
+            &b"sudo sh -c 'rm -rf /tmp/x y'"[..],
+            &join(["sudo", "sh", "-c", "rm -rf /tmp/x y"].iter())[..],
+        );
+    }
+}
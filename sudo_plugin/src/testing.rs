@@ -0,0 +1,570 @@
+// Copyright 2020 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+//! An in-process stand-in for the `sudo` front-end, for testing plugins
+//! end to end without a setuid `sudo`.
+//!
+//! [`MockSudo`] builds realistic `settings`, `user_info`,
+//! `command_info`, and `plugin_options` arrays and uses them to `open`
+//! an I/O plugin through its exported `io_plugin` struct, exactly as
+//! `sudo` would. The returned [`IoSession`] drives the plugin's
+//! callbacks and captures anything it printed through `sudo`'s `printf`
+//! facility.
+//!
+//! ```ignore
+//! use sudo_plugin::testing::MockSudo;
+//!
+//! let mut session = unsafe {
+//!     MockSudo::new()
+//!         .command("/usr/bin/id", &["-u"])
+//!         .plugin_option("greeting", "hello")
+//!         .open_io(&*std::ptr::addr_of!(example))
+//! };
+//!
+//! assert_eq!(1, session.status());
+//! assert_eq!(1, session.log_stdout(b"0\n"));
+//!
+//! let output = session.close(0, 0);
+//!
+//! assert!(output.stderr.is_empty());
+//! ```
+//!
+//! Plugins built as a `cdylib` may also be loaded with
+//! [`Library::open`].
+//!
+//! Since each plugin's state is global to the process, only one session
+//! may be open at a time; opening a second session blocks until the
+//! first is closed.
+//!
+//! This module is only available with the `testing` feature.
+
+use crate::options::{CStringArray, CommandInfo, OptionMap, Settings, UserInfo};
+use crate::options::traits::ToSudoOption;
+use crate::sys;
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use once_cell::sync::Lazy;
+
+// held by each `IoSession` for as long as it's open, since plugin state
+// is global to the process
+static SESSION: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+
+// everything printed through `printf` by the current session; this is
+// global (rather than thread-local) so that output from any worker
+// threads the plugin spawns is captured too
+static OUTPUT: Lazy<Mutex<Output>> = Lazy::new(Mutex::default);
+
+/// Everything a plugin printed through `sudo`'s `printf` facility.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Output {
+    /// Messages printed at the `SUDO_CONV_INFO_MSG` level.
+    pub stdout: Vec<u8>,
+
+    /// Messages printed at the `SUDO_CONV_ERROR_MSG` level.
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    fn lock() -> MutexGuard<'static, Self> {
+        OUTPUT.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A builder for the arguments `sudo` provides to a plugin's `open`
+/// callback.
+///
+/// The defaults describe an unprivileged user (uid and gid `1000`)
+/// running `/usr/bin/true` as root, with none of the `iolog_*` hints
+/// set and no plugin options.
+#[derive(Clone, Debug)]
+pub struct MockSudo {
+    version:        c_uint,
+    argv:           Vec<CString>,
+    settings:       OptionMap,
+    user_info:      OptionMap,
+    command_info:   OptionMap,
+    user_env:       OptionMap,
+    plugin_options: OptionMap,
+}
+
+impl MockSudo {
+    /// Creates a new `MockSudo` with realistic default arguments.
+    #[must_use]
+    pub fn new() -> Self {
+        let settings = Settings::builder()
+            .plugin_dir("/usr/libexec/sudo")
+            .plugin_path("/usr/libexec/sudo/plugin.so")
+            .build();
+
+        let user_info = UserInfo::builder()
+            .user("user")
+            .uid(1000)
+            .euid(0)
+            .gid(1000)
+            .groups(vec![1000])
+            .cwd("/home/user")
+            .host("localhost")
+            .tty("/dev/pts/0")
+            .build();
+
+        let mut user_env = OptionMap::new();
+
+        user_env.insert("PATH", "/usr/bin:/bin");
+        user_env.insert("HOME", "/home/user");
+
+        let mut mock = Self {
+            version:        sys::SUDO_API_VERSION,
+            argv:           vec![],
+            settings:       OptionMap::from(&settings),
+            user_info:      OptionMap::from(&user_info),
+            command_info:   OptionMap::new(),
+            user_env,
+            plugin_options: OptionMap::new(),
+        };
+
+        let _ = mock.command_info_from(&CommandInfo::builder()
+            .runas_uid(0_u32)
+            .runas_gid(0_u32)
+            .cwd("/home/user")
+            .build()
+        );
+
+        let _ = mock.command("/usr/bin/true", &[]);
+
+        mock
+    }
+
+    /// Sets the version of the plugin API `sudo` claims to support.
+    pub fn api_version(&mut self, major: c_uint, minor: c_uint) -> &mut Self {
+        self.version = sys::sudo_api_mkversion(major, minor);
+        self
+    }
+
+    /// Sets the command being run, along with its arguments. This sets
+    /// both `argv` and the `command` in `command_info`. An empty
+    /// command mimics `sudo -V`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the arguments contain NUL bytes.
+    pub fn command<P: AsRef<Path>>(&mut self, command: P, args: &[&str]) -> &mut Self {
+        let command = command.as_ref();
+
+        self.command_info.insert("command", command);
+
+        self.argv = Some(command.as_os_str().as_bytes())
+            .into_iter()
+            .chain(args.iter().map(|arg| arg.as_bytes()))
+            .map(|arg| CString::new(arg).expect("arguments may not contain NUL bytes"))
+            .collect();
+
+        self
+    }
+
+    /// Sets a key in `settings`.
+    pub fn setting<T: ToSudoOption>(&mut self, key: &str, value: T) -> &mut Self {
+        self.settings.insert(key, value);
+        self
+    }
+
+    /// Sets a key in `user_info`.
+    pub fn user_info<T: ToSudoOption>(&mut self, key: &str, value: T) -> &mut Self {
+        self.user_info.insert(key, value);
+        self
+    }
+
+    /// Sets a key in `command_info`.
+    pub fn command_info<T: ToSudoOption>(&mut self, key: &str, value: T) -> &mut Self {
+        self.command_info.insert(key, value);
+        self
+    }
+
+    /// Replaces `settings` entirely.
+    pub fn settings_from(&mut self, settings: &Settings) -> &mut Self {
+        self.settings = OptionMap::from(settings);
+        self
+    }
+
+    /// Replaces `user_info` entirely.
+    pub fn user_info_from(&mut self, info: &UserInfo) -> &mut Self {
+        self.user_info = OptionMap::from(info);
+        self
+    }
+
+    /// Replaces `command_info` entirely, preserving the command being
+    /// run unless `info` sets one.
+    pub fn command_info_from(&mut self, info: &CommandInfo) -> &mut Self {
+        let command = self.command_info.remove("command");
+
+        self.command_info = OptionMap::from(info);
+
+        if let (None, Some(command)) = (self.command_info.get_str("command"), command) {
+            self.command_info.insert_bytes(b"command", &command);
+        }
+
+        self
+    }
+
+    /// Sets a variable in the user's environment.
+    pub fn user_env<T: ToSudoOption>(&mut self, key: &str, value: T) -> &mut Self {
+        self.user_env.insert(key, value);
+        self
+    }
+
+    /// Sets an option passed to the plugin from `sudo.conf`.
+    pub fn plugin_option<T: ToSudoOption>(&mut self, key: &str, value: T) -> &mut Self {
+        self.plugin_options.insert(key, value);
+        self
+    }
+
+    /// Opens the provided I/O plugin, returning a session that can be
+    /// used to drive the rest of its callbacks. The session is returned
+    /// even if the plugin failed to open; its [`status`](IoSession::status)
+    /// is whatever the plugin's `open` callback returned.
+    ///
+    /// Blocks if another session is already open.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the configured options contain NUL bytes.
+    ///
+    /// # Safety
+    ///
+    /// The callbacks in `plugin` must implement the `io_plugin` API
+    /// (e.g., it must be the struct exported by `sudo_io_plugin!`).
+    pub unsafe fn open_io<'a>(&self, plugin: &'a sys::io_plugin) -> IoSession<'a> {
+        let guard = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+
+        *Output::lock() = Output::default();
+
+        let arrays : Vec<CStringArray> = vec![
+            &self.settings,
+            &self.user_info,
+            &self.command_info,
+            &self.user_env,
+            &self.plugin_options,
+        ].into_iter()
+            .map(|options| CStringArray::try_from(options).expect("options may not contain NUL bytes"))
+            .collect();
+
+        let mut session = IoSession {
+            plugin,
+            status: 0,
+            closed: false,
+            errstr: None,
+            argv:   CStringArray::new(self.argv.clone()),
+            arrays,
+            _guard: guard,
+        };
+
+        let mut errstr = ptr::null();
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let argc = self.argv.len() as c_int;
+
+        session.status = match plugin.open {
+            Some(open) => (open)(
+                self.version,
+                None,
+                Some(printf()),
+                session.arrays[0].as_mut_ptr(),
+                session.arrays[1].as_mut_ptr(),
+                session.arrays[2].as_mut_ptr(),
+                argc,
+                session.argv.as_mut_ptr(),
+                session.arrays[3].as_mut_ptr(),
+                session.arrays[4].as_mut_ptr(),
+                &mut errstr,
+            ),
+
+            None => 1,
+        };
+
+        session.record_errstr(errstr);
+
+        session
+    }
+}
+
+impl Default for MockSudo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An I/O plugin opened by [`MockSudo`]. Every method calls the
+/// corresponding callback of the plugin and returns its raw return
+/// value, or `1` if the plugin doesn't implement that callback.
+///
+/// The plugin is closed with an exit status of `0` when the session is
+/// dropped, unless it was closed explicitly with
+/// [`close`](IoSession::close).
+#[derive(Debug)]
+pub struct IoSession<'a> {
+    plugin: &'a sys::io_plugin,
+    status: c_int,
+    closed: bool,
+    errstr: Option<CString>,
+
+    // `sudo` keeps these alive for as long as the plugin is open
+    argv:   CStringArray,
+    arrays: Vec<CStringArray>,
+
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl IoSession<'_> {
+    /// Returns the value returned by the plugin's `open` callback.
+    #[must_use]
+    pub fn status(&self) -> c_int {
+        self.status
+    }
+
+    /// Returns the error string the plugin reported through `errstr`
+    /// during its most recent callback, if any.
+    #[must_use]
+    pub fn errstr(&self) -> Option<&CStr> {
+        self.errstr.as_deref()
+    }
+
+    /// Returns everything the plugin has printed so far.
+    #[must_use]
+    pub fn output(&self) -> Output {
+        Output::lock().clone()
+    }
+
+    /// Returns everything the plugin has printed so far, clearing it
+    /// for subsequent calls.
+    pub fn take_output(&mut self) -> Output {
+        mem::take(&mut *Output::lock())
+    }
+
+    /// Calls the plugin's `show_version` callback.
+    pub fn show_version(&mut self, verbose: bool) -> c_int {
+        self.plugin.show_version.map_or(1, |f| unsafe {
+            (f)(verbose.into())
+        })
+    }
+
+    /// Sends input read from the user's terminal to the plugin.
+    pub fn log_ttyin(&mut self, buf: &[u8]) -> c_int {
+        self.log(self.plugin.log_ttyin, buf)
+    }
+
+    /// Sends output written to the user's terminal to the plugin.
+    pub fn log_ttyout(&mut self, buf: &[u8]) -> c_int {
+        self.log(self.plugin.log_ttyout, buf)
+    }
+
+    /// Sends input read from a non-terminal stdin to the plugin.
+    pub fn log_stdin(&mut self, buf: &[u8]) -> c_int {
+        self.log(self.plugin.log_stdin, buf)
+    }
+
+    /// Sends output written to a non-terminal stdout to the plugin.
+    pub fn log_stdout(&mut self, buf: &[u8]) -> c_int {
+        self.log(self.plugin.log_stdout, buf)
+    }
+
+    /// Sends output written to a non-terminal stderr to the plugin.
+    pub fn log_stderr(&mut self, buf: &[u8]) -> c_int {
+        self.log(self.plugin.log_stderr, buf)
+    }
+
+    /// Notifies the plugin that the user's terminal was resized.
+    pub fn change_winsize(&mut self, lines: c_uint, cols: c_uint) -> c_int {
+        let mut errstr = ptr::null();
+
+        let status = self.plugin.change_winsize.map_or(1, |f| unsafe {
+            (f)(lines, cols, &mut errstr)
+        });
+
+        self.record_errstr(errstr);
+
+        status
+    }
+
+    /// Notifies the plugin that the command was suspended (or resumed,
+    /// if `signal` is `SIGCONT`).
+    pub fn log_suspend(&mut self, signal: c_int) -> c_int {
+        let mut errstr = ptr::null();
+
+        let status = self.plugin.log_suspend.map_or(1, |f| unsafe {
+            (f)(signal, &mut errstr)
+        });
+
+        self.record_errstr(errstr);
+
+        status
+    }
+
+    /// Closes the plugin, returning everything it printed that hasn't
+    /// already been taken with [`take_output`](IoSession::take_output).
+    #[allow(clippy::must_use_candidate)]
+    pub fn close(mut self, exit_status: c_int, error: c_int) -> Output {
+        self.close_plugin(exit_status, error);
+        self.take_output()
+    }
+
+    fn close_plugin(&mut self, exit_status: c_int, error: c_int) {
+        if mem::replace(&mut self.closed, true) {
+            return;
+        }
+
+        if let Some(close) = self.plugin.close {
+            unsafe { (close)(exit_status, error) };
+        }
+    }
+
+    fn log(
+        &mut self,
+        f:   Option<unsafe extern "C" fn(*const c_char, c_uint, *mut *const c_char) -> c_int>,
+        buf: &[u8],
+    ) -> c_int {
+        let mut errstr = ptr::null();
+
+        #[allow(clippy::cast_possible_truncation)]
+        let status = f.map_or(1, |f| unsafe {
+            (f)(buf.as_ptr().cast(), buf.len() as c_uint, &mut errstr)
+        });
+
+        self.record_errstr(errstr);
+
+        status
+    }
+
+    // the string is only guaranteed to be valid until the plugin's next
+    // callback, so it's copied out immediately
+    fn record_errstr(&mut self, errstr: *const c_char) {
+        self.errstr = if errstr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(errstr) }.to_owned())
+        };
+    }
+}
+
+impl Drop for IoSession<'_> {
+    fn drop(&mut self) {
+        self.close_plugin(0, 0);
+    }
+}
+
+/// A plugin shared library loaded with `dlopen(3)`.
+#[derive(Debug)]
+pub struct Library {
+    handle: *mut c_void,
+}
+
+impl Library {
+    /// Loads the shared library at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the library couldn't be loaded.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let handle = unsafe {
+            libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL)
+        };
+
+        if handle.is_null() {
+            return Err(dlerror());
+        }
+
+        Ok(Self { handle })
+    }
+
+    /// Looks up the `io_plugin` struct exported by the library under
+    /// `symbol` (the name given to `sudo_io_plugin!`).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the library doesn't export `symbol`.
+    ///
+    /// # Safety
+    ///
+    /// The symbol must refer to an `io_plugin` struct.
+    pub unsafe fn io_plugin(&self, symbol: &str) -> io::Result<&sys::io_plugin> {
+        let symbol = CString::new(symbol)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let plugin : *const sys::io_plugin = libc::dlsym(self.handle, symbol.as_ptr()).cast();
+
+        plugin.as_ref().ok_or_else(dlerror)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+fn dlerror() -> io::Error {
+    let message = unsafe { libc::dlerror() };
+
+    if message.is_null() {
+        return io::Error::new(io::ErrorKind::NotFound, "symbol not found");
+    }
+
+    let message = unsafe { CStr::from_ptr(message) };
+
+    io::Error::new(io::ErrorKind::Other, message.to_string_lossy().into_owned())
+}
+
+type Printf = unsafe extern "C" fn(c_int, *const c_char, ...) -> c_int;
+type Capture = unsafe extern "C" fn(c_int, *const c_char) -> c_int;
+
+/// Returns a fake `sudo_printf_t` that captures output into [`OUTPUT`].
+fn printf() -> Printf {
+    // `sudo_printf_t` is variadic, which can't be implemented in stable
+    // Rust. `PrintFacility` only ever calls it with preformatted
+    // messages and no variadic arguments, which every supported ABI
+    // passes identically to a non-variadic call.
+    unsafe { mem::transmute::<Capture, Printf>(capture) }
+}
+
+unsafe extern "C" fn capture(msg_type: c_int, message: *const c_char) -> c_int {
+    if message.is_null() {
+        return -1;
+    }
+
+    let message = CStr::from_ptr(message).to_bytes();
+    let mut out = Output::lock();
+
+    #[allow(clippy::cast_possible_wrap)]
+    let stream = if msg_type & 0xff == sys::SUDO_CONV_ERROR_MSG as c_int {
+        &mut out.stderr
+    } else {
+        &mut out.stdout
+    };
+
+    stream.extend_from_slice(message);
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let len = message.len() as c_int;
+
+    len
+}
@@ -12,6 +12,15 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
+//! Plugin API versions, and the capabilities each of them provides.
+//!
+//! A plugin is built against a single version of the `sudo_plugin`
+//! API, but may be loaded by any `sudo` that supports the
+//! [minimum](Version::minimum) version required by this crate. Newer
+//! features are only used when the `sudo` that loaded the plugin
+//! advertises support for them, which can be checked with
+//! [`Version::supports`].
+
 use crate::errors::{Result, Error};
 use crate::sys;
 
@@ -20,17 +29,71 @@ use std::os::raw::c_uint;
 
 const MINIMUM: Version = Version::new(1, 9);
 
+/// A version of the `sudo_plugin` API.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Version {
     major: u16,
     minor: u16,
 }
 
+/// A feature of the plugin API that is only available to plugins
+/// loaded by a sufficiently recent version of `sudo`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Capability {
+    /// `register_hooks` and `deregister_hooks` are called to allow the
+    /// plugin to intercept changes to the environment.
+    Hooks,
+
+    /// `change_winsize` is called when the user's terminal is resized.
+    /// Introduced in sudo 1.8.21.
+    ChangeWinsize,
+
+    /// `log_suspend` is called when the command is suspended or
+    /// resumed. Introduced in sudo 1.8.26.
+    LogSuspend,
+
+    /// Errors can be reported to `sudo` through the `errstr` argument
+    /// to plugin callbacks. Introduced in sudo 1.9.0.
+    Errstr,
+
+    /// `sudo` provides `event_alloc`, allowing plugins to schedule
+    /// events in its event loop. Introduced in sudo 1.9.0.
+    EventAlloc,
+
+    /// `sudo` loads approval plugins. Introduced in sudo 1.9.0.
+    Approval,
+
+    /// `sudo` loads audit plugins. Introduced in sudo 1.9.0.
+    Audit,
+}
+
+impl Capability {
+    /// Returns the earliest version of the plugin API that provides
+    /// this capability.
+    #[must_use]
+    pub const fn since(self) -> Version {
+        match self {
+            Self::Hooks         => Version::new(1,  2),
+            Self::ChangeWinsize => Version::new(1, 12),
+            Self::LogSuspend    => Version::new(1, 13),
+            Self::Errstr        |
+            Self::EventAlloc    |
+            Self::Approval      |
+            Self::Audit         => Version::new(1, 15),
+        }
+    }
+}
+
 impl Version {
+    /// Creates a version from its major and minor components.
+    #[must_use]
     pub const fn new(major: u16, minor: u16) -> Self {
         Self { major, minor }
     }
 
+    /// Converts a version from the packed representation used by
+    /// `sudo`.
+    #[must_use]
     pub const fn from_ffi(version: c_uint) -> Self {
         // this cast is guaranteed not to truncate thanks to the shifts
         // and masks
@@ -41,6 +104,9 @@ impl Version {
         )
     }
 
+    /// Converts a version into the packed representation used by
+    /// `sudo`.
+    #[must_use]
     pub const fn into_ffi(self) -> c_uint {
         sys::sudo_api_mkversion(
             self.major as _,
@@ -48,14 +114,44 @@ impl Version {
         )
     }
 
+    /// Returns the major component of the version.
+    #[must_use]
+    pub const fn major(self) -> u16 {
+        self.major
+    }
+
+    /// Returns the minor component of the version.
+    #[must_use]
+    pub const fn minor(self) -> u16 {
+        self.minor
+    }
+
+    /// Returns the minimum version of the plugin API supported by this
+    /// crate.
+    #[must_use]
     pub const fn minimum() -> &'static Self {
         &MINIMUM
     }
 
+    /// Returns whether or not this version is supported by this crate.
+    #[must_use]
     pub fn supported(self) -> bool {
         self >= *Self::minimum()
     }
 
+    /// Returns whether or not this version of the plugin API provides
+    /// `capability`.
+    #[must_use]
+    pub fn supports(self, capability: Capability) -> bool {
+        self >= capability.since()
+    }
+
+    /// Verifies that this version is supported by this crate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this version is older than the
+    /// [minimum](Version::minimum).
     pub fn check(self) -> Result<Self> {
         if !self.supported() {
             return Err(Error::UnsupportedApiVersion {
@@ -66,6 +162,28 @@ impl Version {
 
         Ok(self)
     }
+
+    /// Verifies that this version is supported by this crate and that
+    /// it provides `capability`, for plugins that can't function
+    /// without it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this version is older than the
+    /// [minimum](Version::minimum) or than the version that introduced
+    /// `capability`.
+    pub fn require(self, capability: Capability) -> Result<Self> {
+        let required = capability.since().max(MINIMUM);
+
+        if self < required {
+            return Err(Error::UnsupportedApiVersion {
+                required,
+                provided: self,
+            });
+        }
+
+        Ok(self)
+    }
 }
 
 impl From<c_uint> for Version {
@@ -85,3 +203,33 @@ impl fmt::Display for Version {
         write!(f, "{}.{}", self.major, self.minor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_require_their_introducing_version() {
+        let version = Version::new(1, 13);
+
+        assert!(version.supports(Capability::Hooks));
+        assert!(version.supports(Capability::ChangeWinsize));
+        assert!(version.supports(Capability::LogSuspend));
+        assert!(!version.supports(Capability::Errstr));
+        assert!(!version.supports(Capability::EventAlloc));
+    }
+
+    #[test]
+    fn required_capabilities_raise_the_minimum_version() {
+        assert!(Version::new(1, 14).require(Capability::Approval).is_err());
+        assert!(Version::new(1, 15).require(Capability::Approval).is_ok());
+        assert!(Version::new(1,  8).require(Capability::Hooks).is_err());
+    }
+
+    #[test]
+    fn versions_round_trip_through_ffi() {
+        let version = Version::new(1, 17);
+
+        assert_eq!(version, Version::from_ffi(version.into_ffi()));
+    }
+}